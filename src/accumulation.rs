@@ -0,0 +1,106 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, CommandEncoder, Device, LoadOp, Operations, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{post, texture::Texture};
+
+// A texture that keeps what was drawn into it from one frame to the next, which the surface's textures
+// don't promise to. The scene draws into `target` without clearing it first, and it gets copied to the screen.
+pub struct Accumulation {
+    // Whether `target` holds the last frame yet. Until it does, the scene clears it instead of drawing over it.
+    pub started: bool,
+    format: TextureFormat,
+    texture: Texture,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl Accumulation {
+    pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+        let texture =
+            Texture::create_render_target(device, width, height, format, "accumulation_texture");
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::texture_entry(0), post::sampler_entry(1)],
+            label: Some("accumulation_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, &texture);
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "Accumulation Pipeline",
+            include_str!("accumulation.wgsl"),
+            &[&bind_group_layout],
+            format,
+            None,
+        );
+
+        Self {
+            started: false,
+            format,
+            texture,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.texture = Texture::create_render_target(
+            device,
+            width,
+            height,
+            self.format,
+            "accumulation_texture",
+        );
+        self.bind_group = create_bind_group(device, &self.bind_group_layout, &self.texture);
+        self.started = false;
+    }
+
+    // Where the frame needs to be drawn to build on the last one.
+    pub fn target(&self) -> &TextureView {
+        &self.texture.view
+    }
+
+    // Copies the target into `view`, covering everything in it.
+    pub fn render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Accumulation Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    // Every pixel gets overwritten anyway
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(device: &Device, layout: &BindGroupLayout, texture: &Texture) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&texture.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("accumulation_bind_group"),
+    })
+}
@@ -0,0 +1,256 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, Buffer, BufferUsages, ColorTargetState, ColorWrites, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, FragmentState, MultisampleState, PipelineLayout,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState,
+    TextureFormat, VertexState,
+};
+
+use crate::{post, texture::Texture};
+
+// How the background image is fitted to a window of a different shape.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BackgroundMode {
+    // Covers the window exactly, squashing the image
+    Stretch,
+    // All of the image shows, with bars of the clear color on two sides
+    Fit,
+    // Covers the window, cutting off two sides of the image
+    Fill,
+}
+
+impl BackgroundMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Stretch => Self::Fit,
+            Self::Fit => Self::Fill,
+            Self::Fill => Self::Stretch,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BackgroundUniform {
+    uv_scale: [f32; 2],
+    _padding: [f32; 2],
+}
+
+// An image drawn behind everything, e.g. a photo to show a model against. Drawn first in the main pass,
+// covering the clear color, and doesn't write depth so the scene always goes in front of it.
+pub struct Background {
+    mode: BackgroundMode,
+    // None draws nothing, leaving the clear color
+    image: Option<(Texture, BindGroup)>,
+    screen_size: (u32, u32),
+    buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+}
+
+impl Background {
+    pub fn new(
+        device: &Device,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Background Uniform Buffer"),
+            contents: cast_slice(&[BackgroundUniform {
+                uv_scale: [1.0; 2],
+                _padding: [0.0; 2],
+            }]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                post::texture_entry(0),
+                post::sampler_entry(1),
+                post::uniform_entry(2),
+            ],
+            label: Some("background_bind_group_layout"),
+        });
+
+        // Not a post-processing pass, but it uses the same fullscreen triangle
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Background Shader"),
+            source: ShaderSource::Wgsl(
+                format!(
+                    "{}\n{}",
+                    include_str!("fullscreen.wgsl"),
+                    include_str!("background.wgsl")
+                )
+                .into(),
+            ),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Background Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &layout, &shader, color_format, depth_format, 1);
+
+        Self {
+            mode: BackgroundMode::Fill,
+            image: None,
+            screen_size: (width, height),
+            buffer,
+            bind_group_layout,
+            pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+        }
+    }
+
+    // None goes back to the plain clear color.
+    pub fn set_image(&mut self, device: &Device, queue: &Queue, texture: Option<Texture>) {
+        self.image = texture.map(|texture| {
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&texture.view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&texture.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: self.buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("background_bind_group"),
+            });
+            (texture, bind_group)
+        });
+        self.write_uniform(queue);
+    }
+
+    pub fn mode(&self) -> BackgroundMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, queue: &Queue, mode: BackgroundMode) {
+        self.mode = mode;
+        self.write_uniform(queue);
+    }
+
+    // The image is fitted to the screen's shape, so it has to be redone when that changes.
+    pub fn resize(&mut self, queue: &Queue, width: u32, height: u32) {
+        self.screen_size = (width, height);
+        self.write_uniform(queue);
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            sample_count,
+        );
+    }
+
+    // Does nothing without an image. Call before drawing anything else in the pass.
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some((_, bind_group)) = &self.image {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn write_uniform(&self, queue: &Queue) {
+        let Some((texture, _)) = &self.image else {
+            return;
+        };
+
+        let (image_width, image_height) = texture.size();
+        let (screen_width, screen_height) = self.screen_size;
+        // Above 1 when the screen is wider than the image
+        let ratio = (screen_width as f32 / screen_height.max(1) as f32)
+            / (image_width as f32 / image_height.max(1) as f32);
+
+        let uv_scale = match self.mode {
+            BackgroundMode::Stretch => [1.0, 1.0],
+            // Widens whichever way the screen is longer, so the image takes up less of it
+            BackgroundMode::Fit if ratio > 1.0 => [ratio, 1.0],
+            BackgroundMode::Fit => [1.0, 1.0 / ratio],
+            // Narrows the other way, so only part of the image is used
+            BackgroundMode::Fill if ratio > 1.0 => [1.0, 1.0 / ratio],
+            BackgroundMode::Fill => [ratio, 1.0],
+        };
+
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            cast_slice(&[BackgroundUniform {
+                uv_scale,
+                _padding: [0.0; 2],
+            }]),
+        );
+    }
+}
+
+// `sample_count` has to match the main pass.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Background Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState::default(),
+        // The main pass has a depth buffer, but the background neither tests against it nor writes to it
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
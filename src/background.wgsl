@@ -0,0 +1,26 @@
+// Draws an image behind the scene. fullscreen.wgsl's vertex stage is put in front of this by background.rs.
+
+struct BackgroundUniform {
+    // How much of the image fits across the screen. Above 1 shows bars, below 1 crops.
+    uv_scale: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_background: texture_2d<f32>;
+@group(0) @binding(1)
+var s_background: sampler;
+@group(0) @binding(2)
+var<uniform> background: BackgroundUniform;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    // Scaled around the middle, so the image stays centered
+    let image_uv = (uv - 0.5) * background.uv_scale + 0.5;
+
+    // Off the edge of a letterboxed image the clear color shows through
+    if (any(image_uv < vec2<f32>(0.0)) || any(image_uv > vec2<f32>(1.0))) {
+        discard;
+    }
+
+    return textureSampleLevel(t_background, s_background, image_uv, 0.0);
+}
@@ -0,0 +1,106 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, Buffer,
+    BufferUsages, CommandEncoder, Device, LoadOp, Operations, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::post;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct CalibrationUniform {
+    gamma: f32,
+    srgb_surface: u32,
+}
+
+// Fullscreen gamma test pattern, for checking colors reach the screen with the right encoding.
+// See calibration.wgsl for how to read it.
+pub struct Calibration {
+    pub enabled: bool,
+    uniform: CalibrationUniform,
+    buffer: Buffer,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl Calibration {
+    pub fn new(device: &Device, format: TextureFormat) -> Self {
+        let uniform = CalibrationUniform {
+            gamma: 2.2,
+            srgb_surface: format.describe().srgb as u32,
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Calibration Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::uniform_entry(0)],
+            label: Some("calibration_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("calibration_bind_group"),
+        });
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "Calibration Pipeline",
+            include_str!("calibration.wgsl"),
+            &[&bind_group_layout],
+            format,
+            None,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.uniform.gamma
+    }
+
+    pub fn set_gamma(&mut self, queue: &Queue, gamma: f32) {
+        self.uniform.gamma = gamma.clamp(1.0, 3.0);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    // Whether the surface does the sRGB encoding, or it's left to the shaders.
+    pub fn srgb_surface(&self) -> bool {
+        self.uniform.srgb_surface != 0
+    }
+
+    // Draws the pattern over everything in `target`.
+    pub fn render(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Calibration Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,71 @@
+struct CalibrationUniform {
+    // Gamma used when encoding output by hand
+    gamma: f32,
+    // Non-zero when the surface converts linear output to sRGB by itself
+    srgb_surface: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> calibration: CalibrationUniform;
+
+// Display gammas covered by the reference patches in the top row
+let FIRST_GAMMA: f32 = 1.6;
+let GAMMA_STEP: f32 = 0.2;
+let COLUMNS: f32 = 7.0;
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if (c <= 0.04045) {
+        return c / 12.92;
+    }
+    return pow((c + 0.055) / 1.055, 2.4);
+}
+
+// Returns what the shader has to output so `encoded` is the value that ends up in the surface.
+fn store_encoded(encoded: f32) -> f32 {
+    if (calibration.srgb_surface != 0u) {
+        return srgb_to_linear(encoded);
+    }
+    return encoded;
+}
+
+// Encodes a linear value the way the rest of the renderer should.
+// An sRGB surface already encodes, so the gamma only nudges it away from the sRGB curve (2.2 = no change).
+fn encode_linear(linear: f32) -> f32 {
+    if (calibration.srgb_surface != 0u) {
+        return pow(linear, 2.2 / calibration.gamma);
+    }
+    return pow(linear, 1.0 / calibration.gamma);
+}
+
+// Each column is alternating black and white lines on the left, a solid gray patch on the right.
+// Seen from a distance (or squinting), the lines average out to 50% of the light.
+// - Top row: patches pre-encoded for display gammas 1.6 to 2.8, left to right. The one that blends in is your display's gamma.
+// - Bottom row: 50% gray sent through the output encoding. It blends in when color management is right.
+//   A double gamma bug makes it far too bright.
+@fragment
+fn fs_main(
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+) -> @location(0) vec4<f32> {
+    let column = floor(uv.x * COLUMNS);
+    let in_column = fract(uv.x * COLUMNS);
+
+    // Thin dark gaps between the columns and rows
+    if (in_column < 0.02 || in_column > 0.98 || abs(uv.y - 0.5) < 0.01) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    var value: f32;
+
+    if (in_column < 0.5) {
+        // 1 pixel lines are exactly 0 or 1 in every encoding
+        value = f32(u32(position.y) % 2u);
+    } else if (uv.y < 0.5) {
+        let display_gamma = FIRST_GAMMA + column * GAMMA_STEP;
+        value = store_encoded(pow(0.5, 1.0 / display_gamma));
+    } else {
+        value = encode_linear(0.5);
+    }
+
+    return vec4<f32>(vec3<f32>(value), 1.0);
+}
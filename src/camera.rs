@@ -1,5 +1,21 @@
-use cgmath::{perspective, Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use std::{
+    f32::consts::{FRAC_PI_2, TAU},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use cgmath::{
+    ortho, perspective, Deg, EuclideanSpace, InnerSpace, Matrix4, One, Point3, Quaternion, Rad,
+    Rotation, SquareMatrix, Vector3, Vector4, Zero,
+};
+use serde::{Deserialize, Serialize};
+use wgpu::{CompareFunction, SurfaceConfiguration};
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -9,6 +25,34 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+// Flips depth so the near plane lands on 1 and the far plane on 0. Floats are most precise near 0, which
+// cancels out perspective bunching most of the depth range up close to the camera.
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+// Nearer things win the depth test, which with reversed-Z means greater depth values.
+pub fn depth_compare(reversed_z: bool) -> CompareFunction {
+    if reversed_z {
+        CompareFunction::Greater
+    } else {
+        CompareFunction::Less
+    }
+}
+
+// Depth buffers are cleared to the far plane.
+pub fn depth_clear_value(reversed_z: bool) -> f32 {
+    if reversed_z {
+        0.0
+    } else {
+        1.0
+    }
+}
+
 // For rust to store the data correctly for the shaders
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -16,62 +60,500 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // Where the eye is, for specular lighting. A vec4 because a vec3 would need padding anyway.
+    view_position: [f32; 4],
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
             view_proj: Matrix4::identity().into(),
+            view_position: [0.0; 4],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.view_position = camera.eye.to_homogeneous().into();
+    }
+}
+
+// Seconds of scene time, for shaders that animate. Shares the camera's bind group, and matches `Time` in shader.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TimeUniform {
+    elapsed: f32,
+    // Uniforms need to be 16 byte aligned
+    _padding: [f32; 3],
+}
+
+impl TimeUniform {
+    pub fn new(elapsed: Duration) -> Self {
+        Self {
+            elapsed: elapsed.as_secs_f32(),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    // Vertical field of view in degrees
+    Perspective { fovy: f32 },
+    // World units visible from the bottom to the top of the screen. Things stay the same size at any distance.
+    Orthographic { height: f32 },
+}
+
+// What Camera::save writes out, in plain arrays since cgmath's types can't be serialized.
+// e.g. {"eye": [0, 1, 2], "target": [0, 0, 0], "up": [0, 1, 0], "fovy": 45, "znear": 0.1, "zfar": 100}
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct CameraState {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    // Vertical field of view in degrees. Ignored when `ortho_height` is set.
+    pub fovy: f32,
+    // Set for an orthographic camera, see Projection::Orthographic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ortho_height: Option<f32>,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl CameraState {
+    // Reads a file written by Camera::save.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+
+        serde_json::from_str(&json)
+            .with_context(|| format!("{} isn't a camera state", path.display()))
     }
 }
 
+// Slow at both ends, quick in the middle. Takes and gives 0..1.
+pub fn ease_in_out(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
 pub struct Camera {
     pub eye: Point3<f32>,
     pub target: Point3<f32>,
     pub up: Vector3<f32>,
     pub aspect: f32,
-    pub fovy: f32,
+    pub projection: Projection,
     pub znear: f32,
     pub zfar: f32,
+    // Depth goes from 1 at the near plane to 0 at the far plane. See REVERSE_Z_MATRIX.
+    pub reversed_z: bool,
 }
 
 impl Camera {
+    // Where the camera starts out, looking at the origin from just above and behind it.
+    pub fn default_for(config: &SurfaceConfiguration) -> Self {
+        Self {
+            // Camera is 1 unit up and 2 units back
+            eye: (0.0, 1.0, 2.0).into(),
+            // This looks at the origin point
+            target: (0.0, 0.0, 0.0).into(),
+            // This says which way is "up"
+            up: Vector3::unit_y(),
+            aspect: config.width as f32 / config.height as f32,
+            projection: Projection::Perspective { fovy: DEFAULT_FOVY },
+            znear: 0.1,
+            zfar: 100.0,
+            reversed_z: false,
+        }
+    }
+
     pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         // Moves the world to be at the position and rotation of the camera.
         let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
 
-        // Warps the scene to give the effect of depth. W/o this objects far away and close would be the same size.
-        let proj = perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        self.build_projection_matrix() * view
+    }
+
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
+        let proj = match self.projection {
+            // Warps the scene to give the effect of depth. W/o this objects far away and close would be the same size.
+            Projection::Perspective { fovy } => {
+                perspective(Deg(fovy), self.aspect, self.znear, self.zfar)
+            }
+            Projection::Orthographic { height } => {
+                let top = height * 0.5;
+                let right = top * self.aspect;
+                ortho(-right, right, -top, top, self.znear, self.zfar)
+            }
+        };
 
         // This will scale and translate the scene from OpenGL to WGPU.
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+        let proj = OPENGL_TO_WGPU_MATRIX * proj;
+
+        if self.reversed_z {
+            REVERSE_Z_MATRIX * proj
+        } else {
+            proj
+        }
+    }
+
+    // Switches between perspective and orthographic, keeping things at the target the same size on screen.
+    pub fn toggle_projection(&mut self) {
+        let distance = (self.target - self.eye).magnitude();
+
+        self.projection = match self.projection {
+            Projection::Perspective { fovy } => Projection::Orthographic {
+                height: 2.0 * distance * (Rad::from(Deg(fovy)).0 * 0.5).tan(),
+            },
+            Projection::Orthographic { height } => Projection::Perspective {
+                fovy: Deg::from(Rad(2.0 * (height * 0.5 / distance).atan())).0,
+            },
+        };
+    }
+
+    pub fn state(&self) -> CameraState {
+        let (fovy, ortho_height) = match self.projection {
+            Projection::Perspective { fovy } => (fovy, None),
+            Projection::Orthographic { height } => (DEFAULT_FOVY, Some(height)),
+        };
+
+        CameraState {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: self.up.into(),
+            fovy,
+            ortho_height,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    // Keeps the aspect ratio, which belongs to the window rather than the camera.
+    pub fn set_state(&mut self, state: CameraState) {
+        self.eye = state.eye.into();
+        self.target = state.target.into();
+        self.up = state.up.into();
+        self.projection = match state.ortho_height {
+            Some(height) => Projection::Orthographic { height },
+            None => Projection::Perspective { fovy: state.fovy },
+        };
+        self.znear = state.znear;
+        self.zfar = state.zfar;
+    }
+
+    // Writes where the camera is and what it sees to a JSON file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(&self.state())?;
+        fs::write(path, json).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    // Reads back a file written by `save`.
+    pub fn load(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.set_state(CameraState::load(path)?);
+
+        Ok(())
+    }
+
+    // Moves `t` of the way from here to `target`, 0 staying put and 1 landing on it.
+    // The up vector turns along the shortest arc rather than being blended, so it stays unit length.
+    // Switching between perspective and orthographic can't be blended and happens once `t` reaches 1.
+    pub fn lerp_to(&mut self, target: &CameraState, t: f32) {
+        if t >= 1.0 {
+            self.set_state(*target);
+            return;
+        }
+
+        self.eye += (Point3::from(target.eye) - self.eye) * t;
+        self.target += (Point3::from(target.target) - self.target) * t;
+
+        let target_up = Vector3::from(target.up).normalize();
+        let arc = Quaternion::from_arc(self.up.normalize(), target_up, None);
+        self.up = Quaternion::one().slerp(arc, t).rotate_vector(self.up);
+
+        self.projection = match (self.projection, target.ortho_height) {
+            (Projection::Perspective { fovy }, None) => Projection::Perspective {
+                fovy: fovy + (target.fovy - fovy) * t,
+            },
+            (Projection::Orthographic { height }, Some(target_height)) => {
+                Projection::Orthographic {
+                    height: height + (target_height - height) * t,
+                }
+            }
+            (projection, _) => projection,
+        };
+        self.znear += (target.znear - self.znear) * t;
+        self.zfar += (target.zfar - self.zfar) * t;
+    }
+
+    // Moves the eye straight back from the center of the box until all of it fits on screen.
+    // Keeps looking from the same direction, and pushes the far plane back if the box would poke through it.
+    pub fn frame_aabb(&mut self, min: Point3<f32>, max: Point3<f32>) {
+        let center = min.midpoint(max);
+        // A sphere around the box fits no matter which way the camera faces
+        let radius = ((max - min).magnitude() * 0.5).max(f32::EPSILON);
+
+        let offset = self.eye - self.target;
+        let direction = if offset.magnitude2() > 0.0 {
+            offset.normalize()
+        } else {
+            Vector3::unit_z()
+        };
+
+        // The narrower of the two fields of view decides how far back the sphere fits
+        let distance = match &mut self.projection {
+            Projection::Perspective { fovy } => {
+                let half_fovy = Rad::from(Deg(*fovy)).0 * 0.5;
+                let half_fovx = (half_fovy.tan() * self.aspect).atan();
+                radius / half_fovy.min(half_fovx).sin()
+            }
+            Projection::Orthographic { height } => {
+                *height = 2.0 * radius * 1.0f32.max(1.0 / self.aspect);
+                radius * 2.0
+            }
+        };
+
+        self.target = center;
+        self.eye = center + direction * distance;
+        self.zfar = self.zfar.max(distance + radius);
+    }
+
+    // Origin and direction of the ray through a point on screen, in uv coordinates where (0, 0) is the top left.
+    pub fn screen_ray(&self, uv: [f32; 2]) -> Option<(Point3<f32>, Vector3<f32>)> {
+        let inverse = self.build_view_projection_matrix().invert()?;
+
+        let x = uv[0] * 2.0 - 1.0;
+        let y = 1.0 - uv[1] * 2.0;
+
+        // Depth goes from 0 at the near plane to 1 at the far plane, or the other way with reversed-Z
+        let far_depth = depth_clear_value(self.reversed_z);
+        let near_depth = 1.0 - far_depth;
+        let near = Point3::from_homogeneous(inverse * Vector4::new(x, y, near_depth, 1.0));
+        let far = Point3::from_homogeneous(inverse * Vector4::new(x, y, far_depth, 1.0));
+
+        Some((near, (far - near).normalize()))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    // WASD moves the eye towards and around the target, shift and control move it up and down,
+    // scrolling changes the field of view. The keys can be changed with KeyBindings.
+    Fly,
+    // Dragging with the right mouse button rotates the eye around the target, scrolling zooms
+    Orbit,
+}
+
+// Which keys move the fly camera. Any of the keys in a list works. Defaults to WASD and the arrow keys,
+// with left shift and left control going up and down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    pub forward: Vec<VirtualKeyCode>,
+    pub backward: Vec<VirtualKeyCode>,
+    pub left: Vec<VirtualKeyCode>,
+    pub right: Vec<VirtualKeyCode>,
+    // Along the world Y axis, whichever way the camera is facing
+    pub up: Vec<VirtualKeyCode>,
+    pub down: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: vec![VirtualKeyCode::W, VirtualKeyCode::Up],
+            backward: vec![VirtualKeyCode::S, VirtualKeyCode::Down],
+            left: vec![VirtualKeyCode::A, VirtualKeyCode::Left],
+            right: vec![VirtualKeyCode::D, VirtualKeyCode::Right],
+            up: vec![VirtualKeyCode::LShift],
+            down: vec![VirtualKeyCode::LControl],
+        }
     }
 }
 
 pub struct CameraController {
+    // Units per second
     speed: f32,
+    mode: CameraMode,
+    key_bindings: KeyBindings,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_rotating: bool,
+    // Set when R is pressed, applied to the camera on the next update
+    toggle_projection: bool,
+    // Set when Backspace is pressed, puts the field of view back to DEFAULT_FOVY on the next update
+    reset_fovy: bool,
+    // Set while the window holds on to the cursor. Mouse movement then turns the view without a button held.
+    mouse_look: bool,
+    // Mouse movement and scrolling since the last update
+    rotate_delta: (f32, f32),
+    look_delta: (f32, f32),
+    scroll_delta: f32,
+    // Orbit angles in radians. A yaw of 0 puts the eye on the +z side of the target.
+    yaw: f32,
+    pitch: f32,
+    radius: f32,
+    // Where the orbit put the eye last update. If the eye is somewhere else, something else moved the camera
+    // and the angles get worked out again.
+    orbit_eye: Option<Point3<f32>>,
+    shake: Shake,
+}
+
+// Radians of rotation per pixel of mouse movement
+const ORBIT_SENSITIVITY: f32 = 0.005;
+// Radians the fly camera turns per pixel of mouse movement
+const LOOK_SENSITIVITY: f32 = 0.003;
+// Just under straight up or down, where the view would flip over
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+// Fraction of the radius one scroll wheel notch zooms by
+const ZOOM_STEP: f32 = 0.1;
+const MIN_RADIUS: f32 = 0.5;
+
+// Vertical field of view in degrees that the camera starts with
+pub const DEFAULT_FOVY: f32 = 45.0;
+// Degrees one scroll wheel notch narrows or widens the fly camera's view by
+const FOVY_STEP: f32 = 5.0;
+const MIN_FOVY: f32 = 10.0;
+const MAX_FOVY: f32 = 120.0;
+
+// Noise samples per second. Higher values make the shake more jittery.
+const SHAKE_FREQUENCY: f32 = 25.0;
+
+// A decaying random offset added on top of wherever the camera is.
+struct Shake {
+    intensity: f32,
+    duration: f32,
+    remaining: f32,
+    time: f32,
+    seed: u32,
+    // Offset applied last frame. Taken back out before moving the camera so the shake never sticks.
+    offset: Vector3<f32>,
 }
 
 impl CameraController {
-    pub fn new(speed: f32) -> Self {
+    pub fn new(speed: f32, mode: CameraMode) -> Self {
         Self {
             speed,
+            mode,
+            key_bindings: KeyBindings::default(),
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_rotating: false,
+            toggle_projection: false,
+            reset_fovy: false,
+            mouse_look: false,
+            rotate_delta: (0.0, 0.0),
+            look_delta: (0.0, 0.0),
+            scroll_delta: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            radius: 1.0,
+            orbit_eye: None,
+            shake: Shake {
+                intensity: 0.0,
+                duration: 0.0,
+                remaining: 0.0,
+                time: 0.0,
+                seed: 0,
+                offset: Vector3::zero(),
+            },
         }
     }
 
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    // Whether the user is moving the camera right now, with keys held or the mouse moved since the last update.
+    pub fn is_moving(&self) -> bool {
+        self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed
+            || self.is_up_pressed
+            || self.is_down_pressed
+            || self.rotate_delta != (0.0, 0.0)
+            || self.look_delta != (0.0, 0.0)
+            || self.scroll_delta != 0.0
+    }
+
+    // Forgets any mouse movement, scrolling and key toggles that haven't been applied yet,
+    // so a camera that was just put somewhere stays there. Keys still held keep moving it.
+    pub fn reset(&mut self) {
+        self.toggle_projection = false;
+        self.reset_fovy = false;
+        self.rotate_delta = (0.0, 0.0);
+        self.look_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+        self.orbit_eye = None;
+    }
+
+    // Takes the shake back out of the camera and stops it. Called when something other than
+    // update_camera moves the camera, since only update_camera undoes the offset otherwise.
+    pub fn clear_shake(&mut self, camera: &mut Camera) {
+        camera.eye -= self.shake.offset;
+        camera.target -= self.shake.offset;
+        self.shake.remaining = 0.0;
+        self.shake.offset = Vector3::zero();
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = mode;
+        self.orbit_eye = None;
+    }
+
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    // Keys held down under the old bindings are let go, since their releases might not be recognized.
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+        self.is_up_pressed = false;
+        self.is_down_pressed = false;
+    }
+
+    pub fn set_mouse_look(&mut self, mouse_look: bool) {
+        self.mouse_look = mouse_look;
+        self.look_delta = (0.0, 0.0);
+    }
+
+    // Raw mouse movement, from DeviceEvent::MouseMotion. Unlike cursor positions it keeps coming at the window edges.
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        let delta = match self.mode {
+            CameraMode::Orbit if self.is_rotating || self.mouse_look => &mut self.rotate_delta,
+            CameraMode::Fly if self.mouse_look => &mut self.look_delta,
+            _ => return,
+        };
+
+        delta.0 += dx as f32;
+        delta.1 += dy as f32;
+    }
+
+    // Shakes the camera by up to `intensity` units, fading out over `duration`.
+    pub fn add_shake(&mut self, intensity: f32, duration: Duration) {
+        let shake = &mut self.shake;
+
+        // A stronger shake replaces a weaker one that is already running.
+        let current = shake.intensity * shake.decay();
+        shake.intensity = intensity.max(current);
+        shake.duration = duration.as_secs_f32();
+        shake.remaining = shake.duration;
+        shake.time = 0.0;
+        // New seed each time so consecutive shakes don't look identical
+        shake.seed = shake.seed.wrapping_add(1);
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -84,42 +566,105 @@ impl CameraController {
                 ..
             } => {
                 let is_pressed = *state == ElementState::Pressed;
+
+                let bindings = &self.key_bindings;
+                let movement = if bindings.forward.contains(keycode) {
+                    Some(&mut self.is_forward_pressed)
+                } else if bindings.backward.contains(keycode) {
+                    Some(&mut self.is_backward_pressed)
+                } else if bindings.left.contains(keycode) {
+                    Some(&mut self.is_left_pressed)
+                } else if bindings.right.contains(keycode) {
+                    Some(&mut self.is_right_pressed)
+                } else if bindings.up.contains(keycode) {
+                    Some(&mut self.is_up_pressed)
+                } else if bindings.down.contains(keycode) {
+                    Some(&mut self.is_down_pressed)
+                } else {
+                    None
+                };
+                if let Some(pressed) = movement {
+                    *pressed = is_pressed;
+                    return true;
+                }
+
                 match keycode {
-                    VirtualKeyCode::W | VirtualKeyCode::Up => {
-                        self.is_forward_pressed = is_pressed;
+                    VirtualKeyCode::R => {
+                        self.toggle_projection |= is_pressed;
                         true
                     }
-                    VirtualKeyCode::A | VirtualKeyCode::Left => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::S | VirtualKeyCode::Down => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::D | VirtualKeyCode::Right => {
-                        self.is_right_pressed = is_pressed;
+                    VirtualKeyCode::Back => {
+                        self.reset_fovy |= is_pressed;
                         true
                     }
                     _ => false,
                 }
             }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Right,
+                ..
+            } if self.mode == CameraMode::Orbit => {
+                self.is_rotating = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    // Touchpads scroll in pixels. Roughly 50 of them make up a notch.
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                };
+                true
+            }
             _ => false,
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        // Undo last frame's shake so movement works from the real position.
+        camera.eye -= self.shake.offset;
+        camera.target -= self.shake.offset;
+
+        if self.toggle_projection {
+            camera.toggle_projection();
+            self.toggle_projection = false;
+        }
+
+        if self.reset_fovy {
+            if let Projection::Perspective { fovy } = &mut camera.projection {
+                *fovy = DEFAULT_FOVY;
+            }
+            self.reset_fovy = false;
+        }
+
+        match self.mode {
+            CameraMode::Fly => self.fly(camera, dt),
+            CameraMode::Orbit => self.orbit(camera),
+        }
+
+        self.shake.update(dt.as_secs_f32());
+        camera.eye += self.shake.offset;
+        camera.target += self.shake.offset;
+    }
+
+    fn fly(&mut self, camera: &mut Camera, dt: Duration) {
+        self.look(camera);
+        self.zoom(camera);
+
+        // Scaled by the frame time so the camera moves just as fast at any frame rate
+        let step = self.speed * dt.as_secs_f32();
+
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
         // Prevents glitching when camera gets too close to the
         // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+        if self.is_forward_pressed && forward_mag > step {
+            camera.eye += forward_norm * step;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+            camera.eye -= forward_norm * step;
         }
 
         let right = forward_norm.cross(camera.up);
@@ -132,10 +677,243 @@ impl CameraController {
             // Rescale the distance between the target and eye so
             // that it doesn't change. The eye therefore still
             // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward + right * step).normalize() * forward_mag;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward - right * step).normalize() * forward_mag;
+        }
+
+        // Moves the target along too, so the view only slides up or down
+        let lift = match (self.is_up_pressed, self.is_down_pressed) {
+            (true, false) => step,
+            (false, true) => -step,
+            _ => 0.0,
+        };
+        camera.eye.y += lift;
+        camera.target.y += lift;
+    }
+
+    // Scrolling up narrows the field of view, which zooms in. Orthographic views have no field of view to change.
+    fn zoom(&mut self, camera: &mut Camera) {
+        if let Projection::Perspective { fovy } = &mut camera.projection {
+            *fovy = (*fovy - self.scroll_delta * FOVY_STEP).clamp(MIN_FOVY, MAX_FOVY);
+        }
+
+        self.scroll_delta = 0.0;
+    }
+
+    // Turns the view around the eye, keeping the target the same distance away.
+    fn look(&mut self, camera: &mut Camera) {
+        if self.look_delta == (0.0, 0.0) {
+            return;
+        }
+
+        let forward = camera.target - camera.eye;
+        let distance = forward.magnitude();
+        let pitch = (forward.y / distance).clamp(-1.0, 1.0).asin();
+        let yaw = forward.x.atan2(forward.z);
+
+        // Moving the mouse right turns right, moving it up looks up
+        let yaw = (yaw - self.look_delta.0 * LOOK_SENSITIVITY).rem_euclid(TAU);
+        let pitch = (pitch - self.look_delta.1 * LOOK_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+
+        self.look_delta = (0.0, 0.0);
+
+        let direction = Vector3::new(
+            pitch.cos() * yaw.sin(),
+            pitch.sin(),
+            pitch.cos() * yaw.cos(),
+        );
+        camera.target = camera.eye + direction * distance;
+    }
+
+    fn orbit(&mut self, camera: &mut Camera) {
+        if self.orbit_eye != Some(camera.eye) {
+            let offset = camera.eye - camera.target;
+            self.radius = offset.magnitude().max(MIN_RADIUS);
+            self.pitch = (offset.y / self.radius).clamp(-1.0, 1.0).asin();
+            self.yaw = offset.x.atan2(offset.z);
+        }
+
+        // Dragging right moves the eye right, which turns the view left around the target
+        self.yaw -= self.rotate_delta.0 * ORBIT_SENSITIVITY;
+        self.pitch =
+            (self.pitch + self.rotate_delta.1 * ORBIT_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+        // Scrolling up zooms in
+        self.radius = (self.radius * (1.0 - self.scroll_delta * ZOOM_STEP)).max(MIN_RADIUS);
+
+        self.rotate_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+
+        let direction = Vector3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        );
+        camera.eye = camera.target + direction * self.radius;
+        self.orbit_eye = Some(camera.eye);
+    }
+}
+
+impl Shake {
+    // 1 when the shake starts, easing down to 0 when it ends.
+    fn decay(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 0.0;
+        }
+
+        let t = (self.remaining / self.duration).max(0.0);
+        t * t
+    }
+
+    fn update(&mut self, dt: f32) {
+        if self.remaining <= 0.0 {
+            self.offset = Vector3::zero();
+            return;
+        }
+
+        self.remaining -= dt;
+        self.time += dt;
+
+        let amplitude = self.intensity * self.decay();
+        let t = self.time * SHAKE_FREQUENCY;
+
+        self.offset = Vector3::new(
+            noise(self.seed, 0, t),
+            noise(self.seed, 1, t),
+            noise(self.seed, 2, t),
+        ) * amplitude;
+    }
+}
+
+// Integer hash mapped to [-1, 1]. Same inputs always give the same value.
+pub fn hash(seed: u32, axis: u32, i: i32) -> f32 {
+    let mut x = seed.wrapping_mul(0x9e37_79b9)
+        ^ axis.wrapping_mul(0x85eb_ca6b)
+        ^ (i as u32).wrapping_mul(0xc2b2_ae35);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+
+    x as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+// Smooth 1D value noise: random values at whole numbers, smoothstepped in between.
+fn noise(seed: u32, axis: u32, t: f32) -> f32 {
+    let i = t.floor();
+    let f = t - i;
+    let s = f * f * (3.0 - 2.0 * f);
+
+    let a = hash(seed, axis, i as i32);
+    let b = hash(seed, axis, i as i32 + 1);
+
+    a + (b - a) * s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_camera() -> Camera {
+        Camera {
+            eye: (0.0, 1.0, 2.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_y(),
+            aspect: 1.0,
+            projection: Projection::Perspective { fovy: DEFAULT_FOVY },
+            znear: 0.1,
+            zfar: 100.0,
+            reversed_z: false,
+        }
+    }
+
+    // How far the eye gets with backward held for one update of `dt`.
+    fn distance_moved(dt: Duration) -> f32 {
+        let mut camera = test_camera();
+        let start = camera.eye;
+
+        let mut controller = CameraController::new(4.0, CameraMode::Fly);
+        controller.is_backward_pressed = true;
+        controller.update_camera(&mut camera, dt);
+
+        (camera.eye - start).magnitude()
+    }
+
+    #[test]
+    fn movement_scales_with_dt() {
+        let once = distance_moved(Duration::from_millis(50));
+        let twice = distance_moved(Duration::from_millis(100));
+
+        assert!(once > 0.0);
+        assert!(
+            (twice - 2.0 * once).abs() < 1e-5,
+            "moved {} in dt and {} in 2 dt",
+            once,
+            twice
+        );
+    }
+
+    #[test]
+    fn cleared_shake_leaves_the_camera_where_it_was() {
+        let mut camera = test_camera();
+        let (eye, target) = (camera.eye, camera.target);
+
+        let mut controller = CameraController::new(4.0, CameraMode::Fly);
+        controller.add_shake(1.0, Duration::from_secs(1));
+        controller.update_camera(&mut camera, Duration::from_millis(100));
+        assert_ne!(camera.eye, eye);
+
+        controller.clear_shake(&mut camera);
+        assert!((camera.eye - eye).magnitude() < 1e-6);
+        assert!((camera.target - target).magnitude() < 1e-6);
+
+        // Nothing left to undo or add back on the next update
+        controller.update_camera(&mut camera, Duration::from_millis(100));
+        assert!((camera.eye - eye).magnitude() < 1e-6);
+    }
+
+    // Depth after the perspective divide of a point `distance` in front of the camera.
+    fn depth_at(camera: &Camera, distance: f32) -> f32 {
+        let forward = (camera.target - camera.eye).normalize();
+        let point = camera.eye + forward * distance;
+        let clip = camera.build_view_projection_matrix() * point.to_homogeneous();
+
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn near_and_far_planes_map_to_the_depth_range() {
+        for reversed_z in [false, true] {
+            for projection in [
+                Projection::Perspective { fovy: DEFAULT_FOVY },
+                Projection::Orthographic { height: 2.0 },
+            ] {
+                let camera = Camera {
+                    projection,
+                    reversed_z,
+                    ..test_camera()
+                };
+                let (near, far) = if reversed_z { (1.0, 0.0) } else { (0.0, 1.0) };
+
+                let near_depth = depth_at(&camera, camera.znear);
+                let far_depth = depth_at(&camera, camera.zfar);
+                assert!(
+                    (near_depth - near).abs() < 1e-4,
+                    "{:?} reversed_z {}: near plane at {}",
+                    projection,
+                    reversed_z,
+                    near_depth
+                );
+                assert!(
+                    (far_depth - far).abs() < 1e-4,
+                    "{:?} reversed_z {}: far plane at {}",
+                    projection,
+                    reversed_z,
+                    far_depth
+                );
+            }
         }
     }
 }
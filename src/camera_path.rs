@@ -0,0 +1,108 @@
+use anyhow::*;
+use cgmath::{EuclideanSpace, Point3, Vector3};
+use serde::Deserialize;
+
+// Where the camera should be at `time` seconds into the path.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Keyframe {
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    // Vertical field of view in degrees
+    pub fovy: f32,
+    pub time: f32,
+}
+
+// What a path gives the camera at some point in time.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSample {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub fovy: f32,
+}
+
+// A flythrough the camera follows by passing smoothly through each keyframe.
+// e.g. {"keyframes": [{"position": [0, 5, 10], "target": [0, 0, 0], "fovy": 45, "time": 0}, ...]}
+#[derive(Clone, Debug, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut path: CameraPath = serde_json::from_str(json)?;
+
+        if path.keyframes.is_empty() {
+            bail!("Camera path has no keyframes");
+        }
+
+        path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Ok(path)
+    }
+
+    // Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Catmull-Rom interpolates between the keyframes around `time`, so the camera doesn't jolt
+    // when passing through one. Before the first or after the last keyframe it just holds still.
+    pub fn sample(&self, time: f32) -> CameraSample {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len() - 1;
+
+        // Index of the keyframe that starts the segment `time` falls in
+        let i = keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.time <= time)
+            .unwrap_or(0)
+            .min(last.saturating_sub(1));
+
+        // The curve through k1 and k2 is shaped by the keyframes either side. The ends reuse their own keyframe.
+        let k0 = &keyframes[i.saturating_sub(1)];
+        let k1 = &keyframes[i];
+        let k2 = &keyframes[(i + 1).min(last)];
+        let k3 = &keyframes[(i + 2).min(last)];
+
+        let length = k2.time - k1.time;
+        let t = if length > 0.0 {
+            ((time - k1.time) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let vector = |f: fn(&Keyframe) -> [f32; 3]| {
+            catmull_rom(
+                Vector3::from(f(k0)),
+                Vector3::from(f(k1)),
+                Vector3::from(f(k2)),
+                Vector3::from(f(k3)),
+                t,
+            )
+        };
+
+        CameraSample {
+            eye: Point3::from_vec(vector(|keyframe| keyframe.position)),
+            target: Point3::from_vec(vector(|keyframe| keyframe.target)),
+            fovy: catmull_rom(k0.fovy, k1.fovy, k2.fovy, k3.fovy, t),
+        }
+    }
+}
+
+// Curve through p1 at t = 0 and p2 at t = 1.
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
@@ -0,0 +1,56 @@
+// `hue` is in degrees and wraps, so 360 is red again. `saturation` and `value` go from 0 to 1.
+// https://en.wikipedia.org/wiki/HSL_and_HSV#HSV_to_RGB
+pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [f32; 3] {
+    let chroma = value * saturation;
+    // Which sixth of the wheel the hue is in
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+
+    let m = value - chroma;
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rgb(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.into_iter().zip(expected) {
+            assert!(
+                (a - e).abs() < 1e-5,
+                "got {:?}, expected {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn primaries() {
+        assert_rgb(hsv_to_rgb(0.0, 1.0, 1.0), [1.0, 0.0, 0.0]);
+        assert_rgb(hsv_to_rgb(120.0, 1.0, 1.0), [0.0, 1.0, 0.0]);
+        assert_rgb(hsv_to_rgb(240.0, 1.0, 1.0), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn no_saturation_is_gray() {
+        assert_rgb(hsv_to_rgb(0.0, 0.0, 1.0), [1.0; 3]);
+        assert_rgb(hsv_to_rgb(200.0, 0.0, 0.5), [0.5; 3]);
+    }
+
+    #[test]
+    fn hue_wraps_around() {
+        assert_rgb(hsv_to_rgb(360.0, 1.0, 1.0), hsv_to_rgb(0.0, 1.0, 1.0));
+        assert_rgb(hsv_to_rgb(480.0, 1.0, 1.0), hsv_to_rgb(120.0, 1.0, 1.0));
+        assert_rgb(hsv_to_rgb(-120.0, 1.0, 1.0), hsv_to_rgb(240.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,161 @@
+// CPU block compression for textures, so they take less VRAM without needing pre-compressed files.
+// Both formats split the image into 4x4 pixel blocks:
+// - BC1 stores each block in 8 bytes: two RGB565 end colors and a 2 bit index per pixel picking a color between them.
+// - BC3 adds an 8 byte alpha block in front, which works the same way with 8 alpha levels.
+// https://learn.microsoft.com/en-us/windows/win32/direct3d10/d3d10-graphics-programming-guide-resources-block-compression
+
+pub const BLOCK_SIZE: u32 = 4;
+
+pub fn compress_bc1(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, 8, |block, out| {
+        out.extend_from_slice(&color_block(block));
+    })
+}
+
+pub fn compress_bc3(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    compress_blocks(rgba, width, height, 16, |block, out| {
+        out.extend_from_slice(&alpha_block(block));
+        out.extend_from_slice(&color_block(block));
+    })
+}
+
+// `width` and `height` need to be multiples of BLOCK_SIZE.
+fn compress_blocks(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    bytes_per_block: usize,
+    mut compress_block: impl FnMut(&[[u8; 4]; 16], &mut Vec<u8>),
+) -> Vec<u8> {
+    let blocks_wide = width / BLOCK_SIZE;
+    let blocks_high = height / BLOCK_SIZE;
+
+    let mut out = Vec::with_capacity((blocks_wide * blocks_high) as usize * bytes_per_block);
+
+    for block_y in 0..blocks_high {
+        for block_x in 0..blocks_wide {
+            let mut block = [[0; 4]; 16];
+
+            for (i, pixel) in block.iter_mut().enumerate() {
+                let x = block_x * BLOCK_SIZE + i as u32 % BLOCK_SIZE;
+                let y = block_y * BLOCK_SIZE + i as u32 / BLOCK_SIZE;
+                let offset = ((y * width + x) * 4) as usize;
+                pixel.copy_from_slice(&rgba[offset..offset + 4]);
+            }
+
+            compress_block(&block, &mut out);
+        }
+    }
+
+    out
+}
+
+fn color_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    // The corners of the block's bounding box in RGB make decent end colors
+    let mut min = [255u8; 3];
+    let mut max = [0u8; 3];
+    for pixel in block {
+        for c in 0..3 {
+            min[c] = min[c].min(pixel[c]);
+            max[c] = max[c].max(pixel[c]);
+        }
+    }
+
+    let mut color0 = to_rgb565(max);
+    let mut color1 = to_rgb565(min);
+
+    // color0 > color1 selects the 4 color mode. Otherwise BC1 treats the last index as transparent black.
+    if color0 < color1 {
+        std::mem::swap(&mut color0, &mut color1);
+    }
+
+    let mut indices = 0u32;
+
+    if color0 != color1 {
+        let c0 = from_rgb565(color0);
+        let c1 = from_rgb565(color1);
+        let palette = [c0, c1, lerp_color(c0, c1, 1, 3), lerp_color(c0, c1, 2, 3)];
+
+        for (i, pixel) in block.iter().enumerate() {
+            let index = closest(&palette, |color| {
+                (0..3)
+                    .map(|c| (color[c] as i32 - pixel[c] as i32).pow(2))
+                    .sum()
+            });
+            indices |= (index as u32) << (i * 2);
+        }
+    }
+
+    let mut out = [0; 8];
+    out[0..2].copy_from_slice(&color0.to_le_bytes());
+    out[2..4].copy_from_slice(&color1.to_le_bytes());
+    out[4..8].copy_from_slice(&indices.to_le_bytes());
+    out
+}
+
+fn alpha_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let alpha0 = block.iter().map(|pixel| pixel[3]).max().unwrap();
+    let alpha1 = block.iter().map(|pixel| pixel[3]).min().unwrap();
+
+    let mut indices = 0u64;
+
+    // alpha0 > alpha1 selects 6 alphas between the two ends
+    if alpha0 != alpha1 {
+        let mut palette = [0u8; 8];
+        palette[0] = alpha0;
+        palette[1] = alpha1;
+        for (i, alpha) in palette[2..].iter_mut().enumerate() {
+            let i = i as u32 + 1;
+            *alpha = ((alpha0 as u32 * (7 - i) + alpha1 as u32 * i) / 7) as u8;
+        }
+
+        for (i, pixel) in block.iter().enumerate() {
+            let index = closest(&palette, |alpha| (*alpha as i32 - pixel[3] as i32).abs());
+            indices |= (index as u64) << (i * 3);
+        }
+    }
+
+    let mut out = [0; 8];
+    out[0] = alpha0;
+    out[1] = alpha1;
+    out[2..8].copy_from_slice(&indices.to_le_bytes()[0..6]);
+    out
+}
+
+// Index of the palette entry with the smallest error.
+fn closest<T>(palette: &[T], error: impl Fn(&T) -> i32) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| error(entry))
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+fn to_rgb565(color: [u8; 3]) -> u16 {
+    let r = (color[0] as u16 * 31 + 127) / 255;
+    let g = (color[1] as u16 * 63 + 127) / 255;
+    let b = (color[2] as u16 * 31 + 127) / 255;
+    (r << 11) | (g << 5) | b
+}
+
+fn from_rgb565(color: u16) -> [u8; 3] {
+    let r = (color >> 11) & 31;
+    let g = (color >> 5) & 63;
+    let b = color & 31;
+    [
+        (r * 255 / 31) as u8,
+        (g * 255 / 63) as u8,
+        (b * 255 / 31) as u8,
+    ]
+}
+
+// `a` moved `numerator / denominator` of the way towards `b`.
+fn lerp_color(a: [u8; 3], b: [u8; 3], numerator: u32, denominator: u32) -> [u8; 3] {
+    let mut out = [0; 3];
+    for c in 0..3 {
+        out[c] = ((a[c] as u32 * (denominator - numerator) + b[c] as u32 * numerator) / denominator)
+            as u8;
+    }
+    out
+}
@@ -0,0 +1,228 @@
+use std::mem::size_of;
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    BindGroup, BindGroupLayout, BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device,
+    FragmentState, FrontFace, MultisampleState, PipelineLayout, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState,
+    TextureFormat, VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl LineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<LineVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// Lines pushed from anywhere during a frame and drawn at the end of it, e.g. to show normals or bounding
+// boxes. The list is cleared every frame, so anything that should stay up has to be pushed every frame.
+pub struct DebugLines {
+    // Pushed since the last clear, two per line
+    vertices: Vec<LineVertex>,
+    vertex_buffer: Buffer,
+    // How many vertices fit in `vertex_buffer`
+    capacity: usize,
+    // How many the last upload put in the buffer
+    num_vertices: u32,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count or depth test changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+}
+
+impl DebugLines {
+    pub fn new(
+        device: &Device,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        // Room for a box's 12 edges before it has to grow
+        let capacity = 24;
+        let vertex_buffer = create_vertex_buffer(device, capacity);
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Debug Line Shader"),
+            source: ShaderSource::Wgsl(include_str!("debug_lines.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            &layout,
+            &shader,
+            color_format,
+            depth_format,
+            1,
+            CompareFunction::Less,
+        );
+
+        Self {
+            vertices: Vec::new(),
+            vertex_buffer,
+            capacity,
+            num_vertices: 0,
+            pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            sample_count: 1,
+            depth_compare: CompareFunction::Less,
+        }
+    }
+
+    // Adds a line from `start` to `end` to this frame.
+    pub fn push(&mut self, start: [f32; 3], end: [f32; 3], color: [f32; 3]) {
+        self.vertices.push(LineVertex {
+            position: start,
+            color,
+        });
+        self.vertices.push(LineVertex {
+            position: end,
+            color,
+        });
+    }
+
+    // Starts an empty list for the next frame.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    // Writes the lines pushed so far to the GPU for `draw`.
+    pub fn upload(&mut self, device: &Device, queue: &Queue) {
+        if self.vertices.len() > self.capacity {
+            // Leave room so a few more lines next frame don't recreate the buffer every time
+            self.capacity = self.vertices.len() * 2;
+            self.vertex_buffer = create_vertex_buffer(device, self.capacity);
+        }
+
+        if !self.vertices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&self.vertices));
+        }
+        self.num_vertices = self.vertices.len() as u32;
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.rebuild_pipeline(device);
+    }
+
+    // Rebuilds the pipeline to test depth with `depth_compare`, e.g. Greater for reversed-Z.
+    pub fn set_depth_compare(&mut self, device: &Device, depth_compare: CompareFunction) {
+        self.depth_compare = depth_compare;
+        self.rebuild_pipeline(device);
+    }
+
+    fn rebuild_pipeline(&mut self, device: &Device) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            self.sample_count,
+            self.depth_compare,
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_vertices == 0
+    }
+
+    // Draws what the last `upload` sent.
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera_bind_group: &'a BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+fn create_vertex_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Debug Line Vertex Buffer"),
+        size: (capacity * size_of::<LineVertex>()) as BufferAddress,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// `sample_count` has to match the pass the lines are drawn in.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Debug Line Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[LineVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::LineList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        // Hidden behind anything solid, but doesn't hide anything itself
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,141 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, Buffer, BufferUsages, CommandEncoder, Device, LoadOp, Operations, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{
+    camera::{Camera, Projection},
+    post,
+    texture::Texture,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct DepthViewUniform {
+    znear: f32,
+    zfar: f32,
+    orthographic: u32,
+    reversed_z: u32,
+}
+
+// Shows the depth buffer in place of the scene, as distance from the camera in greyscale.
+// For debugging depth issues like z-fighting or a far plane that's too close.
+pub struct DepthView {
+    pub enabled: bool,
+    uniform: DepthViewUniform,
+    buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl DepthView {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        depth_texture: &Texture,
+        camera: &Camera,
+    ) -> Self {
+        let mut uniform = DepthViewUniform {
+            znear: 0.0,
+            zfar: 0.0,
+            orthographic: 0,
+            reversed_z: 0,
+        };
+        set_camera(&mut uniform, camera);
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Depth View Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::depth_entry(0), post::uniform_entry(1)],
+            label: Some("depth_view_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, depth_texture, &buffer);
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "Depth View Pipeline",
+            include_str!("depth_view.wgsl"),
+            &[&bind_group_layout],
+            format,
+            None,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // The depth texture gets recreated when the window resizes.
+    pub fn resize(&mut self, device: &Device, depth_texture: &Texture) {
+        self.bind_group =
+            create_bind_group(device, &self.bind_group_layout, depth_texture, &self.buffer);
+    }
+
+    pub fn update(&mut self, queue: &Queue, camera: &Camera) {
+        set_camera(&mut self.uniform, camera);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    // Overwrites everything in `target`.
+    pub fn render(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Depth View Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn set_camera(uniform: &mut DepthViewUniform, camera: &Camera) {
+    uniform.znear = camera.znear;
+    uniform.zfar = camera.zfar;
+    uniform.orthographic = matches!(camera.projection, Projection::Orthographic { .. }) as u32;
+    uniform.reversed_z = camera.reversed_z as u32;
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    depth_texture: &Texture,
+    buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&post::depth_view(depth_texture)),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("depth_view_bind_group"),
+    })
+}
@@ -0,0 +1,38 @@
+struct DepthViewUniform {
+    znear: f32,
+    zfar: f32,
+    // Non-zero when the depth buffer is already linear
+    orthographic: u32,
+    // Non-zero when the near plane is at depth 1 instead of 0
+    reversed_z: u32,
+}
+
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+
+@group(0) @binding(1)
+var<uniform> depth_view: DepthViewUniform;
+
+// Near things are black and the far plane is white.
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coords = vec2<i32>(clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0)) * (size - 1.0));
+    var depth = textureLoad(t_depth, coords, 0);
+    if (depth_view.reversed_z != 0u) {
+        depth = 1.0 - depth;
+    }
+
+    let near = depth_view.znear;
+    let far = depth_view.zfar;
+
+    var distance = mix(near, far, depth);
+    if (depth_view.orthographic == 0u) {
+        // Undo OPENGL_TO_WGPU_MATRIX, which squashed z from [-1, 1] to [0, 1]
+        let z = depth * 2.0 - 1.0;
+        distance = 2.0 * near * far / (far + near - z * (far - near));
+    }
+
+    let shade = (distance - near) / (far - near);
+    return vec4<f32>(vec3<f32>(shade), 1.0);
+}
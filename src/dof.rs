@@ -0,0 +1,175 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, Buffer, BufferUsages, Device, Queue, RenderPipeline, TextureFormat,
+};
+
+use crate::{camera::Camera, post, texture::Texture};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct DofUniform {
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    znear: f32,
+    zfar: f32,
+    autofocus: u32,
+    focus_point: [f32; 2],
+    reversed_z: u32,
+    _padding: [u32; 3],
+}
+
+// Blurs whatever is in front of or behind the focus distance, like a real camera lens.
+pub struct DepthOfField {
+    pub enabled: bool,
+    uniform: DofUniform,
+    buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+    pub pipeline: RenderPipeline,
+}
+
+impl DepthOfField {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        scene_texture: &Texture,
+        depth_texture: &Texture,
+        camera: &Camera,
+    ) -> Self {
+        let uniform = DofUniform {
+            focus_distance: 5.0,
+            aperture: 0.5,
+            max_radius: 8.0,
+            znear: camera.znear,
+            zfar: camera.zfar,
+            autofocus: 0,
+            focus_point: [0.5, 0.5],
+            reversed_z: camera.reversed_z as u32,
+            _padding: [0; 3],
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("DoF Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                post::texture_entry(0),
+                post::sampler_entry(1),
+                post::depth_entry(2),
+                post::uniform_entry(3),
+            ],
+            label: Some("dof_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            &buffer,
+            scene_texture,
+            depth_texture,
+        );
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "DoF Pipeline",
+            include_str!("dof.wgsl"),
+            &[&bind_group_layout],
+            format,
+            None,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // The scene and depth textures get recreated when the window resizes.
+    pub fn resize(&mut self, device: &Device, scene_texture: &Texture, depth_texture: &Texture) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.buffer,
+            scene_texture,
+            depth_texture,
+        );
+    }
+
+    pub fn focus_distance(&self) -> f32 {
+        self.uniform.focus_distance
+    }
+
+    pub fn set_focus_distance(&mut self, focus_distance: f32) {
+        self.uniform.focus_distance = focus_distance.max(0.0);
+    }
+
+    pub fn aperture(&self) -> f32 {
+        self.uniform.aperture
+    }
+
+    pub fn set_aperture(&mut self, aperture: f32) {
+        self.uniform.aperture = aperture.max(0.0);
+    }
+
+    pub fn autofocus(&self) -> bool {
+        self.uniform.autofocus != 0
+    }
+
+    // Focus on whatever is under `focus_point` instead of a fixed distance.
+    pub fn set_autofocus(&mut self, autofocus: bool) {
+        self.uniform.autofocus = autofocus as u32;
+    }
+
+    // In uv coordinates, (0, 0) is the top left of the screen.
+    pub fn set_focus_point(&mut self, focus_point: [f32; 2]) {
+        self.uniform.focus_point = focus_point;
+    }
+
+    pub fn update(&mut self, queue: &Queue, camera: &Camera) {
+        self.uniform.znear = camera.znear;
+        self.uniform.zfar = camera.zfar;
+        self.uniform.reversed_z = camera.reversed_z as u32;
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    buffer: &Buffer,
+    scene_texture: &Texture,
+    depth_texture: &Texture,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&scene_texture.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&scene_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&post::depth_view(depth_texture)),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("dof_bind_group"),
+    })
+}
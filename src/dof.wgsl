@@ -0,0 +1,84 @@
+struct DofUniform {
+    // Distance from the camera that is perfectly sharp
+    focus_distance: f32,
+    // How quickly things blur away from the focus distance
+    aperture: f32,
+    // Blur radius in pixels for things that are completely out of focus
+    max_radius: f32,
+    znear: f32,
+    zfar: f32,
+    // When non-zero the focus distance is read from the depth buffer at focus_point
+    autofocus: u32,
+    focus_point: vec2<f32>,
+    // Non-zero when the near plane is at depth 1 instead of 0
+    reversed_z: u32,
+}
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+
+@group(0) @binding(1)
+var s_color: sampler;
+
+@group(0) @binding(2)
+var t_depth: texture_depth_2d;
+
+@group(0) @binding(3)
+var<uniform> dof: DofUniform;
+
+let SAMPLE_COUNT: i32 = 32;
+// Rotating each sample by the golden angle spreads them evenly over the disk.
+let GOLDEN_ANGLE: f32 = 2.39996323;
+
+// The depth buffer is non-linear. This turns it back into a distance from the camera.
+fn linear_depth(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coords = vec2<i32>(clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0)) * (size - 1.0));
+    var depth = textureLoad(t_depth, coords, 0);
+    if (dof.reversed_z != 0u) {
+        depth = 1.0 - depth;
+    }
+
+    // Undo OPENGL_TO_WGPU_MATRIX, which squashed z from [-1, 1] to [0, 1]
+    let z = depth * 2.0 - 1.0;
+    return 2.0 * dof.znear * dof.zfar / (dof.zfar + dof.znear - z * (dof.zfar - dof.znear));
+}
+
+// Circle of confusion: how big a blur disk (in pixels) a point at `depth` turns into.
+fn circle_of_confusion(depth: f32, focus_distance: f32) -> f32 {
+    let coc = abs(depth - focus_distance) * dof.aperture / max(depth, 0.0001);
+    return clamp(coc, 0.0, 1.0) * dof.max_radius;
+}
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    var focus_distance = dof.focus_distance;
+    if (dof.autofocus != 0u) {
+        focus_distance = linear_depth(dof.focus_point);
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_color));
+    let radius = circle_of_confusion(linear_depth(uv), focus_distance);
+
+    var color = textureSampleLevel(t_color, s_color, uv, 0.0);
+    var total_weight = 1.0;
+
+    // Disk blur. Skipped entirely for pixels that are in focus.
+    if (radius >= 0.5) {
+        for (var i = 0; i < SAMPLE_COUNT; i = i + 1) {
+            let r = sqrt((f32(i) + 0.5) / f32(SAMPLE_COUNT)) * radius;
+            let theta = f32(i) * GOLDEN_ANGLE;
+            let sample_uv = uv + vec2<f32>(cos(theta), sin(theta)) * r * texel;
+
+            // A sample only contributes if it is blurry enough to reach this pixel.
+            // Stops sharp objects from bleeding into the blurred background.
+            let sample_radius = circle_of_confusion(linear_depth(sample_uv), focus_distance);
+            let weight = smoothstep(r - 1.0, r + 1.0, sample_radius);
+
+            color = color + textureSampleLevel(t_color, s_color, sample_uv, 0.0) * weight;
+            total_weight = total_weight + weight;
+        }
+    }
+
+    return color / total_weight;
+}
@@ -0,0 +1,352 @@
+use std::{f32::consts::PI, num::NonZeroU32, sync::mpsc};
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::Vector3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingResource, BindingType, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device,
+    Extent3d, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp, Maintain, MapMode,
+    Operations, Origin3d, Queue, RenderPassColorAttachment, RenderPassDescriptor,
+    SamplerBindingType, ShaderStages, TextureAspect, TextureFormat, TextureSampleType,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::{post, texture::Texture};
+
+// Cubemap faces never get bigger than this, however big the equirectangular image is
+const MAX_FACE_SIZE: u32 = 1024;
+// Keeps the environment's full range and, unlike Rgba32Float, can be filtered everywhere
+pub const CUBEMAP_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+// The grid irradiance_sh resamples the environment into. Irradiance only changes slowly across directions,
+// so a coarse one loses nothing.
+const SH_SAMPLES_WIDE: u32 = 64;
+const SH_SAMPLES_HIGH: u32 = 32;
+// How much of each coefficient's band a cosine-weighted hemisphere lets through, divided by pi:
+// all of band 0, two thirds of band 1 and a quarter of band 2
+const SH_BAND_SCALES: [f32; 9] = [
+    1.0,
+    2.0 / 3.0,
+    2.0 / 3.0,
+    2.0 / 3.0,
+    0.25,
+    0.25,
+    0.25,
+    0.25,
+    0.25,
+];
+
+// Matches `Irradiance` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct IrradianceUniform {
+    // From irradiance_sh. The fourth component is padding.
+    coefficients: [[f32; 4]; 9],
+    // 0 leaves the lights' flat ambient term in place of the environment's
+    enabled: u32,
+    _padding: [u32; 3],
+}
+
+impl IrradianceUniform {
+    // Without an environment
+    pub fn flat() -> Self {
+        Zeroable::zeroed()
+    }
+
+    pub fn from_coefficients(coefficients: [[f32; 3]; 9]) -> Self {
+        Self {
+            coefficients: coefficients.map(|[r, g, b]| [r, g, b, 0.0]),
+            enabled: 1,
+            _padding: [0; 3],
+        }
+    }
+}
+
+// Matches `Face` in environment.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FaceUniform {
+    index: u32,
+    _padding: [u32; 3],
+}
+
+// Turns an equirectangular image, longitude across and latitude down like the ones Texture::from_hdr loads,
+// into a cubemap. That's what skyboxes and image-based lighting sample.
+// Each face covers a quarter of the image's width, so it gets half its height in texels.
+pub fn equirect_to_cubemap(device: &Device, queue: &Queue, equirect: &Texture) -> Texture {
+    let face_size = (equirect.size().1 / 2).clamp(1, MAX_FACE_SIZE);
+    let cubemap = Texture::create_cubemap(device, face_size, CUBEMAP_FORMAT, "environment_cubemap");
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: TextureViewDimension::D2,
+                    sample_type: TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+            post::uniform_entry(1),
+        ],
+        label: Some("equirect_bind_group_layout"),
+    });
+
+    let pipeline = post::create_fullscreen_pipeline(
+        device,
+        "Equirect To Cubemap Pipeline",
+        include_str!("environment.wgsl"),
+        &[&bind_group_layout],
+        CUBEMAP_FORMAT,
+        None,
+    );
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Equirect To Cubemap Encoder"),
+    });
+
+    for index in 0..6 {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Cubemap Face Buffer"),
+            contents: bytemuck::bytes_of(&FaceUniform {
+                index,
+                _padding: [0; 3],
+            }),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&equirect.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("equirect_bind_group"),
+        });
+
+        // Just this face, as a plain 2D texture to draw into
+        let face_view = cubemap.texture.create_view(&TextureViewDescriptor {
+            label: Some("cubemap_face_view"),
+            dimension: Some(TextureViewDimension::D2),
+            base_array_layer: index,
+            array_layer_count: NonZeroU32::new(1),
+            ..Default::default()
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Equirect To Cubemap Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &face_view,
+                resolve_target: None,
+                ops: Operations {
+                    // Every pixel gets overwritten anyway
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    queue.submit(Some(encoder.finish()));
+
+    cubemap
+}
+
+// Bands 0 to 2 of the real spherical harmonics at `direction`, in the order shader.wgsl evaluates them.
+fn sh_basis(direction: Vector3<f32>) -> [f32; 9] {
+    let Vector3 { x, y, z } = direction;
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+// Boils the light coming from every direction of an environment cubemap down to 9 spherical harmonics
+// coefficients per channel. They're already convolved with a cosine, so evaluating them at a normal gives
+// the diffuse light a white surface facing that way reflects.
+// The cubemap is resampled into a small grid on the GPU and projected on the CPU.
+pub fn irradiance_sh(
+    device: &Device,
+    queue: &Queue,
+    cubemap: &Texture,
+) -> anyhow::Result<[[f32; 3]; 9]> {
+    let target = Texture::create_render_target(
+        device,
+        SH_SAMPLES_WIDE,
+        SH_SAMPLES_HIGH,
+        TextureFormat::Rgba32Float,
+        "sh_projection_target",
+    );
+
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: TextureViewDimension::Cube,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("sh_projection_bind_group_layout"),
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&cubemap.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&cubemap.sampler),
+            },
+        ],
+        label: Some("sh_projection_bind_group"),
+    });
+
+    let pipeline = post::create_fullscreen_pipeline(
+        device,
+        "SH Projection Pipeline",
+        include_str!("sh_projection.wgsl"),
+        &[&bind_group_layout],
+        TextureFormat::Rgba32Float,
+        None,
+    );
+
+    // 4 channels of 4 bytes each. 64 texels wide already lines up with COPY_BYTES_PER_ROW_ALIGNMENT.
+    let bytes_per_row = SH_SAMPLES_WIDE * 16;
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("SH Projection Buffer"),
+        size: (bytes_per_row * SH_SAMPLES_HIGH) as u64,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("SH Projection Encoder"),
+    });
+
+    {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("SH Projection Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &target.view,
+                resolve_target: None,
+                ops: Operations {
+                    // Every pixel gets overwritten anyway
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    encoder.copy_texture_to_buffer(
+        ImageCopyTexture {
+            aspect: TextureAspect::All,
+            texture: &target.texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+        },
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(bytes_per_row),
+                rows_per_image: NonZeroU32::new(SH_SAMPLES_HIGH),
+            },
+        },
+        Extent3d {
+            width: SH_SAMPLES_WIDE,
+            height: SH_SAMPLES_HIGH,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(MapMode::Read, move |result| {
+        sender.send(result).ok();
+    });
+    device.poll(Maintain::Wait);
+    receiver.recv()??;
+
+    let mut coefficients = [[0.0; 3]; 9];
+    {
+        let view = slice.get_mapped_range();
+        let texels: &[[f32; 4]] = bytemuck::cast_slice(&view);
+
+        let texel_width = 2.0 * PI / SH_SAMPLES_WIDE as f32;
+        let texel_height = PI / SH_SAMPLES_HIGH as f32;
+
+        for (i, texel) in texels.iter().enumerate() {
+            let x = i as u32 % SH_SAMPLES_WIDE;
+            let y = i as u32 / SH_SAMPLES_WIDE;
+
+            // Same directions as sh_projection.wgsl
+            let theta = (y as f32 + 0.5) * texel_height;
+            let phi = (x as f32 + 0.5) * texel_width - PI;
+            let direction = Vector3::new(
+                phi.cos() * theta.sin(),
+                theta.cos(),
+                phi.sin() * theta.sin(),
+            );
+            // Rows near the poles cover less of the sphere
+            let solid_angle = texel_width * texel_height * theta.sin();
+
+            for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                for channel in 0..3 {
+                    coefficient[channel] += texel[channel] * basis * solid_angle;
+                }
+            }
+        }
+    }
+    buffer.unmap();
+
+    for (coefficient, scale) in coefficients.iter_mut().zip(SH_BAND_SCALES) {
+        for channel in coefficient.iter_mut() {
+            *channel *= scale;
+        }
+    }
+
+    Ok(coefficients)
+}
@@ -0,0 +1,62 @@
+// Fills one cubemap face from an equirectangular image, for environment.rs. Drawn once per face.
+
+struct Face {
+    // 0 to 5 for +X, -X, +Y, -Y, +Z, -Z
+    index: u32,
+}
+
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> face: Face;
+
+let PI: f32 = 3.14159265359;
+
+// From the cube's center through `uv` on the face, laid out the way wgpu samples cubemaps
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    let a = uv.x * 2.0 - 1.0;
+    let b = uv.y * 2.0 - 1.0;
+
+    var direction: vec3<f32>;
+    switch (index) {
+        case 0u: {
+            direction = vec3<f32>(1.0, -b, -a);
+        }
+        case 1u: {
+            direction = vec3<f32>(-1.0, -b, a);
+        }
+        case 2u: {
+            direction = vec3<f32>(a, 1.0, b);
+        }
+        case 3u: {
+            direction = vec3<f32>(a, -1.0, -b);
+        }
+        case 4u: {
+            direction = vec3<f32>(a, -b, 1.0);
+        }
+        default: {
+            direction = vec3<f32>(-a, -b, -1.0);
+        }
+    }
+    return normalize(direction);
+}
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let direction = face_direction(face.index, uv);
+
+    // Longitude across the image, latitude down it with straight up at the top
+    let equirect_uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+
+    // The source is 32 bit float, which can't be filtered, so the nearest texel is read
+    let size = textureDimensions(t_equirect);
+    let coords = clamp(
+        vec2<i32>(equirect_uv * vec2<f32>(size)),
+        vec2<i32>(0, 0),
+        size - vec2<i32>(1, 1),
+    );
+    return vec4<f32>(textureLoad(t_equirect, coords, 0).rgb, 1.0);
+}
@@ -0,0 +1,182 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::SquareMatrix;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, BlendState, Buffer, BufferUsages, CommandEncoder, Device, LoadOp, Operations,
+    Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, TextureFormat,
+    TextureView,
+};
+
+use crate::{camera::Camera, post, texture::Texture};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FogUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    color: [f32; 4],
+    density: f32,
+    height: f32,
+    falloff: f32,
+    _padding: f32,
+}
+
+// Fog that gets thicker with distance and settles near the ground, thinning out with altitude.
+// Works out where each pixel is in the world from the depth buffer, so it needs no extra render targets.
+pub struct HeightFog {
+    pub enabled: bool,
+    uniform: FogUniform,
+    buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl HeightFog {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        depth_texture: &Texture,
+        camera: &Camera,
+    ) -> Self {
+        let mut uniform = FogUniform {
+            inv_view_proj: [[0.0; 4]; 4],
+            eye: [0.0; 4],
+            color: [0.6, 0.65, 0.7, 1.0],
+            density: 0.05,
+            height: 0.0,
+            falloff: 0.5,
+            _padding: 0.0,
+        };
+        set_camera(&mut uniform, camera);
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Fog Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::depth_entry(0), post::uniform_entry(1)],
+            label: Some("fog_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, depth_texture, &buffer);
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "Fog Pipeline",
+            include_str!("fog.wgsl"),
+            &[&bind_group_layout],
+            format,
+            Some(BlendState::ALPHA_BLENDING),
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // The depth texture gets recreated when the window resizes.
+    pub fn resize(&mut self, device: &Device, depth_texture: &Texture) {
+        self.bind_group =
+            create_bind_group(device, &self.bind_group_layout, depth_texture, &self.buffer);
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        let [r, g, b, _] = self.uniform.color;
+        [r, g, b]
+    }
+
+    // In linear RGB.
+    pub fn set_color(&mut self, color: [f32; 3]) {
+        let [r, g, b] = color;
+        self.uniform.color = [r, g, b, 1.0];
+    }
+
+    pub fn density(&self) -> f32 {
+        self.uniform.density
+    }
+
+    pub fn set_density(&mut self, density: f32) {
+        self.uniform.density = density.max(0.0);
+    }
+
+    pub fn height(&self) -> f32 {
+        self.uniform.height
+    }
+
+    pub fn set_height(&mut self, height: f32) {
+        self.uniform.height = height;
+    }
+
+    pub fn falloff(&self) -> f32 {
+        self.uniform.falloff
+    }
+
+    pub fn set_falloff(&mut self, falloff: f32) {
+        self.uniform.falloff = falloff.max(0.0);
+    }
+
+    pub fn update(&mut self, queue: &Queue, camera: &Camera) {
+        set_camera(&mut self.uniform, camera);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    // Blends the fog over `target`. Only reads depth, so `target` can be the surface or an offscreen texture.
+    pub fn render(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Fog Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn set_camera(uniform: &mut FogUniform, camera: &Camera) {
+    uniform.inv_view_proj = camera
+        .build_view_projection_matrix()
+        .invert()
+        .unwrap()
+        .into();
+    uniform.eye = camera.eye.to_homogeneous().into();
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    depth_texture: &Texture,
+    buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&post::depth_view(depth_texture)),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("fog_bind_group"),
+    })
+}
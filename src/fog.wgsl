@@ -0,0 +1,59 @@
+struct FogUniform {
+    inv_view_proj: mat4x4<f32>,
+    // Only xyz is used
+    eye: vec4<f32>,
+    // Only rgb is used
+    color: vec4<f32>,
+    // Fog per world unit at `height`
+    density: f32,
+    // Where the fog is at its thickest. It's just as thick anywhere below.
+    height: f32,
+    // How quickly the fog thins out going up. Higher values give a thinner layer.
+    falloff: f32,
+}
+
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+
+// Turns a point on the screen back into a position in the world.
+fn world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = fog.inv_view_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// Density along the ray from the eye to `position`, added up. Density is
+// `fog.density * exp(-fog.falloff * (y - fog.height))`, which integrates to a closed form.
+fn optical_depth(position: vec3<f32>) -> f32 {
+    let ray = position - fog.eye.xyz;
+    let distance = length(ray);
+
+    // Below the fog height the density stays at its thickest. Clamping the heights is close enough for rays that cross it.
+    let start_height = max(fog.eye.y - fog.height, 0.0);
+    let end_height = max(position.y - fog.height, 0.0);
+    let climb = fog.falloff * (end_height - start_height);
+
+    // Average of exp(-falloff * height) along the ray. Flat rays would divide by 0, but then it's just the start value.
+    var average = exp(-fog.falloff * start_height);
+    if (abs(climb) > 0.0001) {
+        average = average * (1.0 - exp(-climb)) / climb;
+    }
+
+    return fog.density * distance * average;
+}
+
+// Blended over the scene with the fog amount as alpha.
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coords = vec2<i32>(clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0)) * (size - 1.0));
+    // Empty pixels sit at the far plane, so the background fades into the fog too
+    let depth = textureLoad(t_depth, coords, 0);
+
+    let amount = 1.0 - exp(-optical_depth(world_position(uv, depth)));
+
+    return vec4<f32>(fog.color.rgb, clamp(amount, 0.0, 1.0));
+}
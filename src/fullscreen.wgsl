@@ -0,0 +1,18 @@
+// Shared vertex stage for post-processing passes. Prepended to the effect shader by `post::create_fullscreen_pipeline`.
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // (0, 0) is the top left of the screen
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle big enough to cover the whole screen. Draw it with 3 vertices and no vertex buffer.
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: FullscreenOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
@@ -0,0 +1,100 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, CommandEncoder, Device, LoadOp, Operations, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{post, texture::Texture};
+
+// Fast approximate anti-aliasing. Finds edges by their change in brightness and blends across them.
+// Cheaper than MSAA and needs no multisampled textures, but softens the whole image a little.
+// Everything before it draws into `target` instead of the screen, and it copies the result across.
+pub struct Fxaa {
+    pub enabled: bool,
+    format: TextureFormat,
+    texture: Texture,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl Fxaa {
+    pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+        let texture = Texture::create_render_target(device, width, height, format, "fxaa_texture");
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::texture_entry(0), post::sampler_entry(1)],
+            label: Some("fxaa_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, &texture);
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "FXAA Pipeline",
+            include_str!("fxaa.wgsl"),
+            &[&bind_group_layout],
+            format,
+            None,
+        );
+
+        Self {
+            enabled: false,
+            format,
+            texture,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.texture =
+            Texture::create_render_target(device, width, height, self.format, "fxaa_texture");
+        self.bind_group = create_bind_group(device, &self.bind_group_layout, &self.texture);
+    }
+
+    // Where the frame needs to be drawn for `render` to smooth it.
+    pub fn target(&self) -> &TextureView {
+        &self.texture.view
+    }
+
+    // Writes the anti-aliased target into `view`, covering everything in it.
+    pub fn render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("FXAA Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    // Every pixel gets overwritten anyway
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(device: &Device, layout: &BindGroupLayout, texture: &Texture) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&texture.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                // Linear, so the shader can blend between neighbours by sampling in between them
+                resource: BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("fxaa_bind_group"),
+    })
+}
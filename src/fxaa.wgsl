@@ -0,0 +1,160 @@
+// FXAA: looks for edges by the change in brightness around each pixel, walks along them to find
+// where they end, and samples a little across the edge depending on how far along it the pixel is.
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+// Brightness differences smaller than this aren't edges. The second is relative to the brightest neighbour.
+let EDGE_THRESHOLD_MIN: f32 = 0.0312;
+let EDGE_THRESHOLD_MAX: f32 = 0.125;
+// How much single pixel detail gets smoothed, 0 to 1
+let SUBPIXEL_QUALITY: f32 = 0.75;
+// Steps taken along an edge in each direction looking for its end
+let ITERATIONS: i32 = 12;
+
+// Perceived brightness. The square root roughly undoes the sRGB decoding done when sampling.
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+fn sample_luma(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_scene, s_scene, uv, 0.0).rgb);
+}
+
+// Later steps go further, so long edges are found without too many samples
+fn step_size(i: i32) -> f32 {
+    if (i < 4) {
+        return 1.0;
+    }
+    if (i == 4) {
+        return 1.5;
+    }
+    if (i < 9) {
+        return 2.0;
+    }
+    if (i == 9) {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_scene));
+
+    let center = textureSampleLevel(t_scene, s_scene, uv, 0.0);
+    let luma_center = luma(center.rgb);
+    // uv's y goes down the screen
+    let luma_up = sample_luma(uv + vec2<f32>(0.0, -texel.y));
+    let luma_down = sample_luma(uv + vec2<f32>(0.0, texel.y));
+    let luma_left = sample_luma(uv + vec2<f32>(-texel.x, 0.0));
+    let luma_right = sample_luma(uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let range = luma_max - luma_min;
+
+    // Flat areas are left alone
+    if (range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        return center;
+    }
+
+    let luma_up_left = sample_luma(uv - texel);
+    let luma_down_right = sample_luma(uv + texel);
+    let luma_up_right = sample_luma(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_down_left = sample_luma(uv + vec2<f32>(-texel.x, texel.y));
+
+    let luma_up_down = luma_up + luma_down;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_up_left + luma_down_left;
+    let luma_right_corners = luma_up_right + luma_down_right;
+    let luma_up_corners = luma_up_left + luma_up_right;
+    let luma_down_corners = luma_down_left + luma_down_right;
+
+    // Whichever way the brightness changes the most is across the edge
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_up_down) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // The neighbours on either side of the edge, before and after the pixel
+    let luma_before = select(luma_left, luma_up, is_horizontal);
+    let luma_after = select(luma_right, luma_down, is_horizontal);
+    let gradient_before = luma_before - luma_center;
+    let gradient_after = luma_after - luma_center;
+    let before_steepest = abs(gradient_before) >= abs(gradient_after);
+    let gradient_scaled = 0.25 * max(abs(gradient_before), abs(gradient_after));
+
+    // Towards the side of the edge that differs the most
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average: f32;
+    if (before_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_before + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_after + luma_center);
+    }
+
+    // Half a pixel over, right on the edge
+    var edge_uv = uv;
+    if (is_horizontal) {
+        edge_uv.y = edge_uv.y + step_length * 0.5;
+    } else {
+        edge_uv.x = edge_uv.x + step_length * 0.5;
+    }
+
+    // Walk both ways along the edge until the brightness stops matching it
+    let along = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+    var uv_back = edge_uv - along;
+    var uv_forward = edge_uv + along;
+    var luma_end_back = sample_luma(uv_back) - luma_local_average;
+    var luma_end_forward = sample_luma(uv_forward) - luma_local_average;
+    var reached_back = abs(luma_end_back) >= gradient_scaled;
+    var reached_forward = abs(luma_end_forward) >= gradient_scaled;
+
+    for (var i = 0; i < ITERATIONS && !(reached_back && reached_forward); i = i + 1) {
+        if (!reached_back) {
+            uv_back = uv_back - along * step_size(i);
+            luma_end_back = sample_luma(uv_back) - luma_local_average;
+            reached_back = abs(luma_end_back) >= gradient_scaled;
+        }
+        if (!reached_forward) {
+            uv_forward = uv_forward + along * step_size(i);
+            luma_end_forward = sample_luma(uv_forward) - luma_local_average;
+            reached_forward = abs(luma_end_forward) >= gradient_scaled;
+        }
+    }
+
+    let distance_back = select(uv.y - uv_back.y, uv.x - uv_back.x, is_horizontal);
+    let distance_forward = select(uv_forward.y - uv.y, uv_forward.x - uv.x, is_horizontal);
+    let back_closer = distance_back < distance_forward;
+    let distance = min(distance_back, distance_forward);
+    let edge_length = distance_back + distance_forward;
+
+    // Pixels near the end of an edge get moved across it the most
+    let pixel_offset = 0.5 - distance / edge_length;
+    // Unless the end found is on the wrong side, in which case it's not this edge that ended
+    let center_darker = luma_center < luma_local_average;
+    let end_darker = select(luma_end_forward < 0.0, luma_end_back < 0.0, back_closer);
+    var offset = select(0.0, pixel_offset, end_darker != center_darker);
+
+    // Single pixel specks, like the tip of a thin line, get smoothed against all their neighbours
+    let luma_average = (2.0 * (luma_up_down + luma_left_right) + luma_left_corners + luma_right_corners) / 12.0;
+    let subpixel = clamp(abs(luma_average - luma_center) / range, 0.0, 1.0);
+    let subpixel_smooth = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+    offset = max(offset, subpixel_smooth * subpixel_smooth * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if (is_horizontal) {
+        final_uv.y = final_uv.y + offset * step_length;
+    } else {
+        final_uv.x = final_uv.x + offset * step_length;
+    }
+
+    return textureSampleLevel(t_scene, s_scene, final_uv, 0.0);
+}
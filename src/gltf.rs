@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+use serde::Deserialize;
+
+use crate::model::ModelVertex;
+
+// Just the parts of a glTF 2.0 file needed to pull out meshes and their base color textures.
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Document {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    pub textures: Vec<GltfTexture>,
+    pub images: Vec<GltfImage>,
+    pub accessors: Vec<Accessor>,
+    pub buffer_views: Vec<BufferView>,
+    pub buffers: Vec<GltfBuffer>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Primitive {
+    // Attribute name (POSITION, NORMAL, TEXCOORD_0, ...) to accessor index
+    pub attributes: HashMap<String, usize>,
+    pub indices: Option<usize>,
+    pub material: Option<usize>,
+    // 4 = triangles, which is also the default
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    pub pbr_metallic_roughness: Option<PbrMetallicRoughness>,
+    pub normal_texture: Option<TextureInfo>,
+    pub emissive_texture: Option<TextureInfo>,
+    pub emissive_factor: Option<[f32; 3]>,
+    // OPAQUE, MASK or BLEND. Missing means OPAQUE.
+    pub alpha_mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PbrMetallicRoughness {
+    pub base_color_texture: Option<TextureInfo>,
+    pub base_color_factor: Option<[f32; 4]>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextureInfo {
+    pub index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GltfTexture {
+    pub source: Option<usize>,
+}
+
+// Either a file next to the .gltf, a data URI, or a slice of a buffer
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GltfImage {
+    pub uri: Option<String>,
+    pub buffer_view: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Accessor {
+    pub buffer_view: Option<usize>,
+    #[serde(default)]
+    pub byte_offset: usize,
+    pub component_type: u32,
+    pub count: usize,
+    // SCALAR, VEC2, VEC3, ...
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BufferView {
+    pub buffer: usize,
+    #[serde(default)]
+    pub byte_offset: usize,
+    pub byte_length: usize,
+    // Set when vertex attributes are interleaved
+    pub byte_stride: Option<usize>,
+}
+
+// A .glb's own binary chunk when there's no uri
+#[derive(Debug, Deserialize)]
+pub struct GltfBuffer {
+    pub uri: Option<String>,
+}
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+const COMPONENT_UNSIGNED_BYTE: u32 = 5121;
+const COMPONENT_UNSIGNED_SHORT: u32 = 5123;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const COMPONENT_FLOAT: u32 = 5126;
+
+// Reads a .gltf or .glb. Also returns the binary chunk of a .glb, which buffers without a uri refer to.
+pub fn parse(bytes: &[u8]) -> Result<(Document, Option<Vec<u8>>)> {
+    if !bytes.starts_with(GLB_MAGIC) {
+        return Ok((serde_json::from_slice(bytes)?, None));
+    }
+
+    let read_u32 = |offset: usize| -> Result<u32> {
+        let word = bytes
+            .get(offset..offset + 4)
+            .context("Unexpected end of .glb")?;
+        Ok(u32::from_le_bytes(word.try_into()?))
+    };
+
+    // 12 byte header, then chunks of (length, type, data)
+    let mut document = None;
+    let mut bin = None;
+    let mut offset = 12;
+
+    while offset < bytes.len() {
+        let length = read_u32(offset)? as usize;
+        let kind = read_u32(offset + 4)?;
+        let data = bytes
+            .get(offset + 8..offset + 8 + length)
+            .context("Unexpected end of .glb")?;
+
+        match kind {
+            GLB_CHUNK_JSON => document = Some(serde_json::from_slice(data)?),
+            GLB_CHUNK_BIN => bin = Some(data.to_vec()),
+            _ => {}
+        }
+
+        offset += 8 + length;
+    }
+
+    Ok((document.context(".glb has no JSON chunk")?, bin))
+}
+
+// Returns the bytes of a `data:...;base64,` uri, or None if it points at a file.
+pub fn decode_data_uri(uri: &str) -> Option<Result<Vec<u8>>> {
+    let data = uri.strip_prefix("data:")?;
+    let (_, base64) = data.split_once(";base64,")?;
+    Some(decode_base64(base64))
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let value = |c: u8| -> Result<u32> {
+        Ok(match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("Invalid base64 character {:?}", c as char),
+        } as u32)
+    };
+
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+
+    // Every 4 characters hold 3 bytes. A shorter last group holds 1 or 2.
+    for group in text.chunks(4) {
+        let mut bits = 0;
+        for (i, &c) in group.iter().enumerate() {
+            bits |= value(c)? << (18 - 6 * i);
+        }
+
+        let count = group.len() * 3 / 4;
+        bytes.extend_from_slice(&bits.to_be_bytes()[1..1 + count]);
+    }
+
+    Ok(bytes)
+}
+
+impl Document {
+    // The vertices and indices of primitive `i` of the mesh called `name`. Tangents are left for compute_tangents.
+    pub fn read_primitive(
+        &self,
+        buffers: &[Vec<u8>],
+        primitive: &Primitive,
+        name: &str,
+        i: usize,
+    ) -> Result<(Vec<ModelVertex>, Vec<u32>)> {
+        if primitive.mode.unwrap_or(4) != 4 {
+            bail!("Primitive {} of {} isn't a triangle list", i, name);
+        }
+
+        let attribute = |attribute: &str, components: usize| {
+            let accessor = primitive
+                .attributes
+                .get(attribute)
+                .with_context(|| format!("Primitive {} of {} has no {}", i, name, attribute))?;
+            self.read_floats(buffers, *accessor, components)
+        };
+
+        let positions = attribute("POSITION", 3)?;
+        let normals = attribute("NORMAL", 3)?;
+        let tex_coords = attribute("TEXCOORD_0", 2)?;
+        // Lightmaps usually get a second set, so they can be laid out without any overlap
+        let tex_coords1 = if primitive.attributes.contains_key("TEXCOORD_1") {
+            attribute("TEXCOORD_1", 2)?
+        } else {
+            tex_coords.clone()
+        };
+
+        let vertices = (0..positions.len() / 3)
+            .map(|v| ModelVertex {
+                position: [positions[v * 3], positions[v * 3 + 1], positions[v * 3 + 2]],
+                tex_coords: [tex_coords[v * 2], tex_coords[v * 2 + 1]],
+                normal: [normals[v * 3], normals[v * 3 + 1], normals[v * 3 + 2]],
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+                color: [1.0; 3],
+                tex_coords1: [tex_coords1[v * 2], tex_coords1[v * 2 + 1]],
+            })
+            .collect::<Vec<_>>();
+
+        let indices = match primitive.indices {
+            Some(accessor) => self.read_indices(buffers, accessor)?,
+            None => (0..vertices.len() as u32).collect(),
+        };
+
+        Ok((vertices, indices))
+    }
+
+    // The image a texture refers to, if there is one.
+    pub fn texture_image(&self, info: &TextureInfo) -> Option<&GltfImage> {
+        let source = self.textures.get(info.index)?.source?;
+        self.images.get(source)
+    }
+
+    // The bytes a buffer view covers. `buffers` lines up with `self.buffers`.
+    pub fn view<'a>(&self, buffers: &'a [Vec<u8>], index: usize) -> Result<&'a [u8]> {
+        let view = self
+            .buffer_views
+            .get(index)
+            .context("Missing buffer view")?;
+        buffers
+            .get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset + view.byte_length))
+            .context("Buffer view is out of bounds")
+    }
+
+    // Reads a float accessor with `components` floats per element, e.g. 3 for VEC3.
+    pub fn read_floats(
+        &self,
+        buffers: &[Vec<u8>],
+        index: usize,
+        components: usize,
+    ) -> Result<Vec<f32>> {
+        let accessor = self.accessors.get(index).context("Missing accessor")?;
+
+        if accessor.component_type != COMPONENT_FLOAT {
+            bail!(
+                "Expected a float accessor, got component type {}",
+                accessor.component_type
+            );
+        }
+
+        self.read(buffers, accessor, components, 4, |bytes| {
+            f32::from_le_bytes(bytes.try_into().unwrap())
+        })
+    }
+
+    // Reads an index accessor, widening it to u32.
+    pub fn read_indices(&self, buffers: &[Vec<u8>], index: usize) -> Result<Vec<u32>> {
+        let accessor = self.accessors.get(index).context("Missing accessor")?;
+
+        match accessor.component_type {
+            COMPONENT_UNSIGNED_BYTE => self.read(buffers, accessor, 1, 1, |bytes| bytes[0] as u32),
+            COMPONENT_UNSIGNED_SHORT => self.read(buffers, accessor, 1, 2, |bytes| {
+                u16::from_le_bytes(bytes.try_into().unwrap()) as u32
+            }),
+            COMPONENT_UNSIGNED_INT => self.read(buffers, accessor, 1, 4, |bytes| {
+                u32::from_le_bytes(bytes.try_into().unwrap())
+            }),
+            other => bail!("Unsupported index component type {}", other),
+        }
+    }
+
+    fn read<T>(
+        &self,
+        buffers: &[Vec<u8>],
+        accessor: &Accessor,
+        components: usize,
+        component_size: usize,
+        convert: impl Fn(&[u8]) -> T,
+    ) -> Result<Vec<T>> {
+        let expected = match components {
+            1 => "SCALAR",
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "VEC4",
+        };
+        if accessor.kind != expected {
+            bail!("Expected a {} accessor, got {}", expected, accessor.kind);
+        }
+
+        let view_index = accessor
+            .buffer_view
+            .context("Sparse accessors aren't supported")?;
+        let view = self.view(buffers, view_index)?;
+
+        let element_size = components * component_size;
+        let stride = self.buffer_views[view_index]
+            .byte_stride
+            .unwrap_or(element_size);
+
+        let mut values = Vec::with_capacity(accessor.count * components);
+        for i in 0..accessor.count {
+            let start = accessor.byte_offset + i * stride;
+            let element = view
+                .get(start..start + element_size)
+                .context("Accessor is out of bounds")?;
+            values.extend(element.chunks_exact(component_size).map(&convert));
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each buffer of a parsed file, from its data URI or the .glb's binary chunk.
+    fn buffers(document: &Document, bin: Option<Vec<u8>>) -> Vec<Vec<u8>> {
+        document
+            .buffers
+            .iter()
+            .map(|buffer| match &buffer.uri {
+                Some(uri) => decode_data_uri(uri).unwrap().unwrap(),
+                None => bin.clone().unwrap(),
+            })
+            .collect()
+    }
+
+    // Reads every primitive of the first mesh in res/`file_name`.
+    fn read_fixture(file_name: &str) -> Result<Vec<(Vec<ModelVertex>, Vec<u32>)>> {
+        let (document, bin) = parse(&std::fs::read(format!("res/{}", file_name))?)?;
+        let buffers = buffers(&document, bin);
+        let mesh = &document.meshes[0];
+
+        mesh.primitives
+            .iter()
+            .enumerate()
+            .map(|(i, primitive)| document.read_primitive(&buffers, primitive, "quad", i))
+            .collect()
+    }
+
+    fn glb(chunks: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut bytes = GLB_MAGIC.to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        // Total length, unused since parse goes by the slice's
+        bytes.extend(0u32.to_le_bytes());
+        for (kind, data) in chunks {
+            bytes.extend((data.len() as u32).to_le_bytes());
+            bytes.extend(kind.to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    #[test]
+    fn base64_groups() {
+        assert_eq!(decode_base64("TWFu").unwrap(), b"Man");
+        assert_eq!(decode_base64("TWE=").unwrap(), b"Ma");
+        assert_eq!(decode_base64("TQ==").unwrap(), b"M");
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+        assert!(decode_base64("TW!u").is_err());
+    }
+
+    #[test]
+    fn data_uris_are_decoded_and_files_left_alone() {
+        let data = decode_data_uri("data:application/octet-stream;base64,TWFu");
+        assert_eq!(data.unwrap().unwrap(), b"Man");
+        assert!(decode_data_uri("quad.bin").is_none());
+    }
+
+    #[test]
+    fn glb_chunks() {
+        let json = br#"{"buffers": [{"byteLength": 4}]}"#;
+        let bytes = glb(&[(GLB_CHUNK_JSON, json), (GLB_CHUNK_BIN, &[1, 2, 3, 4])]);
+
+        let (document, bin) = parse(&bytes).unwrap();
+        assert_eq!(document.buffers.len(), 1);
+        assert!(document.buffers[0].uri.is_none());
+        assert_eq!(bin.unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn broken_glbs_are_errors() {
+        let json = br#"{"buffers": []}"#;
+        let bytes = glb(&[(GLB_CHUNK_JSON, json)]);
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+
+        let bytes = glb(&[(GLB_CHUNK_BIN, &[1, 2, 3, 4])]);
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn quad_fixtures() {
+        for file_name in ["quad.gltf", "quad.glb"] {
+            let primitives = read_fixture(file_name).unwrap();
+            let (vertices, indices) = &primitives[0];
+
+            assert_eq!(vertices.len(), 4, "{}", file_name);
+            assert_eq!(indices, &[0, 1, 2, 0, 2, 3], "{}", file_name);
+            assert_eq!(vertices[0].normal, [0.0, 0.0, 1.0], "{}", file_name);
+        }
+    }
+
+    #[test]
+    fn missing_normals_are_an_error() {
+        let error = read_fixture("bad.gltf").unwrap_err();
+        assert!(
+            error.to_string().contains("has no NORMAL"),
+            "unexpected error: {}",
+            error
+        );
+    }
+}
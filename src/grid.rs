@@ -0,0 +1,233 @@
+use std::mem::size_of;
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BlendState, Buffer, BufferAddress, BufferUsages, ColorTargetState,
+    ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device, FragmentState,
+    FrontFace, MultisampleState, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState, TextureFormat,
+    VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+// Lines run from -HALF_EXTENT to HALF_EXTENT on both axes. Far enough that the fade hides the edge.
+const HALF_EXTENT: i32 = 50;
+const SPACING: f32 = 1.0;
+
+const LINE_COLOR: [f32; 3] = [0.4, 0.4, 0.4];
+// Every MAJOR_EVERY lines is brighter
+const MAJOR_EVERY: i32 = 10;
+const MAJOR_COLOR: [f32; 3] = [0.7, 0.7, 0.7];
+// The lines through the origin along X and Z
+const X_AXIS_COLOR: [f32; 3] = [0.9, 0.2, 0.2];
+const Z_AXIS_COLOR: [f32; 3] = [0.2, 0.3, 0.9];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct GridVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl GridVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<GridVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// Lines on the XZ plane for a sense of scale and direction. They fade out away from the camera.
+pub struct Grid {
+    pub enabled: bool,
+    vertex_buffer: Buffer,
+    num_vertices: u32,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count or depth test changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+}
+
+impl Grid {
+    pub fn new(
+        device: &Device,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let vertices = grid_vertices();
+
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Grid Vertex Buffer"),
+            contents: cast_slice(&vertices),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: ShaderSource::Wgsl(include_str!("grid.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            &layout,
+            &shader,
+            color_format,
+            depth_format,
+            1,
+            CompareFunction::Less,
+        );
+
+        Self {
+            enabled: false,
+            vertex_buffer,
+            num_vertices: vertices.len() as u32,
+            pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            sample_count: 1,
+            depth_compare: CompareFunction::Less,
+        }
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.rebuild_pipeline(device);
+    }
+
+    // Rebuilds the pipeline to test depth with `depth_compare`, e.g. Greater for reversed-Z.
+    pub fn set_depth_compare(&mut self, device: &Device, depth_compare: CompareFunction) {
+        self.depth_compare = depth_compare;
+        self.rebuild_pipeline(device);
+    }
+
+    fn rebuild_pipeline(&mut self, device: &Device) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            self.sample_count,
+            self.depth_compare,
+        );
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera_bind_group: &'a BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+// Two vertices per line, one set running along X and one along Z.
+fn grid_vertices() -> Vec<GridVertex> {
+    let extent = HALF_EXTENT as f32 * SPACING;
+    let mut vertices = Vec::new();
+
+    for i in -HALF_EXTENT..=HALF_EXTENT {
+        let offset = i as f32 * SPACING;
+        let color = if i % MAJOR_EVERY == 0 {
+            MAJOR_COLOR
+        } else {
+            LINE_COLOR
+        };
+
+        // Runs along X, so at z = 0 it's the X axis
+        let x_line_color = if i == 0 { X_AXIS_COLOR } else { color };
+        vertices.push(GridVertex {
+            position: [-extent, 0.0, offset],
+            color: x_line_color,
+        });
+        vertices.push(GridVertex {
+            position: [extent, 0.0, offset],
+            color: x_line_color,
+        });
+
+        let z_line_color = if i == 0 { Z_AXIS_COLOR } else { color };
+        vertices.push(GridVertex {
+            position: [offset, 0.0, -extent],
+            color: z_line_color,
+        });
+        vertices.push(GridVertex {
+            position: [offset, 0.0, extent],
+            color: z_line_color,
+        });
+    }
+
+    vertices
+}
+
+// `sample_count` has to match the pass the grid is drawn in.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Grid Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[GridVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                // The fade is in the alpha
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::LineList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        // Hidden behind anything solid, but doesn't hide anything itself
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
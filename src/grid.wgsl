@@ -0,0 +1,38 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct GridInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+// Lines start fading out at FADE_START units from the camera and are gone by FADE_END
+let FADE_START: f32 = 10.0;
+let FADE_END: f32 = 40.0;
+
+@vertex
+fn vs_main(vertex: GridInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(vertex.position, 1.0);
+    out.world_position = vertex.position;
+    out.color = vertex.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.world_position - camera.view_position.xyz);
+    let fade = 1.0 - smoothstep(FADE_START, FADE_END, distance);
+    return vec4<f32>(in.color, fade);
+}
@@ -0,0 +1,518 @@
+use std::{mem::size_of, time::Duration};
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBindingType,
+    BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoderDescriptor,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, FragmentState, FrontFace, LoadOp,
+    MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, SamplerBindingType, ShaderModule, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureFormat, TextureSampleType,
+    TextureViewDimension, VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+use crate::{
+    camera::{Camera, CameraUniform, Projection, TimeUniform},
+    instance::{Instance, InstanceRaw},
+    model::Model,
+    resources::DrawModel,
+    texture::Texture,
+};
+
+// Width and height of the baked impostor texture.
+const IMPOSTOR_RESOLUTION: u32 = 256;
+// Half the width of the area captured around the model's origin.
+// The cube is 2 units across, so this leaves room for its corners.
+const IMPOSTOR_EXTENT: f32 = 1.8;
+
+// Per-instance data for the billboards. Rotation doesn't matter since they always face the camera.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ImpostorInstance {
+    position: [f32; 3],
+    size: f32,
+}
+
+impl ImpostorInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<ImpostorInstance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// Camera basis vectors for building camera-facing quads.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct BillboardUniform {
+    right: [f32; 4],
+    up: [f32; 4],
+}
+
+impl BillboardUniform {
+    pub fn new(camera: &Camera) -> Self {
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+
+        Self {
+            right: right.extend(0.0).into(),
+            up: up.extend(0.0).into(),
+        }
+    }
+}
+
+// Cheap level of detail: instances further than `distance` from the camera are drawn as a single
+// camera-facing quad showing a pre-rendered picture of the model instead of the full geometry.
+pub struct Impostors {
+    enabled: bool,
+    pub distance: f32,
+    billboard_buffer: Buffer,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count or depth test changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+    // Full geometry instances, laid out like the regular instance buffer
+    near_buffer: Buffer,
+    far_buffer: Buffer,
+    capacity: usize,
+    num_near: u32,
+    num_far: u32,
+}
+
+impl Impostors {
+    // Bakes the impostor texture by rendering `model` with the regular `render_pipeline`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+        render_pipeline: &RenderPipeline,
+        light_bind_group: &BindGroup,
+        model: &Model,
+        capacity: usize,
+    ) -> Self {
+        let texture = bake(
+            device,
+            queue,
+            color_format,
+            depth_format,
+            camera_bind_group_layout,
+            render_pipeline,
+            light_bind_group,
+            model,
+        );
+
+        let billboard_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Billboard Uniform Buffer"),
+            size: size_of::<BillboardUniform>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("impostor_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&texture.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&texture.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: billboard_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("impostor_bind_group"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Impostor Shader"),
+            source: ShaderSource::Wgsl(include_str!("impostor.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Impostor Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            &layout,
+            &shader,
+            color_format,
+            depth_format,
+            1,
+            CompareFunction::Less,
+        );
+
+        let (near_buffer, far_buffer) = create_instance_buffers(device, capacity);
+
+        Self {
+            enabled: false,
+            distance: 15.0,
+            billboard_buffer,
+            bind_group,
+            pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            sample_count: 1,
+            depth_compare: CompareFunction::Less,
+            near_buffer,
+            far_buffer,
+            capacity,
+            num_near: 0,
+            num_far: 0,
+        }
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.rebuild_pipeline(device);
+    }
+
+    // Rebuilds the pipeline to test depth with `depth_compare`, e.g. Greater for reversed-Z.
+    pub fn set_depth_compare(&mut self, device: &Device, depth_compare: CompareFunction) {
+        self.depth_compare = depth_compare;
+        self.rebuild_pipeline(device);
+    }
+
+    fn rebuild_pipeline(&mut self, device: &Device) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            self.sample_count,
+            self.depth_compare,
+        );
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        // Forget the old counts so the next update reports them again
+        self.num_near = 0;
+        self.num_far = 0;
+    }
+
+    // (full geometry, impostor) instance counts from the last update.
+    pub fn counts(&self) -> (u32, u32) {
+        (self.num_near, self.num_far)
+    }
+
+    // Splits the instances by distance to the camera and uploads both halves.
+    // Returns true if the counts changed since the last update.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        camera: &Camera,
+        instances: &[Instance],
+    ) -> bool {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len();
+            (self.near_buffer, self.far_buffer) = create_instance_buffers(device, self.capacity);
+        }
+
+        let billboard = BillboardUniform::new(camera);
+        queue.write_buffer(&self.billboard_buffer, 0, cast_slice(&[billboard]));
+
+        let eye = camera.eye.to_vec();
+        let (near, far): (Vec<&Instance>, Vec<&Instance>) = instances
+            .iter()
+            .partition(|instance| (instance.position - eye).magnitude() < self.distance);
+
+        let near = near.into_iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let far = far
+            .into_iter()
+            .map(|instance| ImpostorInstance {
+                position: instance.position.into(),
+                size: IMPOSTOR_EXTENT * 2.0,
+            })
+            .collect::<Vec<_>>();
+
+        if !near.is_empty() {
+            queue.write_buffer(&self.near_buffer, 0, cast_slice(&near));
+        }
+        if !far.is_empty() {
+            queue.write_buffer(&self.far_buffer, 0, cast_slice(&far));
+        }
+
+        let counts = (near.len() as u32, far.len() as u32);
+        let changed = counts != self.counts();
+        (self.num_near, self.num_far) = counts;
+
+        changed
+    }
+
+    // Draws the near instances with the pipeline that is currently set, then the far ones as impostors.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        model: &'a Model,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        render_pass.set_vertex_buffer(1, self.near_buffer.slice(..));
+        render_pass.draw_model_instanced(model, 0..self.num_near, camera_bind_group);
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.far_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.num_far);
+    }
+}
+
+fn create_instance_buffers(device: &Device, capacity: usize) -> (Buffer, Buffer) {
+    let near_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Impostor Near Instance Buffer"),
+        size: (capacity * size_of::<InstanceRaw>()) as BufferAddress,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let far_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Impostor Far Instance Buffer"),
+        size: (capacity * size_of::<ImpostorInstance>()) as BufferAddress,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    (near_buffer, far_buffer)
+}
+
+// Renders the model once from the front into a transparent texture.
+#[allow(clippy::too_many_arguments)]
+fn bake(
+    device: &Device,
+    queue: &Queue,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    camera_bind_group_layout: &BindGroupLayout,
+    render_pipeline: &RenderPipeline,
+    light_bind_group: &BindGroup,
+    model: &Model,
+) -> Texture {
+    // Has to match the render pipeline's color target, so the surface format is used here.
+    let texture = Texture::create_render_target(
+        device,
+        IMPOSTOR_RESOLUTION,
+        IMPOSTOR_RESOLUTION,
+        color_format,
+        "impostor_texture",
+    );
+    let depth_texture = Texture::create_depth_texture_with_size(
+        device,
+        IMPOSTOR_RESOLUTION,
+        IMPOSTOR_RESOLUTION,
+        depth_format,
+        "impostor_depth_texture",
+    );
+
+    // Looks at the model from roughly where the default camera does. Orthographic so the
+    // picture doesn't depend on how far away the impostor ends up.
+    let eye = Point3::new(0.0, 1.0, 2.0).to_vec().normalize() * 5.0;
+    let camera = Camera {
+        eye: Point3::from_vec(eye),
+        target: Point3::origin(),
+        up: Vector3::unit_y(),
+        aspect: 1.0,
+        projection: Projection::Orthographic {
+            height: IMPOSTOR_EXTENT * 2.0,
+        },
+        znear: 0.1,
+        zfar: 10.0,
+        // The bake pass is drawn with the main pipeline, which is made before reversed-Z can be turned on
+        reversed_z: false,
+    };
+    let mut camera_uniform = CameraUniform::new();
+    camera_uniform.update_view_proj(&camera);
+
+    let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Impostor Camera Buffer"),
+        contents: cast_slice(&[camera_uniform]),
+        usage: BufferUsages::UNIFORM,
+    });
+
+    // Baked at the start of time, so anything animated is in its first pose
+    let time_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Impostor Time Buffer"),
+        contents: cast_slice(&[TimeUniform::new(Duration::ZERO)]),
+        usage: BufferUsages::UNIFORM,
+    });
+
+    let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: camera_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: time_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("impostor_camera_bind_group"),
+    });
+
+    // A single instance sitting at the origin. Baked with the untinted material, which all impostors show.
+    let instance = InstanceRaw {
+        model: Matrix4::identity().into(),
+        tint: [1.0; 3],
+    };
+    let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Impostor Bake Instance Buffer"),
+        contents: cast_slice(&[instance]),
+        usage: BufferUsages::VERTEX,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Impostor Bake Encoder"),
+    });
+
+    {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Impostor Bake Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &texture.view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &depth_texture.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        render_pass.set_bind_group(2, light_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.draw_model(model, &camera_bind_group);
+    }
+
+    queue.submit(std::iter::once(encoder.finish()));
+
+    texture
+}
+
+// `sample_count` has to match the pass the impostors are drawn in.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Impostor Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[ImpostorInstance::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            // The quads always face the camera anyway
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,70 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+}
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Camera basis vectors in world space so the quads always face the camera. Only xyz is used.
+struct BillboardUniform {
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var t_impostor: texture_2d<f32>;
+
+@group(0) @binding(1)
+var s_impostor: sampler;
+
+@group(0) @binding(2)
+var<uniform> billboard: BillboardUniform;
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// No vertex buffer: the 6 vertices of the quad are built from the vertex index.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[vertex_index];
+
+    let world_position = instance.position
+        + billboard.right.xyz * corner.x * instance.size
+        + billboard.up.xyz * corner.y * instance.size;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    // Texture space has y pointing down
+    out.tex_coords = vec2<f32>(corner.x + 0.5, 0.5 - corner.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_impostor, s_impostor, in.tex_coords);
+
+    // The baked texture is transparent around the model. Discarding keeps the depth buffer clean there.
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    return color;
+}
@@ -1,9 +1,19 @@
-use cgmath::{Matrix4, Quaternion, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Quaternion, Vector3};
+
+// Colors instances multiply their material by, picked with their `material_index`
+pub const MATERIAL_TINTS: [[f32; 3]; 4] = [
+    [1.0, 1.0, 1.0],
+    [1.0, 0.55, 0.45],
+    [0.55, 1.0, 0.6],
+    [0.5, 0.65, 1.0],
+];
 
 pub struct Instance {
     pub position: Vector3<f32>,
     // https://mathworld.wolfram.com/Quaternion.html
     pub rotation: Quaternion<f32>,
+    // Which of MATERIAL_TINTS to draw with, wrapping around
+    pub material_index: u32,
 }
 
 // Quaternions don't have a WGSL analog so creating a Matrix4x4 from a Quaternion is the best we can do
@@ -11,6 +21,7 @@ pub struct Instance {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
+    pub tint: [f32; 3],
 }
 
 impl InstanceRaw {
@@ -48,6 +59,12 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // 9 and 10 are taken by the morph target
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -57,6 +74,52 @@ impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             model: (Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)).into(),
+            tint: MATERIAL_TINTS[self.material_index as usize % MATERIAL_TINTS.len()],
+        }
+    }
+}
+
+// Order to upload instances in, by distance to the camera.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InstanceSort {
+    // Keep the order the instances were created in
+    None,
+    // Nearest first. Opaque objects hide more of what's drawn after them, so the depth test can skip their fragments early.
+    FrontToBack,
+    // Furthest first. Needed for transparent objects to blend correctly.
+    BackToFront,
+}
+
+// Returns instance indices in draw order.
+pub fn sorted_order(instances: &[Instance], eye: Point3<f32>, sort: InstanceSort) -> Vec<usize> {
+    let mut order = (0..instances.len()).collect::<Vec<_>>();
+
+    let distance = |i: &usize| (instances[*i].position - eye.to_vec()).magnitude2();
+
+    match sort {
+        InstanceSort::None => {}
+        InstanceSort::FrontToBack => {
+            order.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        }
+        InstanceSort::BackToFront => {
+            order.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
         }
     }
+
+    order
+}
+
+// Where the ray crosses the horizontal plane at `height`. None if it points away from it.
+pub fn ground_point(
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    height: f32,
+) -> Option<Vector3<f32>> {
+    let t = (height - origin.y) / direction.y;
+
+    if !t.is_finite() || t < 0.0 {
+        return None;
+    }
+
+    Some(origin.to_vec() + direction * t)
 }
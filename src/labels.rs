@@ -0,0 +1,379 @@
+use std::mem::size_of;
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{EuclideanSpace, InnerSpace, Vector3};
+use wgpu::{
+    util::DeviceExt, AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+    BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState,
+    ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device, Extent3d, FilterMode,
+    FragmentState, FrontFace, MultisampleState, PipelineLayout, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, SamplerBindingType, SamplerDescriptor, ShaderModule,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
+    TextureViewDimension, VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+use crate::{camera::Camera, impostor::BillboardUniform, instance::Instance};
+
+// Digits that fit in one label. Has to fit in the 32 bit `digits` field along with the count.
+const MAX_DIGITS: u32 = 7;
+// How far above each instance its label floats
+const LABEL_HEIGHT: f32 = 1.6;
+
+// Each digit is 3x5 pixels, one bit per pixel starting at the top left.
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+#[rustfmt::skip]
+const GLYPHS: [u16; 10] = [
+    0b111_101_101_101_111, // 0
+    0b010_110_010_010_111, // 1
+    0b111_001_111_100_111, // 2
+    0b111_001_111_001_111, // 3
+    0b101_101_111_001_001, // 4
+    0b111_100_111_001_111, // 5
+    0b111_100_111_101_111, // 6
+    0b111_001_010_010_010, // 7
+    0b111_101_111_101_111, // 8
+    0b111_101_111_001_111, // 9
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct LabelInstance {
+    position: [f32; 3],
+    digits: u32,
+}
+
+impl LabelInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Uint32];
+
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        VertexBufferLayout {
+            array_stride: size_of::<LabelInstance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// Draws each instance's index above it, all in a single instanced draw call.
+pub struct Labels {
+    pub enabled: bool,
+    // Labels further than this from the camera are skipped
+    pub max_distance: f32,
+    billboard_buffer: Buffer,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    instance_buffer: Buffer,
+    capacity: usize,
+    num_labels: u32,
+}
+
+impl Labels {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+        capacity: usize,
+    ) -> Self {
+        let font_texture = create_font_texture(device, queue);
+        let font_view = font_texture.create_view(&TextureViewDescriptor::default());
+
+        // Nearest keeps the pixel font crisp
+        let font_sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let billboard_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Label Billboard Buffer"),
+            size: size_of::<BillboardUniform>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("label_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&font_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&font_sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: billboard_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("label_bind_group"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Label Shader"),
+            source: ShaderSource::Wgsl(include_str!("labels.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Label Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &layout, &shader, color_format, depth_format, 1);
+
+        let instance_buffer = create_instance_buffer(device, capacity);
+
+        Self {
+            enabled: false,
+            max_distance: 30.0,
+            billboard_buffer,
+            bind_group,
+            pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            instance_buffer,
+            capacity,
+            num_labels: 0,
+        }
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            sample_count,
+        );
+    }
+
+    // Rebuilds the labels for the instances that are on screen and close enough.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        camera: &Camera,
+        instances: &[Instance],
+    ) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len();
+            self.instance_buffer = create_instance_buffer(device, self.capacity);
+        }
+
+        let billboard = BillboardUniform::new(camera);
+        queue.write_buffer(&self.billboard_buffer, 0, cast_slice(&[billboard]));
+
+        let view_proj = camera.build_view_projection_matrix();
+        let eye = camera.eye.to_vec();
+
+        let labels = instances
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instance)| {
+                let position = instance.position + Vector3::unit_y() * LABEL_HEIGHT;
+
+                if (position - eye).magnitude() > self.max_distance {
+                    return None;
+                }
+
+                // Outside the view frustum. The margin stops labels popping out while still partly on screen.
+                let clip = view_proj * position.extend(1.0);
+                let margin = clip.w * 1.2;
+                if clip.w <= 0.0 || clip.x.abs() > margin || clip.y.abs() > margin {
+                    return None;
+                }
+
+                Some(LabelInstance {
+                    position: position.into(),
+                    digits: pack_digits(i as u32),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if !labels.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, cast_slice(&labels));
+        }
+
+        self.num_labels = labels.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera_bind_group: &'a BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6 * MAX_DIGITS, 0..self.num_labels);
+    }
+}
+
+// Count in the lowest 4 bits, then the digits from most to least significant, 4 bits each.
+fn pack_digits(mut number: u32) -> u32 {
+    let mut digits = Vec::new();
+    loop {
+        digits.push(number % 10);
+        number /= 10;
+        if number == 0 || digits.len() as u32 == MAX_DIGITS {
+            break;
+        }
+    }
+
+    digits
+        .iter()
+        .rev()
+        .enumerate()
+        .fold(digits.len() as u32, |packed, (slot, digit)| {
+            packed | digit << (4 + slot * 4)
+        })
+}
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Label Instance Buffer"),
+        size: (capacity * size_of::<LabelInstance>()) as BufferAddress,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// Turns GLYPHS into a white-on-transparent texture with the digits side by side.
+fn create_font_texture(device: &Device, queue: &Queue) -> wgpu::Texture {
+    let width = GLYPH_WIDTH * GLYPHS.len() as u32;
+
+    let mut data = vec![0u8; (width * GLYPH_HEIGHT * 4) as usize];
+    for (digit, glyph) in GLYPHS.iter().enumerate() {
+        for y in 0..GLYPH_HEIGHT {
+            for x in 0..GLYPH_WIDTH {
+                let bit = GLYPH_WIDTH * GLYPH_HEIGHT - 1 - (y * GLYPH_WIDTH + x);
+                if glyph >> bit & 1 == 1 {
+                    let offset = ((y * width + digit as u32 * GLYPH_WIDTH + x) * 4) as usize;
+                    data[offset..offset + 4].copy_from_slice(&[255; 4]);
+                }
+            }
+        }
+    }
+
+    device.create_texture_with_data(
+        queue,
+        &TextureDescriptor {
+            label: Some("label_font_texture"),
+            size: Extent3d {
+                width,
+                height: GLYPH_HEIGHT,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        },
+        &data,
+    )
+}
+
+// `sample_count` has to match the pass the labels are drawn in.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Label Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[LabelInstance::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            // The quads always face the camera anyway
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        // Drawn on top of everything so labels are never hidden behind other instances
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,93 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+}
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Camera basis vectors in world space so the labels always face the camera. Only xyz is used.
+struct BillboardUniform {
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+
+// The digits 0-9 side by side
+@group(0) @binding(0)
+var t_font: texture_2d<f32>;
+
+@group(0) @binding(1)
+var s_font: sampler;
+
+@group(0) @binding(2)
+var<uniform> billboard: BillboardUniform;
+
+struct LabelInput {
+    @location(0) position: vec3<f32>,
+    // The lowest 4 bits are the digit count, then 4 bits per digit starting with the first one
+    @location(1) digits: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+let GLYPH_COUNT: f32 = 10.0;
+// Size of one digit in world units
+let GLYPH_WIDTH: f32 = 0.24;
+let GLYPH_HEIGHT: f32 = 0.4;
+// Extra space between digits as a fraction of GLYPH_WIDTH
+let GLYPH_SPACING: f32 = 0.3;
+
+// Every label is drawn with 6 vertices per possible digit. Quads past the label's digit count collapse to a point.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    label: LabelInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[vertex_index % 6u];
+    let slot = vertex_index / 6u;
+
+    let count = label.digits & 15u;
+
+    var out: VertexOutput;
+
+    if (slot >= count) {
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        out.tex_coords = vec2<f32>(0.0);
+        return out;
+    }
+
+    let digit = (label.digits >> (4u + slot * 4u)) & 15u;
+
+    // Center the whole number over the position
+    let advance = GLYPH_WIDTH * (1.0 + GLYPH_SPACING);
+    let x = (f32(slot) - f32(count - 1u) * 0.5) * advance + corner.x * GLYPH_WIDTH;
+    let y = corner.y * GLYPH_HEIGHT;
+
+    let world_position = label.position + billboard.right.xyz * x + billboard.up.xyz * y;
+
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    // Texture space has y pointing down
+    out.tex_coords = vec2<f32>((f32(digit) + corner.x + 0.5) / GLYPH_COUNT, 0.5 - corner.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_font, s_font, in.tex_coords);
+
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    return color;
+}
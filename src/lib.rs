@@ -0,0 +1,55 @@
+mod accumulation;
+mod background;
+mod calibration;
+mod camera;
+mod camera_path;
+mod color;
+mod compress;
+mod debug_lines;
+mod depth_view;
+mod dof;
+mod environment;
+mod fog;
+mod fxaa;
+mod gltf;
+mod grid;
+mod impostor;
+mod instance;
+mod labels;
+mod layouts;
+mod light;
+mod model;
+mod morph;
+mod object_transform;
+mod outline;
+mod particles;
+mod picking;
+mod post;
+mod profiler;
+mod resources;
+mod scene;
+mod scene_pipeline;
+mod screenshot;
+mod shader_constants;
+mod shadow;
+mod ssao;
+mod state;
+mod texture;
+mod tonemap;
+mod upscale;
+mod vertex;
+mod wind;
+mod window_adapter;
+
+// What's needed to drive the renderer from code, e.g. headless from tests or CI, and the types its methods take
+pub use background::BackgroundMode;
+pub use camera::{CameraMode, KeyBindings};
+pub use dof::DepthOfField;
+pub use fog::HeightFog;
+pub use instance::{Instance, InstanceSort};
+pub use profiler::RenderStats;
+pub use ssao::Ssao;
+pub use state::{ShadingRate, State, DEFAULT_RENDER_TARGET_FORMAT};
+pub use texture::{SamplerConfig, Texture};
+pub use wind::Wind;
+pub use window_adapter::{run, WindowConfig};
@@ -0,0 +1,75 @@
+// Point lights the shader can take at once. Also the array size of `Lights` in shader.wgsl, which gets it
+// through `shader_constants::with_constants`.
+pub const MAX_LIGHTS: usize = 16;
+
+// A single point light. The padding keeps each vec3 16 byte aligned like the shader expects.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightRaw {
+    pub position: [f32; 3],
+    _pad: u32,
+    pub color: [f32; 3],
+    _pad2: u32,
+}
+
+impl LightRaw {
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            position,
+            _pad: 0,
+            color,
+            _pad2: 0,
+        }
+    }
+}
+
+// Every light, uploaded as one uniform. Only the first `count` entries of `lights` are lit with.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Lights {
+    count: u32,
+    // The array has to start 16 bytes in
+    _pad: [u32; 3],
+    lights: [LightRaw; MAX_LIGHTS],
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+
+    // Returns false without adding it when there are already MAX_LIGHTS.
+    pub fn push(&mut self, light: LightRaw) -> bool {
+        let count = self.count as usize;
+        if count == MAX_LIGHTS {
+            return false;
+        }
+
+        self.lights[count] = light;
+        self.count += 1;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn as_slice(&self) -> &[LightRaw] {
+        &self.lights[..self.len()]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [LightRaw] {
+        let count = self.len();
+        &mut self.lights[..count]
+    }
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,30 +1,631 @@
-use std::mem::size_of;
+use std::{collections::HashMap, mem::size_of};
 
-use bytemuck::{Pod, Zeroable};
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{Angle, Deg, EuclideanSpace, InnerSpace, Point3, Vector2, Vector3, Zero};
 use wgpu::{
-    BindGroup, Buffer, BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat,
-    VertexStepMode,
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource, Buffer,
+    BufferAddress, BufferUsages, Device, FrontFace, IndexFormat, VertexAttribute,
+    VertexBufferLayout, VertexFormat, VertexStepMode,
 };
 
-use crate::texture::Texture;
+use crate::{
+    layouts::BindGroupLayouts,
+    texture::{SamplerConfig, Texture},
+};
+
+// How shiny a material is. The first half of `Material` in shader.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct Specular {
+    // Tints the highlight. Black turns it off.
+    pub color: [f32; 3],
+    // Blinn-Phong exponent. Higher values make a smaller, sharper highlight.
+    pub shininess: f32,
+}
+
+impl Specular {
+    // Half strength white highlights. What materials get when the file doesn't say.
+    pub const DEFAULT: Self = Self {
+        color: [0.5; 3],
+        shininess: 32.0,
+    };
+
+    // From an MTL's Ks and Ns. tobj leaves both at zero when they're missing. Ns 0 would turn the whole
+    // surface into highlight, so it counts as missing, and Ks 0 0 0 only counts as missing along with it.
+    pub fn from_mtl(specular: [f32; 3], shininess: f32) -> Self {
+        if shininess > 0.0 {
+            Self {
+                color: specular,
+                shininess,
+            }
+        } else if specular != [0.0; 3] {
+            Self {
+                color: specular,
+                ..Self::DEFAULT
+            }
+        } else {
+            Self::DEFAULT
+        }
+    }
+}
+
+impl Default for Specular {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// Matches `Material` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct MaterialUniform {
+    specular: Specular,
+    emissive: [f32; 3],
+    _emissive_padding: f32,
+    uv_scroll: [f32; 2],
+    // Uniforms need to be 16 byte aligned
+    _padding: [f32; 2],
+}
+
+// `emissive` is light the material gives off by itself, multiplied by its emissive texture.
+// `uv_scroll` is how far the texture slides per second of scene time, in texture widths and heights.
+pub fn material_buffer(
+    device: &Device,
+    specular: Specular,
+    emissive: [f32; 3],
+    uv_scroll: [f32; 2],
+) -> Buffer {
+    device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Material Uniform Buffer"),
+        contents: cast_slice(&[MaterialUniform {
+            specular,
+            emissive,
+            _emissive_padding: 0.0,
+            uv_scroll,
+            _padding: [0.0; 2],
+        }]),
+        usage: BufferUsages::UNIFORM,
+    })
+}
 
 pub struct Material {
     pub name: String,
     pub diffuse_texture: Texture,
+    pub normal_texture: Texture,
     pub bind_group: BindGroup,
+    // Drawn after everything opaque, blended over what's behind it
+    pub transparent: bool,
+    pub specular: Specular,
+    // Added on top of the lighting, so it shows up even in the dark. Black for most materials.
+    pub emissive: [f32; 3],
+    pub emissive_texture: Texture,
+    // Baked lighting the diffuse color is multiplied by, using the second UV set. White for most materials.
+    pub lightmap_texture: Texture,
+    uniform_buffer: Buffer,
+}
+
+impl Material {
+    // `emissive_texture` is multiplied by `emissive`. Pass white when the material has no emissive map,
+    // and for `lightmap_texture` when it has no lightmap.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        layouts: &BindGroupLayouts,
+        name: String,
+        diffuse_texture: Texture,
+        normal_texture: Texture,
+        transparent: bool,
+        specular: Specular,
+        emissive: [f32; 3],
+        emissive_texture: Texture,
+        lightmap_texture: Texture,
+    ) -> Self {
+        let uniform_buffer = material_buffer(device, specular, emissive, [0.0; 2]);
+        let bind_group = texture_bind_group(
+            device,
+            &layouts.texture,
+            &diffuse_texture,
+            &normal_texture,
+            &emissive_texture,
+            &lightmap_texture,
+            &uniform_buffer,
+        );
+
+        Self {
+            name,
+            diffuse_texture,
+            normal_texture,
+            bind_group,
+            transparent,
+            specular,
+            emissive,
+            emissive_texture,
+            lightmap_texture,
+            uniform_buffer,
+        }
+    }
+
+    // Refilters the diffuse texture. The normal map keeps its own sampler.
+    pub fn set_diffuse_sampler(
+        &mut self,
+        device: &Device,
+        layouts: &BindGroupLayouts,
+        sampler_config: SamplerConfig,
+    ) -> anyhow::Result<()> {
+        self.diffuse_texture
+            .set_sampler_config(device, sampler_config)?;
+        self.bind_group = texture_bind_group(
+            device,
+            &layouts.texture,
+            &self.diffuse_texture,
+            &self.normal_texture,
+            &self.emissive_texture,
+            &self.lightmap_texture,
+            &self.uniform_buffer,
+        );
+
+        Ok(())
+    }
+
+    // Sampled with the diffuse texture's sampler, at the mesh's second UV set.
+    pub fn set_lightmap(
+        &mut self,
+        device: &Device,
+        layouts: &BindGroupLayouts,
+        lightmap_texture: Texture,
+    ) {
+        self.lightmap_texture = lightmap_texture;
+        self.bind_group = texture_bind_group(
+            device,
+            &layouts.texture,
+            &self.diffuse_texture,
+            &self.normal_texture,
+            &self.emissive_texture,
+            &self.lightmap_texture,
+            &self.uniform_buffer,
+        );
+    }
+}
+
+// Diffuse texture and sampler at bindings 0 and 1, the normal map's at 2 and 3, the material uniform at 4,
+// then the emissive map and lightmap.
+pub fn texture_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    diffuse_texture: &Texture,
+    normal_texture: &Texture,
+    emissive_texture: &Texture,
+    lightmap_texture: &Texture,
+    material_buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&diffuse_texture.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&diffuse_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&normal_texture.view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Sampler(&normal_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: material_buffer.as_entire_binding(),
+            },
+            // Sampled with the diffuse texture's sampler
+            BindGroupEntry {
+                binding: 5,
+                resource: BindingResource::TextureView(&emissive_texture.view),
+            },
+            // So is this
+            BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(&lightmap_texture.view),
+            },
+        ],
+        label: None,
+    })
 }
 
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
+    // Uint16 unless there are too many vertices for it to reach
+    pub index_format: IndexFormat,
+    pub num_vertices: u32,
     pub num_elements: u32,
     pub material: usize,
+    // In model space. Transparent meshes are sorted by its center.
+    pub aabb: Aabb,
+    // The geometry as loaded, kept around so it can be subdivided again
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &Device,
+        name: &str,
+        vertices: Vec<ModelVertex>,
+        indices: Vec<u32>,
+        material: usize,
+    ) -> Self {
+        let (vertex_buffer, index_buffer, index_format) =
+            create_buffers(device, name, &vertices, &indices);
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            index_format,
+            num_vertices: vertices.len() as u32,
+            num_elements: indices.len() as u32,
+            material,
+            aabb: Aabb::from_points(vertices.iter().map(|vertex| vertex.position.into())),
+            vertices,
+            indices,
+        }
+    }
+}
+
+// Axis-aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    // A single point at the origin when there are no points.
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self {
+                min: Point3::origin(),
+                max: Point3::origin(),
+            };
+        };
+
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: Point3::new(
+                    aabb.min.x.min(point.x),
+                    aabb.min.y.min(point.y),
+                    aabb.min.z.min(point.z),
+                ),
+                max: Point3::new(
+                    aabb.max.x.max(point.x),
+                    aabb.max.y.max(point.y),
+                    aabb.max.z.max(point.z),
+                ),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    subdivision: u32,
+    // Covers every mesh. Subdividing only adds points between existing ones, so it never changes.
+    aabb: Aabb,
+}
+
+impl Model {
+    pub fn new(meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
+        let aabb = Aabb::from_points(
+            meshes
+                .iter()
+                .flat_map(|mesh| [mesh.aabb.min, mesh.aabb.max]),
+        );
+
+        Self {
+            meshes,
+            materials,
+            subdivision: 0,
+            aabb,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    // The min and max corners of the box around every vertex, in model space.
+    pub fn compute_aabb(&self) -> (Point3<f32>, Point3<f32>) {
+        (self.aabb.min, self.aabb.max)
+    }
+
+    pub fn subdivision(&self) -> u32 {
+        self.subdivision
+    }
+
+    pub fn has_transparency(&self) -> bool {
+        self.meshes
+            .iter()
+            .any(|mesh| self.materials[mesh.material].transparent)
+    }
+
+    pub fn num_triangles(&self) -> u32 {
+        self.meshes.iter().map(|mesh| mesh.num_elements / 3).sum()
+    }
+
+    // Splits every triangle into 4, `levels` times over, and uploads the result. Always starts from the
+    // loaded geometry, so 0 brings back the original mesh. Each level quadruples the triangle count.
+    // New vertices blend the normals of the edge they split, so hard edges stay hard.
+    pub fn subdivide(&mut self, device: &Device, levels: u32) {
+        for mesh in &mut self.meshes {
+            let mut vertices = mesh.vertices.clone();
+            let mut indices = mesh.indices.clone();
+
+            if levels > 0 {
+                for _ in 0..levels {
+                    (vertices, indices) = split_triangles(&vertices, &indices);
+                }
+
+                compute_tangents(&mut vertices, &indices);
+            }
+
+            (mesh.vertex_buffer, mesh.index_buffer, mesh.index_format) =
+                create_buffers(device, &mesh.name, &vertices, &indices);
+            mesh.num_vertices = vertices.len() as u32;
+            mesh.num_elements = indices.len() as u32;
+        }
+
+        self.subdivision = levels;
+    }
+}
+
+// 16 bit indices take half the memory, but only reach the first 65536 vertices
+fn index_format(vertex_count: usize) -> IndexFormat {
+    if vertex_count <= u16::MAX as usize + 1 {
+        IndexFormat::Uint16
+    } else {
+        IndexFormat::Uint32
+    }
+}
+
+fn create_buffers(
+    device: &Device,
+    name: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+) -> (Buffer, Buffer, IndexFormat) {
+    let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", name)),
+        contents: cast_slice(vertices),
+        usage: BufferUsages::VERTEX,
+    });
+
+    let index_format = index_format(vertices.len());
+
+    let short_indices;
+    let contents = match index_format {
+        IndexFormat::Uint16 => {
+            short_indices = indices.iter().map(|&i| i as u16).collect::<Vec<_>>();
+            cast_slice(&short_indices)
+        }
+        IndexFormat::Uint32 => cast_slice(indices),
+    };
+
+    let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents,
+        usage: BufferUsages::INDEX,
+    });
+
+    (vertex_buffer, index_buffer, index_format)
+}
+
+// Adds a vertex in the middle of every edge and replaces each triangle with the 4 it gets cut into.
+// Triangles sharing an edge share its middle vertex too, so no cracks open up.
+fn split_triangles(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut vertices = vertices.to_vec();
+    let mut midpoints = HashMap::new();
+    let mut split = Vec::with_capacity(indices.len() * 4);
+
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+
+        let mut midpoint = |i: u32, j: u32| {
+            *midpoints.entry((i.min(j), i.max(j))).or_insert_with(|| {
+                let (v0, v1) = (vertices[i as usize], vertices[j as usize]);
+                let mid = |x: f32, y: f32| (x + y) * 0.5;
+
+                vertices.push(ModelVertex {
+                    position: [0, 1, 2].map(|k| mid(v0.position[k], v1.position[k])),
+                    tex_coords: [0, 1].map(|k| mid(v0.tex_coords[k], v1.tex_coords[k])),
+                    tex_coords1: [0, 1].map(|k| mid(v0.tex_coords1[k], v1.tex_coords1[k])),
+                    normal: mid_normal(v0.normal, v1.normal),
+                    // Worked out again once the split is done
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
+                    color: [0, 1, 2].map(|k| mid(v0.color[k], v1.color[k])),
+                });
+                vertices.len() as u32 - 1
+            })
+        };
+
+        let ab = midpoint(a, b);
+        let bc = midpoint(b, c);
+        let ca = midpoint(c, a);
+
+        // Same winding as the original triangle
+        split.extend_from_slice(&[a, ab, ca, ab, b, bc, ca, bc, c, ab, bc, ca]);
+    }
+
+    (vertices, split)
+}
+
+// Halfway between two normals. Falls back to the first if they point opposite ways.
+fn mid_normal(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    let sum = Vector3::from(a) + Vector3::from(b);
+    if sum.magnitude2() == 0.0 {
+        return a;
+    }
+    sum.normalize().into()
+}
+
+// Past this angle between two triangles the edge between them stays sharp, like a cube's
+const CREASE_ANGLE: Deg<f32> = Deg(60.0);
+
+// Smooth normals from the triangles around each vertex, weighted by their area.
+// Vertices at the same position count as one so UV seams don't show up as creases. Triangles turned further
+// than CREASE_ANGLE from a vertex's own are left out though, so a cube's sides stay flat.
+pub fn compute_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let key = |vertex: &ModelVertex| vertex.position.map(f32::to_bits);
+    // Every triangle touching a position, and the sum of the ones using each vertex itself
+    let mut around: HashMap<_, Vec<Vector3<f32>>> = HashMap::new();
+    let mut own = vec![Vector3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|k| Vector3::from(vertices[triangle[k] as usize].position));
+        // Its length is twice the triangle's area
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() == 0.0 {
+            continue;
+        }
+
+        for &i in triangle {
+            around
+                .entry(key(&vertices[i as usize]))
+                .or_default()
+                .push(normal);
+            own[i as usize] += normal;
+        }
+    }
+
+    let min_cos = CREASE_ANGLE.cos();
+    for (vertex, own) in vertices.iter_mut().zip(own) {
+        if own.magnitude2() == 0.0 {
+            continue;
+        }
+        let own = own.normalize();
+
+        let sum = around[&key(vertex)]
+            .iter()
+            .filter(|normal| normal.normalize().dot(own) >= min_cos)
+            .fold(Vector3::zero(), |sum, normal| sum + normal);
+        if sum.magnitude2() > 0.0 {
+            vertex.normal = sum.normalize().into();
+        }
+    }
+}
+
+// Which way round the triangles go when seen from the side their normals point to.
+// Goes by what most of them agree on, so a few odd triangles don't flip a whole mesh.
+pub fn detect_front_face(vertices: &[ModelVertex], indices: &[u32]) -> FrontFace {
+    let mut votes = 0i64;
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| &vertices[i as usize]);
+        let edge1 = Vector3::from(b.position) - Vector3::from(a.position);
+        let edge2 = Vector3::from(c.position) - Vector3::from(a.position);
+        let normal = Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+
+        let facing = edge1.cross(edge2).dot(normal);
+        if facing > 0.0 {
+            votes += 1;
+        } else if facing < 0.0 {
+            votes -= 1;
+        }
+    }
+
+    if votes < 0 {
+        FrontFace::Cw
+    } else {
+        FrontFace::Ccw
+    }
+}
+
+// Swaps two corners of every triangle, turning clockwise into counter-clockwise and back.
+pub fn flip_winding(indices: &mut [u32]) {
+    for triangle in indices.chunks_exact_mut(3) {
+        triangle.swap(1, 2);
+    }
+}
+
+// Tangent space for normal mapping: the directions the texture's u and v run across the surface.
+// Each triangle's are worked out from how its UVs change along its edges, then averaged per vertex.
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [v0, v1, v2] = [0, 1, 2].map(|k| vertices[triangle[k] as usize]);
+
+        let edge1 = Vector3::from(v1.position) - Vector3::from(v0.position);
+        let edge2 = Vector3::from(v2.position) - Vector3::from(v0.position);
+        let delta_uv1 = Vector2::from(v1.tex_coords) - Vector2::from(v0.tex_coords);
+        let delta_uv2 = Vector2::from(v2.tex_coords) - Vector2::from(v0.tex_coords);
+
+        // Twice the triangle's area in texture space. Zero means the UVs don't give it a direction.
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+
+        let r = 1.0 / determinant;
+        let tangent = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) * r;
+        let bitangent = (edge2 * delta_uv1.x - edge1 * delta_uv2.x) * r;
+
+        for &i in triangle {
+            tangents[i as usize] += tangent;
+            bitangents[i as usize] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vector3::from(vertex.normal);
+
+        // Vertices only touched by degenerate triangles get any directions that are at right angles to the normal
+        let (tangent, bitangent) = if tangent.magnitude2() > 0.0 && bitangent.magnitude2() > 0.0 {
+            (tangent.normalize(), bitangent.normalize())
+        } else {
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            let tangent = normal.cross(axis).normalize();
+            (tangent, normal.cross(tangent))
+        };
+
+        vertex.tangent = tangent.into();
+        vertex.bitangent = bitangent.into();
+    }
 }
 
 pub trait Vertex {
@@ -37,6 +638,12 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
+    // Multiplies the texture. White for models that don't have vertex colors.
+    pub color: [f32; 3],
+    // Second UV set, for the lightmap. A copy of tex_coords for models that only have one.
+    pub tex_coords1: [f32; 2],
 }
 
 impl Vertex for ModelVertex {
@@ -60,7 +667,121 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: VertexFormat::Float32x3,
                 },
+                VertexAttribute {
+                    offset: size_of::<[f32; 8]>() as BufferAddress,
+                    shader_location: 3,
+                    format: VertexFormat::Float32x3,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 11]>() as BufferAddress,
+                    shader_location: 4,
+                    format: VertexFormat::Float32x3,
+                },
+                // 5 to 11 are taken by the instance and morph target attributes
+                VertexAttribute {
+                    offset: size_of::<[f32; 14]>() as BufferAddress,
+                    shader_location: 12,
+                    format: VertexFormat::Float32x3,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 17]>() as BufferAddress,
+                    shader_location: 13,
+                    format: VertexFormat::Float32x2,
+                },
             ],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: Vector3<f32>) -> ModelVertex {
+        ModelVertex {
+            position: position.into(),
+            tex_coords: [0.0; 2],
+            normal: [0.0; 3],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+            color: [1.0; 3],
+            tex_coords1: [0.0; 2],
+        }
+    }
+
+    // A cube the way an OBJ with UVs but no normals loads: 4 vertices per side, sharing positions at the corners.
+    // Returns the direction each side faces too.
+    fn cube() -> (Vec<ModelVertex>, Vec<u32>, Vec<Vector3<f32>>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut sides = Vec::new();
+
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+            for normal in [axis, -axis] {
+                // u × v = normal, so the corners go counter-clockwise seen from outside
+                let u = Vector3::new(normal.y, normal.z, normal.x);
+                let v = normal.cross(u);
+
+                let first = vertices.len() as u32;
+                for corner in [-u - v, u - v, u + v, -u + v] {
+                    vertices.push(vertex(normal + corner));
+                }
+                indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+                sides.extend([normal; 4]);
+            }
+        }
+
+        (vertices, indices, sides)
+    }
+
+    #[test]
+    fn index_format_widens_past_u16() {
+        assert_eq!(index_format(3), IndexFormat::Uint16);
+        assert_eq!(index_format(65_535), IndexFormat::Uint16);
+        // The last vertex is index 65535, which still fits
+        assert_eq!(index_format(65_536), IndexFormat::Uint16);
+        assert_eq!(index_format(65_537), IndexFormat::Uint32);
+    }
+
+    #[test]
+    fn cube_sides_stay_flat() {
+        let (mut vertices, indices, sides) = cube();
+        compute_normals(&mut vertices, &indices);
+
+        for (vertex, side) in vertices.iter().zip(sides) {
+            let normal = Vector3::from(vertex.normal);
+            assert!(
+                normal.dot(side) > 0.99,
+                "vertex at {:?} got {:?} on the side facing {:?}",
+                vertex.position,
+                normal,
+                side
+            );
+        }
+    }
+    #[test]
+    fn subdivided_cube_sides_stay_flat() {
+        let (mut vertices, mut indices, _) = cube();
+        compute_normals(&mut vertices, &indices);
+        for _ in 0..2 {
+            (vertices, indices) = split_triangles(&vertices, &indices);
+        }
+
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|k| Vector3::from(vertices[triangle[k] as usize].position));
+            let face = (b - a).cross(c - a).normalize();
+
+            for &i in triangle {
+                let normal = Vector3::from(vertices[i as usize].normal);
+                assert!(
+                    normal.dot(face) > 0.99,
+                    "vertex at {:?} got {:?} on a triangle facing {:?}",
+                    vertices[i as usize].position,
+                    normal,
+                    face
+                );
+            }
+        }
+    }
+}
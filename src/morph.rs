@@ -0,0 +1,303 @@
+use std::{mem::size_of, ops::Range, time::Duration};
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferAddress, BufferBindingType,
+    BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+    DepthStencilState, Device, Face, FragmentState, FrontFace, MultisampleState, PipelineLayout,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPass,
+    RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderStages, StencilState,
+    VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+use crate::{
+    instance::InstanceRaw,
+    layouts::BindGroupLayouts,
+    model::{Model, ModelVertex, Vertex},
+    resources::DrawModel,
+    scene_pipeline::ScenePipelineConfig,
+};
+
+// How far the weight moves per second while animating.
+const MORPH_SPEED: f32 = 0.5;
+
+// The parts of a vertex that change between the base mesh and the morph target.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct MorphVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Vertex for MorphVertex {
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        const ATTRIBS: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![9 => Float32x3, 10 => Float32x3];
+
+        VertexBufferLayout {
+            array_stride: size_of::<MorphVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct MorphUniform {
+    weight: f32,
+    // Uniforms need to be 16 byte aligned
+    _padding: [f32; 3],
+}
+
+// Blends a model towards a second shape with the same vertices (a blend shape).
+pub struct Morph {
+    // One buffer per mesh of the base model, bound next to its vertex buffer
+    targets: Vec<Buffer>,
+    uniform: MorphUniform,
+    buffer: Buffer,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count, depth test, culling, sidedness or depth bias changes
+    layout: PipelineLayout,
+    pipeline_config: ScenePipelineConfig,
+    animating: bool,
+    // +1 while moving towards the target, -1 while moving back
+    direction: f32,
+}
+
+impl Morph {
+    pub fn new(
+        device: &Device,
+        shader: &ShaderModule,
+        pipeline_config: ScenePipelineConfig,
+        layouts: &BindGroupLayouts,
+        targets: Vec<Buffer>,
+    ) -> Self {
+        let uniform = MorphUniform {
+            weight: 0.0,
+            _padding: [0.0; 3],
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Morph Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("morph_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("morph_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Morph Pipeline Layout"),
+            bind_group_layouts: &[
+                &layouts.texture,
+                &layouts.camera,
+                &layouts.light,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &layout, shader, &pipeline_config);
+
+        Self {
+            targets,
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+            layout,
+            pipeline_config,
+            animating: false,
+            direction: 1.0,
+        }
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    // `shader` is the main shader the pipeline was created with.
+    pub fn set_sample_count(&mut self, device: &Device, shader: &ShaderModule, sample_count: u32) {
+        self.pipeline_config.sample_count = sample_count;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to test depth with `depth_compare`, e.g. Greater for reversed-Z.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &Device,
+        shader: &ShaderModule,
+        depth_compare: CompareFunction,
+    ) {
+        self.pipeline_config.depth_compare = depth_compare;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to cull `cull_mode`, e.g. None to see inside a mesh.
+    pub fn set_cull_mode(
+        &mut self,
+        device: &Device,
+        shader: &ShaderModule,
+        cull_mode: Option<Face>,
+    ) {
+        self.pipeline_config.cull_mode = cull_mode;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to draw and light both sides, like the main pipeline's double sided mode.
+    pub fn set_double_sided(&mut self, device: &Device, shader: &ShaderModule, double_sided: bool) {
+        self.pipeline_config.double_sided = double_sided;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to shift depth by `depth_bias`, see State::set_depth_bias.
+    pub fn set_depth_bias(
+        &mut self,
+        device: &Device,
+        shader: &ShaderModule,
+        depth_bias: DepthBiasState,
+    ) {
+        self.pipeline_config.depth_bias = depth_bias;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    fn rebuild_pipeline(&mut self, device: &Device, shader: &ShaderModule) {
+        self.pipeline = create_pipeline(device, &self.layout, shader, &self.pipeline_config);
+    }
+
+    // Nothing to blend while the weight sits at the base mesh.
+    pub fn is_active(&self) -> bool {
+        self.animating || self.uniform.weight > 0.0
+    }
+
+    pub fn set_weight(&mut self, queue: &Queue, weight: f32) {
+        self.uniform.weight = weight.clamp(0.0, 1.0);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    // Starts or stops the weight moving back and forth. Stopping keeps the current weight.
+    pub fn toggle_animation(&mut self) {
+        self.animating = !self.animating;
+    }
+
+    pub fn update(&mut self, queue: &Queue, dt: Duration) {
+        if !self.animating {
+            return;
+        }
+
+        let weight = self.uniform.weight + self.direction * MORPH_SPEED * dt.as_secs_f32();
+
+        // Bounce off either end
+        if weight >= 1.0 {
+            self.direction = -1.0;
+        } else if weight <= 0.0 {
+            self.direction = 1.0;
+        }
+
+        self.set_weight(queue, weight);
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+
+        for (mesh, target) in model.meshes.iter().zip(&self.targets) {
+            render_pass.set_vertex_buffer(2, target.slice(..));
+            render_pass.draw_mesh_instanced(
+                mesh,
+                &model.materials[mesh.material],
+                instances.clone(),
+                camera_bind_group,
+            );
+        }
+    }
+}
+
+// Same as the main render pipeline, apart from the vertex entry point and the extra buffer.
+// Always an opaque, filled triangle list, whatever `config` says about those.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &ScenePipelineConfig,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Morph Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_morph",
+            buffers: &[
+                ModelVertex::desc(),
+                InstanceRaw::desc(),
+                MorphVertex::desc(),
+            ],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: if config.double_sided {
+                "fs_double_sided"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(ColorTargetState {
+                format: config.format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: if config.double_sided {
+                None
+            } else {
+                config.cull_mode
+            },
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: config.depth_format,
+            depth_write_enabled: true,
+            depth_compare: config.depth_compare,
+            stencil: StencilState::default(),
+            bias: config.depth_bias,
+        }),
+        multisample: MultisampleState {
+            count: config.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,56 @@
+use std::{mem::size_of, ops::Range};
+
+use bytemuck::cast_slice;
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::{PushConstantRange, RenderPass, ShaderStages};
+
+// A mat4x4<f32>, the most push constant space every adapter that has them guarantees
+pub const PUSH_CONSTANT_SIZE: u32 = size_of::<[[f32; 4]; 4]>() as u32;
+
+const PUSH_CONSTANT_RANGES: [PushConstantRange; 1] = [PushConstantRange {
+    stages: ShaderStages::VERTEX,
+    range: Range {
+        start: 0,
+        end: PUSH_CONSTANT_SIZE,
+    },
+}];
+
+// Without push constants there's nowhere to put a per draw transform, since every bind group is taken.
+// Objects fold theirs into their instance data instead, so the shaders leave positions alone.
+const IDENTITY_WGSL: &str = "
+fn object_transform() -> mat4x4<f32> {
+    return mat4x4<f32>(
+        vec4<f32>(1.0, 0.0, 0.0, 0.0),
+        vec4<f32>(0.0, 1.0, 0.0, 0.0),
+        vec4<f32>(0.0, 0.0, 1.0, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+}
+";
+
+// Puts `object_transform()` in front of a shader's source, read from push constants when the device has them.
+pub fn with_object_transform(source: &str, push_constants: bool) -> String {
+    let prelude = if push_constants {
+        include_str!("object_transform.wgsl")
+    } else {
+        IDENTITY_WGSL
+    };
+
+    format!("{}\n{}", prelude, source)
+}
+
+// What the layout of a pipeline whose shader went through `with_object_transform` needs.
+pub fn push_constant_ranges(push_constants: bool) -> &'static [PushConstantRange] {
+    if push_constants {
+        &PUSH_CONSTANT_RANGES
+    } else {
+        &[]
+    }
+}
+
+// Moves the draws after it by `transform`, until another is pushed or the pipeline layout changes.
+// Only for pipelines made with push constants.
+pub fn push(render_pass: &mut RenderPass, transform: Matrix4<f32>) {
+    let offset: [[f32; 4]; 4] = (transform - Matrix4::identity()).into();
+    render_pass.set_push_constants(ShaderStages::VERTEX, 0, cast_slice(&offset));
+}
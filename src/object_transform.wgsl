@@ -0,0 +1,19 @@
+// Placement of a whole model on top of its instances' own matrices, set per draw by object_transform.rs.
+// It's pushed as its difference from the identity matrix, so a draw that never pushes one sees zeroes
+// and stays where its instances put it.
+
+struct ObjectTransform {
+    offset: mat4x4<f32>,
+}
+
+var<push_constant> object_transform_offset: ObjectTransform;
+
+fn object_transform() -> mat4x4<f32> {
+    let identity = mat4x4<f32>(
+        vec4<f32>(1.0, 0.0, 0.0, 0.0),
+        vec4<f32>(0.0, 1.0, 0.0, 0.0),
+        vec4<f32>(0.0, 0.0, 1.0, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+    return object_transform_offset.offset + identity;
+}
@@ -0,0 +1,248 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferUsages, ColorTargetState,
+    ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device, FragmentState,
+    FrontFace, MultisampleState, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, Queue, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilFaceState,
+    StencilOperation, StencilState, TextureFormat, VertexState,
+};
+
+use crate::{
+    instance::InstanceRaw,
+    model::{ModelVertex, Vertex},
+};
+
+// Stencil value written wherever the selected object covers the screen.
+pub const OUTLINE_STENCIL_REFERENCE: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    width: f32,
+    // Uniforms need to be 16 byte aligned
+    _padding: [f32; 3],
+}
+
+// Draws a colored outline around a single instance in two steps:
+// 1. The mask pipeline draws the object into the stencil buffer only.
+// 2. The outline pipeline draws a scaled up copy wherever the stencil was NOT written, leaving just the rim.
+pub struct Outline {
+    uniform: OutlineUniform,
+    buffer: Buffer,
+    pub bind_group: BindGroup,
+    pub mask_pipeline: RenderPipeline,
+    pub outline_pipeline: RenderPipeline,
+    // Kept to rebuild the pipelines when the sample count changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+}
+
+impl Outline {
+    pub fn new(
+        device: &Device,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+        color: [f32; 4],
+    ) -> Self {
+        let uniform = OutlineUniform {
+            color,
+            width: 0.08,
+            _padding: [0.0; 3],
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Outline Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("outline_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("outline_bind_group"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: ShaderSource::Wgsl(include_str!("outline.wgsl").into()),
+        });
+
+        // Camera stays in group 1 so it lines up with the main shader.
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (mask_pipeline, outline_pipeline) =
+            create_pipelines(device, &layout, &shader, color_format, depth_format, 1);
+
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+            mask_pipeline,
+            outline_pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+        }
+    }
+
+    // Rebuilds both pipelines to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        (self.mask_pipeline, self.outline_pipeline) = create_pipelines(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            sample_count,
+        );
+    }
+
+    pub fn set_color(&mut self, queue: &Queue, color: [f32; 4]) {
+        self.uniform.color = color;
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+}
+
+fn create_pipelines(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+) -> (RenderPipeline, RenderPipeline) {
+    // Always write the reference value, no matter what is in front of the object.
+    let mask_stencil = StencilFaceState {
+        compare: CompareFunction::Always,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Replace,
+        pass_op: StencilOperation::Replace,
+    };
+
+    let mask_pipeline = create_pipeline(
+        device,
+        layout,
+        shader,
+        "vs_mask",
+        "Outline Mask Pipeline",
+        color_format,
+        depth_format,
+        // Only the stencil buffer is written
+        ColorWrites::empty(),
+        mask_stencil,
+        sample_count,
+    );
+
+    // Only draw where the mask pipeline didn't, so the object itself stays visible.
+    let outline_stencil = StencilFaceState {
+        compare: CompareFunction::NotEqual,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Keep,
+        pass_op: StencilOperation::Keep,
+    };
+
+    let outline_pipeline = create_pipeline(
+        device,
+        layout,
+        shader,
+        "vs_outline",
+        "Outline Pipeline",
+        color_format,
+        depth_format,
+        ColorWrites::ALL,
+        outline_stencil,
+        sample_count,
+    );
+
+    (mask_pipeline, outline_pipeline)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    vs_entry_point: &str,
+    label: &str,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    write_mask: ColorWrites,
+    stencil_face: StencilFaceState,
+    sample_count: u32,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: vs_entry_point,
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                blend: None,
+                write_mask,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        // The outline should be visible even when the selected object is behind something.
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState {
+                front: stencil_face,
+                back: stencil_face,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
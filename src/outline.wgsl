@@ -0,0 +1,53 @@
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    // How much bigger the outline silhouette is than the object. 0.05 = 5%
+    width: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+}
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+// Draws the object as is. Only used to write the stencil mask.
+@vertex
+fn vs_mask(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * model_matrix(instance) * vec4<f32>(model.position, 1.0);
+}
+
+// Draws a slightly scaled up copy of the object. Scaling in model space keeps the outline even on all sides.
+@vertex
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let position = model.position * (1.0 + outline.width);
+    return camera.view_proj * model_matrix(instance) * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
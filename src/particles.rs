@@ -0,0 +1,433 @@
+use std::{mem::size_of, time::Duration};
+
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use cgmath::Vector3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState,
+    Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState,
+    ColorWrites, CommandEncoder, CompareFunction, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, DepthBiasState, DepthStencilState, Device, FragmentState, FrontFace,
+    MultisampleState, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModule,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureFormat,
+    VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+use crate::{
+    camera::{hash, Camera},
+    impostor::BillboardUniform,
+};
+
+// Particles alive at once. Spawning more than this reuses the oldest slots.
+pub const MAX_PARTICLES: u32 = 16384;
+// Has to match @workgroup_size in particles_compute.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+const GRAVITY: f32 = -9.8;
+// Seconds a particle lives for, give or take half
+const LIFETIME: f32 = 3.0;
+// Upward speed new particles get, give or take a third
+const LAUNCH_SPEED: f32 = 6.0;
+// Sideways speed new particles get at most
+const SPREAD: f32 = 2.0;
+
+// One particle in the storage buffer. Matches `Particle` in particles_compute.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+}
+
+impl Particle {
+    // The same buffer is read per instance by particles.wgsl, skipping the velocity.
+    fn desc<'a>() -> VertexBufferLayout<'a> {
+        const ATTRIBS: [wgpu::VertexAttribute; 3] = [
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            wgpu::VertexAttribute {
+                offset: 12,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32,
+            },
+            wgpu::VertexAttribute {
+                offset: 28,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32,
+            },
+        ];
+
+        VertexBufferLayout {
+            array_stride: size_of::<Particle>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &ATTRIBS,
+        }
+    }
+}
+
+// Matches `Simulation` in particles_compute.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct SimulationUniform {
+    dt: f32,
+    gravity: f32,
+    count: u32,
+    // Uniforms need to be 16 byte aligned
+    _padding: u32,
+}
+
+// Sparks that fly up from `origin`, fall and bounce off the ground.
+// A compute shader moves them in a storage buffer each frame, which the render pipeline then reads as
+// instances. Everything it uses is in plain WebGPU, but adapters without compute shaders (like WebGL2)
+// can't run it at all.
+pub struct Particles {
+    // Where new particles start
+    pub origin: Vector3<f32>,
+    particles: Buffer,
+    // Slots that have been spawned into. Only these get simulated and drawn.
+    count: u32,
+    // Slot the next particle goes in
+    next: u32,
+    // Particles spawned so far. Seeds each one's random velocity.
+    spawned: u32,
+    simulation: SimulationUniform,
+    simulation_buffer: Buffer,
+    compute_bind_group: BindGroup,
+    compute_pipeline: ComputePipeline,
+    billboard_buffer: Buffer,
+    render_bind_group: BindGroup,
+    render_pipeline: RenderPipeline,
+    // Kept to rebuild the render pipeline when the sample count or depth test changes
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+}
+
+impl Particles {
+    pub fn new(
+        device: &Device,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let particles = device.create_buffer(&BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (MAX_PARTICLES as usize * size_of::<Particle>()) as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let simulation = SimulationUniform {
+            dt: 0.0,
+            gravity: GRAVITY,
+            count: 0,
+            _padding: 0,
+        };
+
+        let simulation_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Particle Simulation Buffer"),
+            contents: bytes_of(&simulation),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("particle_compute_bind_group_layout"),
+            });
+
+        let compute_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &compute_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: simulation_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: particles.as_entire_binding(),
+                },
+            ],
+            label: Some("particle_compute_bind_group"),
+        });
+
+        let compute_shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Particle Compute Shader"),
+            source: ShaderSource::Wgsl(include_str!("particles_compute.wgsl").into()),
+        });
+
+        let compute_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Particle Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Particle Compute Pipeline"),
+            layout: Some(&compute_layout),
+            module: &compute_shader,
+            entry_point: "cs_main",
+        });
+
+        let billboard_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Particle Billboard Buffer"),
+            size: size_of::<BillboardUniform>() as BufferAddress,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("particle_render_bind_group_layout"),
+            });
+
+        let render_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &render_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: billboard_buffer.as_entire_binding(),
+            }],
+            label: Some("particle_render_bind_group"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: ShaderSource::Wgsl(include_str!("particles.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Particle Render Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &render_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let render_pipeline = create_render_pipeline(
+            device,
+            &layout,
+            &shader,
+            color_format,
+            depth_format,
+            1,
+            CompareFunction::Less,
+        );
+
+        Self {
+            origin: Vector3::new(0.0, 1.0, 0.0),
+            particles,
+            count: 0,
+            next: 0,
+            spawned: 0,
+            simulation,
+            simulation_buffer,
+            compute_bind_group,
+            compute_pipeline,
+            billboard_buffer,
+            render_bind_group,
+            render_pipeline,
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            sample_count: 1,
+            depth_compare: CompareFunction::Less,
+        }
+    }
+
+    // Rebuilds the render pipeline to draw into a pass with `sample_count` samples per pixel.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.rebuild_pipeline(device);
+    }
+
+    // Rebuilds the render pipeline to test depth with `depth_compare`, e.g. Greater for reversed-Z.
+    pub fn set_depth_compare(&mut self, device: &Device, depth_compare: CompareFunction) {
+        self.depth_compare = depth_compare;
+        self.rebuild_pipeline(device);
+    }
+
+    fn rebuild_pipeline(&mut self, device: &Device) {
+        self.render_pipeline = create_render_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.color_format,
+            self.depth_format,
+            self.sample_count,
+            self.depth_compare,
+        );
+    }
+
+    // Launches `n` particles from `origin`. Past MAX_PARTICLES the oldest ones make room.
+    pub fn spawn(&mut self, queue: &Queue, n: u32) {
+        let n = n.min(MAX_PARTICLES);
+        let new_particles = (0..n)
+            .map(|i| {
+                let seed = self.spawned.wrapping_add(i) as i32;
+                Particle {
+                    position: self.origin.into(),
+                    age: 0.0,
+                    velocity: [
+                        hash(3, 0, seed) * SPREAD,
+                        LAUNCH_SPEED * (1.0 + hash(3, 1, seed) / 3.0),
+                        hash(3, 2, seed) * SPREAD,
+                    ],
+                    lifetime: LIFETIME * (1.0 + hash(3, 3, seed) * 0.5),
+                }
+            })
+            .collect::<Vec<_>>();
+        self.spawned = self.spawned.wrapping_add(n);
+
+        // Written in up to two pieces when it wraps past the end of the buffer
+        let mut written = 0;
+        while written < n {
+            let chunk = (n - written).min(MAX_PARTICLES - self.next);
+            let range = written as usize..(written + chunk) as usize;
+            queue.write_buffer(
+                &self.particles,
+                (self.next as usize * size_of::<Particle>()) as BufferAddress,
+                cast_slice(&new_particles[range]),
+            );
+
+            written += chunk;
+            self.next = (self.next + chunk) % MAX_PARTICLES;
+        }
+
+        self.count = (self.count + n).min(MAX_PARTICLES);
+    }
+
+    pub fn update(&mut self, queue: &Queue, camera: &Camera, dt: Duration) {
+        self.simulation.dt = dt.as_secs_f32();
+        self.simulation.count = self.count;
+        queue.write_buffer(&self.simulation_buffer, 0, bytes_of(&self.simulation));
+
+        let billboard = BillboardUniform::new(camera);
+        queue.write_buffer(&self.billboard_buffer, 0, bytes_of(&billboard));
+    }
+
+    // Runs the simulation. Has to be encoded before the pass that draws them.
+    pub fn simulate(&self, encoder: &mut CommandEncoder) {
+        if self.count == 0 {
+            return;
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Particle Compute Pass"),
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, camera_bind_group: &'a BindGroup) {
+        if self.count == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.render_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.particles.slice(..));
+        render_pass.draw(0..6, 0..self.count);
+    }
+}
+
+// `sample_count` has to match the pass the particles are drawn in.
+fn create_render_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+    depth_compare: CompareFunction,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Particle Render Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Particle::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: color_format,
+                // Additive, so overlapping sparks glow brighter and the draw order doesn't matter
+                blend: Some(BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::SrcAlpha,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent::OVER,
+                }),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        // Hidden behind anything solid, but don't hide each other
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,80 @@
+// Draws the particles particles_compute.wgsl moves around, as small camera-facing quads.
+// The storage buffer the simulation writes to is bound here as a per-instance vertex buffer.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Camera basis vectors in world space. Only xyz is used.
+struct BillboardUniform {
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> billboard: BillboardUniform;
+
+struct ParticleInput {
+    @location(0) position: vec3<f32>,
+    @location(1) age: f32,
+    @location(2) lifetime: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1..1 across the quad
+    @location(0) corner: vec2<f32>,
+    // 1 when born, 0 when it dies
+    @location(1) life: f32,
+};
+
+// Width of a quad in world units
+let PARTICLE_SIZE: f32 = 0.08;
+let YOUNG_COLOR: vec3<f32> = vec3<f32>(1.0, 0.85, 0.4);
+let OLD_COLOR: vec3<f32> = vec3<f32>(0.9, 0.2, 0.05);
+
+// 6 vertices per particle. Dead ones collapse to a point.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    particle: ParticleInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index % 6u];
+
+    var out: VertexOutput;
+    out.corner = corner;
+    out.life = 1.0 - particle.age / particle.lifetime;
+
+    if (particle.age >= particle.lifetime) {
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        return out;
+    }
+
+    let offset = (billboard.right.xyz * corner.x + billboard.up.xyz * corner.y) * PARTICLE_SIZE * 0.5;
+    out.clip_position = camera.view_proj * vec4<f32>(particle.position + offset, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Round, with a soft edge
+    let edge = 1.0 - smoothstep(0.5, 1.0, length(in.corner));
+    if (edge <= 0.0) {
+        discard;
+    }
+
+    let color = mix(OLD_COLOR, YOUNG_COLOR, in.life);
+    return vec4<f32>(color, edge * in.life);
+}
@@ -0,0 +1,54 @@
+// Moves every particle forward by one frame. particles.wgsl draws them afterwards.
+
+// Matches `Particle` in particles.rs
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    // Seconds it lives for. Once age reaches it the particle stops moving and isn't drawn.
+    lifetime: f32,
+}
+
+struct Simulation {
+    // Seconds since the last frame
+    dt: f32,
+    gravity: f32,
+    // Slots in `particles` that have ever been spawned into
+    count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> simulation: Simulation;
+
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+// Where particles bounce. The bottom of the cubes.
+let GROUND_HEIGHT: f32 = -1.0;
+// How much of their speed they keep when they bounce
+let BOUNCE: f32 = 0.5;
+
+// Has to match WORKGROUP_SIZE in particles.rs
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= simulation.count) {
+        return;
+    }
+
+    var particle = particles[i];
+    if (particle.age >= particle.lifetime) {
+        return;
+    }
+
+    particle.velocity.y = particle.velocity.y + simulation.gravity * simulation.dt;
+    particle.position = particle.position + particle.velocity * simulation.dt;
+
+    if (particle.position.y < GROUND_HEIGHT && particle.velocity.y < 0.0) {
+        particle.position.y = GROUND_HEIGHT;
+        particle.velocity = particle.velocity * vec3<f32>(BOUNCE, -BOUNCE, BOUNCE);
+    }
+
+    particle.age = particle.age + simulation.dt;
+    particles[i] = particle;
+}
@@ -0,0 +1,279 @@
+use std::{num::NonZeroU32, ops::Range, sync::mpsc};
+
+use anyhow::*;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, CommandEncoderDescriptor, DepthBiasState, DepthStencilState,
+    Device, Extent3d, FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout,
+    IndexFormat, LoadOp, Maintain, MapMode, MultisampleState, Operations, Origin3d, PipelineLayout,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState,
+    TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor, VertexState, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::{
+    camera,
+    instance::InstanceRaw,
+    model::{ModelVertex, Vertex},
+    texture::Texture,
+};
+
+const ID_FORMAT: TextureFormat = TextureFormat::R32Uint;
+
+// Indexed geometry to draw once per instance in the ID pass.
+pub struct PickGeometry<'a> {
+    pub vertex_buffer: &'a Buffer,
+    pub index_buffer: &'a Buffer,
+    pub index_format: IndexFormat,
+    pub num_indices: u32,
+}
+
+// Finds what's under a pixel by drawing every instance's index into an integer texture and reading
+// the pixel back. Exact for any shape, unlike casting a ray against bounding spheres.
+pub struct ObjectIdPass {
+    texture: wgpu::Texture,
+    view: TextureView,
+    depth_texture: Texture,
+    depth_format: TextureFormat,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when reversed-Z is switched
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    // Has to match the camera, whose matrix the pass draws with
+    reversed_z: bool,
+}
+
+impl ObjectIdPass {
+    pub fn new(
+        device: &Device,
+        depth_format: TextureFormat,
+        camera_bind_group_layout: &BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let (texture, view) = create_id_texture(device, width, height);
+        let depth_texture = Texture::create_depth_texture_with_size(
+            device,
+            width,
+            height,
+            depth_format,
+            "object_id_depth_texture",
+        );
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Object ID Shader"),
+            source: ShaderSource::Wgsl(include_str!("picking.wgsl").into()),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Object ID Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &layout, &shader, depth_format, false);
+
+        Self {
+            texture,
+            view,
+            depth_texture,
+            depth_format,
+            pipeline,
+            shader,
+            layout,
+            reversed_z: false,
+        }
+    }
+
+    pub fn set_reversed_z(&mut self, device: &Device, reversed_z: bool) {
+        self.reversed_z = reversed_z;
+        self.pipeline = create_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            self.depth_format,
+            reversed_z,
+        );
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        (self.texture, self.view) = create_id_texture(device, width, height);
+        self.depth_texture = Texture::create_depth_texture_with_size(
+            device,
+            width,
+            height,
+            self.depth_format,
+            "object_id_depth_texture",
+        );
+    }
+
+    // Draws `geometry` for `instances` and returns the instance buffer slot covering pixel (x, y), if any.
+    // Blocks until the GPU is done, so it's meant for clicks rather than every frame.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pick(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        camera_bind_group: &BindGroup,
+        geometry: &[PickGeometry],
+        instance_buffer: &Buffer,
+        instances: Range<u32>,
+        x: u32,
+        y: u32,
+    ) -> Result<Option<u32>> {
+        let size = self.depth_texture.size();
+        if x >= size.0 || y >= size.1 {
+            return Ok(None);
+        }
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Object ID Readback Buffer"),
+            size: ID_FORMAT.describe().block_size as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Object ID Encoder"),
+        });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Object ID Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        // 0 means nothing was drawn there
+                        load: LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(camera::depth_clear_value(self.reversed_z)),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            // Only the one pixel gets read, so nothing else needs drawing
+            render_pass.set_scissor_rect(x, y, 1, 1);
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+            for geometry in geometry {
+                render_pass.set_vertex_buffer(0, geometry.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(geometry.index_buffer.slice(..), geometry.index_format);
+                render_pass.draw_indexed(0..geometry.num_indices, 0, instances.clone());
+            }
+        }
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d { x, y, z: 0 },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    // A single row, but it still has to be a multiple of the alignment
+                    bytes_per_row: NonZeroU32::new(COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        device.poll(Maintain::Wait);
+        receiver.recv()??;
+
+        let id = {
+            let view = slice.get_mapped_range();
+            u32::from_ne_bytes([view[0], view[1], view[2], view[3]])
+        };
+        buffer.unmap();
+
+        Ok(id.checked_sub(1))
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    depth_format: TextureFormat,
+    reversed_z: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Object ID Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: ID_FORMAT,
+                // Integer formats can't be blended
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        // No culling, so whatever the scene pipelines are set to cull, anything on screen can be picked
+        primitive: PrimitiveState::default(),
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare: camera::depth_compare(reversed_z),
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Needs COPY_SRC so the picked pixel can be read back.
+fn create_id_texture(device: &Device, width: u32, height: u32) -> (wgpu::Texture, TextureView) {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("object_id_texture"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: ID_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+    });
+
+    let view = texture.create_view(&TextureViewDescriptor::default());
+
+    (texture, view)
+}
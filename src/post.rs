@@ -0,0 +1,119 @@
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutEntry, BindingType, BlendState, ColorTargetState, ColorWrites,
+    Device, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState,
+    RenderPipeline, RenderPipelineDescriptor, SamplerBindingType, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, TextureAspect, TextureFormat, TextureSampleType, TextureView,
+    TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::texture::Texture;
+
+// Post-processing passes run a fragment shader over every pixel of the screen.
+// They draw a single fullscreen triangle (`render_pass.draw(0..3, 0..1)`) using the vertex stage in fullscreen.wgsl.
+
+// Builds a pipeline for `source`, which needs an `fs_main` entry point taking `@location(0) uv: vec2<f32>`.
+pub fn create_fullscreen_pipeline(
+    device: &Device,
+    label: &str,
+    source: &str,
+    bind_group_layouts: &[&BindGroupLayout],
+    format: TextureFormat,
+    blend: Option<BlendState>,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(
+            format!("{}\n{}", include_str!("fullscreen.wgsl"), source).into(),
+        ),
+    });
+
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format,
+                blend,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        // The defaults are a triangle list with no culling
+        primitive: PrimitiveState::default(),
+        // Post-processing doesn't need depth testing
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Layout entry for a filterable color texture, e.g. the rendered scene.
+pub fn texture_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture {
+            multisampled: false,
+            view_dimension: TextureViewDimension::D2,
+            sample_type: TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+pub fn sampler_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+        count: None,
+    }
+}
+
+// Layout entry for the depth buffer. Read with `textureLoad` since depth textures can't be filtered.
+pub fn depth_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture {
+            multisampled: false,
+            view_dimension: TextureViewDimension::D2,
+            sample_type: TextureSampleType::Depth,
+        },
+        count: None,
+    }
+}
+
+pub fn uniform_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// The depth texture also has a stencil aspect, and shaders can only see one aspect at a time.
+pub fn depth_view(depth_texture: &Texture) -> TextureView {
+    depth_texture.texture.create_view(&TextureViewDescriptor {
+        label: Some("depth_only_view"),
+        aspect: TextureAspect::DepthOnly,
+        ..Default::default()
+    })
+}
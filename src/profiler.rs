@@ -0,0 +1,242 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use wgpu::{
+    Buffer, BufferAddress, BufferAsyncError, BufferDescriptor, BufferUsages, CommandBuffer,
+    CommandEncoder, CommandEncoderDescriptor, Device, Features, Maintain, MapMode, QuerySet,
+    QuerySetDescriptor, QueryType, Queue, QUERY_SIZE,
+};
+
+// How often new timings are read back. Reading every frame makes the numbers too jumpy to follow.
+const READ_INTERVAL: Duration = Duration::from_millis(250);
+
+// Passes that get their own timing. Each one owns a start and end query.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpuPass {
+    Main,
+    Ssao,
+    DepthOfField,
+}
+
+impl GpuPass {
+    pub const ALL: [GpuPass; 3] = [GpuPass::Main, GpuPass::Ssao, GpuPass::DepthOfField];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GpuPass::Main => "main",
+            GpuPass::Ssao => "ssao",
+            GpuPass::DepthOfField => "dof",
+        }
+    }
+
+    fn start_query(&self) -> u32 {
+        *self as u32 * 2
+    }
+}
+
+// How long each pass took on the GPU, in the order they ran.
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    pub passes: Vec<(GpuPass, Duration)>,
+}
+
+impl fmt::Display for RenderStats {
+    // e.g. "main: 2.10ms, ssao: 0.80ms"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (pass, duration)) in self.passes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}: {:.2}ms",
+                pass.name(),
+                duration.as_secs_f64() * 1000.0
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Counts frames to work out the frame rate, averaged over a second at a time.
+pub struct FrameTimer {
+    start: Instant,
+    frames: u32,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    // Call once per frame. Every second, returns the frames per second and average milliseconds per frame.
+    pub fn tick(&mut self) -> Option<(f32, f32)> {
+        self.frames += 1;
+
+        let elapsed = self.start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs_f32();
+        let fps = self.frames as f32 / seconds;
+        let ms = seconds * 1000.0 / self.frames as f32;
+
+        self.start = Instant::now();
+        self.frames = 0;
+
+        Some((fps, ms))
+    }
+}
+
+// Measures GPU time per pass with timestamp queries.
+pub struct GpuTimer {
+    enabled: bool,
+    query_set: QuerySet,
+    // Timestamps get resolved straight into this so the CPU can map it
+    read_buffer: Buffer,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    // Passes timed in the frame that is being read back
+    timed: Vec<GpuPass>,
+    // Set from the map_async callback once the readback finishes
+    map_result: Arc<Mutex<Option<Result<(), BufferAsyncError>>>>,
+    // A readback is in flight, so the buffers can't be written
+    waiting: bool,
+    last_read: Instant,
+}
+
+impl GpuTimer {
+    // Returns None when the device wasn't created with TIMESTAMP_QUERY.
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let count = GpuPass::ALL.len() as u32 * 2;
+
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("Timestamp Query Set"),
+            ty: QueryType::Timestamp,
+            count,
+        });
+
+        let size = (count * QUERY_SIZE) as BufferAddress;
+
+        let read_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Timestamp Read Buffer"),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            enabled: false,
+            query_set,
+            read_buffer,
+            period: queue.get_timestamp_period(),
+            timed: Vec::new(),
+            map_result: Arc::new(Mutex::new(None)),
+            waiting: false,
+            last_read: Instant::now(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    // Whether this frame should write timestamps. Checked before encoding.
+    pub fn recording(&self) -> bool {
+        self.enabled && !self.waiting && self.last_read.elapsed() >= READ_INTERVAL
+    }
+
+    pub fn begin(&self, encoder: &mut CommandEncoder, pass: GpuPass) {
+        encoder.write_timestamp(&self.query_set, pass.start_query());
+    }
+
+    pub fn end(&self, encoder: &mut CommandEncoder, pass: GpuPass) {
+        encoder.write_timestamp(&self.query_set, pass.start_query() + 1);
+    }
+
+    // Copies the timestamps of `timed` somewhere the CPU can read. Submit it after the frame's other commands.
+    pub fn resolve(&mut self, device: &Device, timed: Vec<GpuPass>) -> CommandBuffer {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Timestamp Resolve Encoder"),
+        });
+
+        // Only the passes that ran wrote their queries
+        for pass in &timed {
+            let start = pass.start_query();
+            encoder.resolve_query_set(
+                &self.query_set,
+                start..start + 2,
+                &self.read_buffer,
+                (start * QUERY_SIZE) as BufferAddress,
+            );
+        }
+
+        self.timed = timed;
+        encoder.finish()
+    }
+
+    // Call after submitting the buffer from `resolve`.
+    pub fn map(&mut self) {
+        let map_result = self.map_result.clone();
+        self.read_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                *map_result.lock().unwrap() = Some(result);
+            });
+
+        self.waiting = true;
+        self.last_read = Instant::now();
+    }
+
+    // Returns new stats once the GPU has finished a timed frame.
+    pub fn poll(&mut self, device: &Device) -> Option<RenderStats> {
+        if !self.waiting {
+            return None;
+        }
+
+        device.poll(Maintain::Poll);
+
+        let result = self.map_result.lock().unwrap().take()?;
+        if let Err(e) = result {
+            log::warn!("Couldn't read back GPU timestamps: {}", e);
+            self.waiting = false;
+            return None;
+        }
+
+        let timestamps = {
+            let view = self.read_buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice::<u8, u64>(&view).to_vec()
+        };
+        self.read_buffer.unmap();
+        self.waiting = false;
+
+        let passes = self
+            .timed
+            .iter()
+            .map(|&pass| {
+                let start = timestamps[pass.start_query() as usize];
+                let end = timestamps[pass.start_query() as usize + 1];
+                let nanos = end.saturating_sub(start) as f64 * self.period as f64;
+                (pass, Duration::from_nanos(nanos as u64))
+            })
+            .collect();
+
+        Some(RenderStats { passes })
+    }
+}
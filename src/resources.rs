@@ -1,49 +1,224 @@
 use std::{
+    env,
     io::{BufReader, Cursor},
     ops::Range,
+    path::{Path, PathBuf},
 };
 
-use wgpu::util::DeviceExt;
+use anyhow::Context;
+use cgmath::{Matrix4, MetricSpace, Point3};
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::{util::DeviceExt, FrontFace};
 
 use crate::{
-    model::{Material, Mesh, Model, ModelVertex},
-    texture::Texture,
+    camera_path::CameraPath,
+    gltf,
+    layouts::BindGroupLayouts,
+    model::{
+        compute_normals, compute_tangents, detect_front_face, flip_winding, Material, Mesh, Model,
+        ModelVertex, Specular,
+    },
+    morph::MorphVertex,
+    object_transform,
+    texture::{SamplerConfig, Texture},
 };
 
+// Set to a directory to load assets from there instead of the defaults below
+pub const ASSETS_DIR_VAR: &str = "WGPU_LEARNING_ASSETS";
+
+// Where every loader finds an asset. Checks, in order:
+// - the directory in ASSETS_DIR_VAR
+// - a res directory next to the executable, for a build copied somewhere with its assets
+// - the copy of res the build script made, which is what `cargo run` uses
+// Assets are read with std::fs, so this is for native builds only.
+pub fn resolve_path(file_name: impl AsRef<Path>) -> PathBuf {
+    assets_dir().join(file_name)
+}
+
+fn assets_dir() -> PathBuf {
+    if let Some(dir) = env::var_os(ASSETS_DIR_VAR) {
+        return PathBuf::from(dir);
+    }
+
+    let beside_exe = env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("res")))
+        .filter(|dir| dir.is_dir());
+
+    beside_exe.unwrap_or_else(|| Path::new(env!("OUT_DIR")).join("res"))
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
+    let path = resolve_path(file_name);
 
-    let txt = std::fs::read_to_string(path)?;
+    let txt = std::fs::read_to_string(&path).with_context(|| path.display().to_string())?;
 
     Ok(txt)
 }
 
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    let data = std::fs::read(path)?;
+pub async fn load_binary(file_name: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+    let path = resolve_path(file_name);
+    let data = std::fs::read(&path).with_context(|| path.display().to_string())?;
 
     Ok(data)
 }
 
 pub async fn load_texture(
     file_name: &str,
+    compress: bool,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<Texture> {
     let data = load_binary(file_name).await?;
-    Texture::from_bytes(device, queue, &data, file_name)
+
+    // DDS files are already block compressed, so `compress` doesn't apply
+    if Path::new(file_name)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("dds"))
+    {
+        return Texture::from_dds(device, queue, &data, file_name);
+    }
+
+    Texture::from_bytes(
+        device,
+        queue,
+        &data,
+        file_name,
+        compress,
+        true,
+        false,
+        SamplerConfig::DEFAULT,
+    )
 }
 
+// A Radiance .hdr image at full range, e.g. an environment to turn into a cubemap.
+pub async fn load_hdr_texture(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let data = load_binary(file_name).await?;
+    Texture::from_hdr(device, queue, &data, file_name)
+}
+
+pub async fn load_normal_map(
+    file_name: &str,
+    compress: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let data = load_binary(file_name).await?;
+    Texture::from_bytes(
+        device,
+        queue,
+        &data,
+        file_name,
+        compress,
+        true,
+        true,
+        SamplerConfig::DEFAULT,
+    )
+}
+
+// A normal map that leaves the surface normal alone. Stands in for materials that don't have one.
+pub fn flat_normal_map(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Texture> {
+    let flat = RgbaImage::from_pixel(1, 1, Rgba([128, 128, 255, 255]));
+    Texture::from_image(
+        device,
+        queue,
+        &DynamicImage::ImageRgba8(flat),
+        Some("flat_normal_map"),
+        false,
+        false,
+        true,
+        SamplerConfig::DEFAULT,
+    )
+}
+
+pub async fn load_camera_path(file_name: &str) -> anyhow::Result<CameraPath> {
+    let json = load_string(file_name).await?;
+    CameraPath::from_json(&json)
+}
+
+// Loads a material's diffuse texture. Instances tint it in the shader.
+// Also returns whether any of it is see-through.
+pub async fn load_material_texture(
+    file_name: &str,
+    compress: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<(Texture, bool)> {
+    let data = load_binary(file_name).await?;
+    let img = image::load_from_memory(&data)?.to_rgba8();
+    let transparent = img.pixels().any(|pixel| pixel[3] < u8::MAX);
+
+    Ok((
+        material_texture(img, file_name, compress, device, queue)?,
+        transparent,
+    ))
+}
+
+// An MTL color like "1.0 0.5 0.0". A single number is used for all three channels.
+fn parse_mtl_color(value: &str) -> Option<[f32; 3]> {
+    let values = value
+        .split_whitespace()
+        .map(|v| v.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    match values[..] {
+        [v] => Some([v; 3]),
+        [r, g, b] => Some([r, g, b]),
+        _ => None,
+    }
+}
+
+// A material texture of a single pixel of `color`.
+fn solid_color_texture(
+    color: [f32; 3],
+    label: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8);
+    let pixel = RgbaImage::from_pixel(1, 1, Rgba([r, g, b, u8::MAX]));
+    material_texture(pixel, label, false, device, queue)
+}
+
+fn material_texture(
+    img: RgbaImage,
+    label: &str,
+    compress: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    Texture::from_image(
+        device,
+        queue,
+        &DynamicImage::ImageRgba8(img),
+        Some(label),
+        compress,
+        true,
+        false,
+        SamplerConfig::DEFAULT,
+    )
+}
+
+// `compress_textures` is passed on to `load_material_texture` for every material.
+// .gltf and .glb files go through `load_gltf`, anything else is read as an OBJ.
+// `front_face` is the winding an OBJ's triangles use. Clockwise meshes get flipped to the counter-clockwise
+// the pipelines cull with. None works it out per mesh from the normals. glTF is always counter-clockwise.
 pub async fn load_model(
     file_name: &str,
+    compress_textures: bool,
+    front_face: Option<FrontFace>,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
+    layouts: &BindGroupLayouts,
 ) -> anyhow::Result<Model> {
+    if file_name.ends_with(".gltf") || file_name.ends_with(".glb") {
+        return load_gltf(file_name, compress_textures, device, queue, layouts).await;
+    }
+
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -56,87 +231,466 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            match load_string(&p).await {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                Err(_) => Err(tobj::LoadError::OpenFileFailed),
+            }
         },
     )
     .await?;
 
+    // Without the .mtl every mesh gets the default material below
+    let obj_materials = obj_materials.unwrap_or_else(|e| {
+        log::warn!("Couldn't load the materials of {}: {}", file_name, e);
+        Vec::new()
+    });
+
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
+    for m in obj_materials {
+        // Models exported without textures are drawn in their diffuse color instead
+        let (diffuse_texture, transparent) = if m.diffuse_texture.is_empty() {
+            (
+                solid_color_texture(m.diffuse, &m.name, device, queue)?,
+                false,
+            )
+        } else {
+            match load_material_texture(&m.diffuse_texture, compress_textures, device, queue).await
+            {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    log::warn!(
+                        "Couldn't load {} for {}, using its diffuse color: {}",
+                        m.diffuse_texture,
+                        m.name,
+                        e
+                    );
+                    (
+                        solid_color_texture(m.diffuse, &m.name, device, queue)?,
+                        false,
+                    )
+                }
+            }
+        };
+        let normal_texture = if m.normal_texture.is_empty() {
+            flat_normal_map(device, queue)?
+        } else {
+            load_normal_map(&m.normal_texture, compress_textures, device, queue).await?
+        };
+
+        // tobj doesn't know Ke or map_Ke, so they end up with the unknown parameters
+        let emissive_color = match m.unknown_param.get("Ke") {
+            Some(ke) => Some(
+                parse_mtl_color(ke)
+                    .with_context(|| format!("Ke of {} isn't three numbers: {}", m.name, ke))?,
+            ),
+            None => None,
+        };
+        let emissive_map = match m.unknown_param.get("map_Ke") {
+            Some(map_ke) => match load_texture(map_ke, compress_textures, device, queue).await {
+                Ok(texture) => Some(texture),
+                Err(e) => {
+                    log::warn!(
+                        "Couldn't load {} for {}, ignoring it: {}",
+                        map_ke,
+                        m.name,
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        // A map on its own glows as bright as it is
+        let (emissive, emissive_texture) = match emissive_map {
+            Some(texture) => (emissive_color.unwrap_or([1.0; 3]), texture),
+            None => (
+                emissive_color.unwrap_or([0.0; 3]),
+                Texture::from_color(device, queue, [u8::MAX; 4])?,
+            ),
+        };
 
-        materials.push(Material {
-            name: m.name,
+        materials.push(Material::new(
+            device,
+            layouts,
+            m.name,
             diffuse_texture,
-            bind_group,
-        })
+            normal_texture,
+            transparent,
+            Specular::from_mtl(m.specular, m.shininess),
+            emissive,
+            emissive_texture,
+            Texture::from_color(device, queue, [u8::MAX; 4])?,
+        ));
     }
 
+    // Meshes without a usable material share a plain white one
+    let default_material = materials.len();
+    let mut uses_default_material = false;
+
     let meshes = models
         .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+        .map(|mut m| {
+            // Filled in by compute_normals once the winding is settled
+            let has_normals = !m.mesh.normals.is_empty();
+            // Meshes without UVs sample the corner of the texture everywhere
+            let tex_coords = |i: usize| {
+                if m.mesh.texcoords.is_empty() {
+                    [0.0; 2]
+                } else {
+                    [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]]
+                }
+            };
+            let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| ModelVertex {
                     position: [
                         m.mesh.positions[i * 3],
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+                    tex_coords: tex_coords(i),
+                    normal: if has_normals {
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    } else {
+                        [0.0; 3]
+                    },
+                    // Filled in by compute_tangents
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
+                    // Only there when the OBJ has extra values after each position
+                    color: if m.mesh.vertex_color.is_empty() {
+                        [1.0; 3]
+                    } else {
+                        [
+                            m.mesh.vertex_color[i * 3],
+                            m.mesh.vertex_color[i * 3 + 1],
+                            m.mesh.vertex_color[i * 3 + 2],
+                        ]
+                    },
+                    // OBJ only has one set of UVs
+                    tex_coords1: tex_coords(i),
                 })
                 .collect::<Vec<_>>();
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+            // Without normals there's nothing to tell the winding by, so it's taken to be counter-clockwise
+            let front_face = front_face.unwrap_or_else(|| {
+                if has_normals {
+                    detect_front_face(&vertices, &m.mesh.indices)
+                } else {
+                    FrontFace::Ccw
+                }
             });
+            if front_face == FrontFace::Cw {
+                log::info!(
+                    "Flipping the clockwise triangles of {} in {}",
+                    m.name,
+                    file_name
+                );
+                flip_winding(&mut m.mesh.indices);
+            }
 
-            Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+            if !has_normals {
+                log::info!("Computing normals for {} in {}", m.name, file_name);
+                compute_normals(&mut vertices, &m.mesh.indices);
             }
+
+            compute_tangents(&mut vertices, &m.mesh.indices);
+
+            let material = match m.mesh.material_id {
+                Some(material) if material < default_material => material,
+                _ => {
+                    uses_default_material = true;
+                    default_material
+                }
+            };
+
+            Mesh::new(device, &m.name, vertices, m.mesh.indices, material)
         })
         .collect::<Vec<_>>();
 
-    Ok(Model { meshes, materials })
+    if uses_default_material {
+        materials.push(Material::new(
+            device,
+            layouts,
+            "default".into(),
+            solid_color_texture([1.0; 3], "default", device, queue)?,
+            flat_normal_map(device, queue)?,
+            false,
+            Specular::DEFAULT,
+            [0.0; 3],
+            Texture::from_color(device, queue, [u8::MAX; 4])?,
+            Texture::from_color(device, queue, [u8::MAX; 4])?,
+        ));
+    }
+
+    Ok(Model::new(meshes, materials))
+}
+
+// Loads the first mesh of a .gltf or .glb into the same kind of Model load_model makes.
+// Each primitive becomes a Mesh. Node transforms, skins and animations are ignored.
+pub async fn load_gltf(
+    file_name: &str,
+    compress_textures: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layouts: &BindGroupLayouts,
+) -> anyhow::Result<Model> {
+    let (document, bin) = gltf::parse(&load_binary(file_name).await?)?;
+
+    // Files the glTF refers to are next to it
+    let directory = Path::new(file_name).parent().unwrap_or(Path::new(""));
+
+    let mut buffers = Vec::new();
+    for buffer in &document.buffers {
+        buffers.push(match &buffer.uri {
+            Some(uri) => load_gltf_uri(directory, uri).await?,
+            None => bin
+                .clone()
+                .context("Buffer has no uri and there's no .glb binary chunk")?,
+        });
+    }
+
+    let mut materials = Vec::new();
+    for (i, m) in document.materials.iter().enumerate() {
+        let pbr = m.pbr_metallic_roughness.as_ref();
+        let name = m
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{} material {}", file_name, i));
+
+        let mut image = match pbr
+            .and_then(|pbr| pbr.base_color_texture.as_ref())
+            .and_then(|info| document.texture_image(info))
+        {
+            Some(image) => {
+                let data = load_gltf_image(&document, &buffers, directory, image).await?;
+                image::load_from_memory(&data)?.to_rgba8()
+            }
+            // Untextured materials are just the factor's color
+            None => RgbaImage::from_pixel(1, 1, Rgba([u8::MAX; 4])),
+        };
+
+        // The texture is multiplied by the factor, like glTF viewers do in the shader
+        let factor = pbr
+            .and_then(|pbr| pbr.base_color_factor)
+            .unwrap_or([1.0; 4]);
+        if factor != [1.0; 4] {
+            for pixel in image.pixels_mut() {
+                for c in 0..4 {
+                    pixel[c] = (pixel[c] as f32 * factor[c]) as u8;
+                }
+            }
+        }
+
+        let diffuse_texture = material_texture(image, &name, compress_textures, device, queue)?;
+
+        let normal_texture = match m
+            .normal_texture
+            .as_ref()
+            .and_then(|info| document.texture_image(info))
+        {
+            Some(image) => {
+                let data = load_gltf_image(&document, &buffers, directory, image).await?;
+                let label = format!("{} normal map", name);
+                Texture::from_bytes(
+                    device,
+                    queue,
+                    &data,
+                    &label,
+                    compress_textures,
+                    true,
+                    true,
+                    SamplerConfig::DEFAULT,
+                )?
+            }
+            None => flat_normal_map(device, queue)?,
+        };
+
+        let emissive_texture = match m
+            .emissive_texture
+            .as_ref()
+            .and_then(|info| document.texture_image(info))
+        {
+            Some(image) => {
+                let data = load_gltf_image(&document, &buffers, directory, image).await?;
+                let label = format!("{} emissive map", name);
+                Texture::from_bytes(
+                    device,
+                    queue,
+                    &data,
+                    &label,
+                    compress_textures,
+                    true,
+                    false,
+                    SamplerConfig::DEFAULT,
+                )?
+            }
+            None => Texture::from_color(device, queue, [u8::MAX; 4])?,
+        };
+
+        materials.push(Material::new(
+            device,
+            layouts,
+            name,
+            diffuse_texture,
+            normal_texture,
+            // Alpha in the texture is ignored unless the material asks to be blended
+            m.alpha_mode.as_deref() == Some("BLEND"),
+            Specular::DEFAULT,
+            // glTF's default is black, even with an emissive texture
+            m.emissive_factor.unwrap_or([0.0; 3]),
+            emissive_texture,
+            Texture::from_color(device, queue, [u8::MAX; 4])?,
+        ));
+    }
+
+    let mesh = document
+        .meshes
+        .first()
+        .with_context(|| format!("{} has no meshes", file_name))?;
+    let name = mesh.name.as_deref().unwrap_or(file_name);
+
+    let mut meshes = Vec::new();
+    for (i, primitive) in mesh.primitives.iter().enumerate() {
+        let (mut vertices, indices) = document.read_primitive(&buffers, primitive, name, i)?;
+        compute_tangents(&mut vertices, &indices);
+
+        // Primitives without a material get a plain white one
+        let material = match primitive.material {
+            Some(material) => material,
+            None => {
+                let texture = solid_color_texture([1.0; 3], "default", device, queue)?;
+                let normal_texture = flat_normal_map(device, queue)?;
+                materials.push(Material::new(
+                    device,
+                    layouts,
+                    "default".into(),
+                    texture,
+                    normal_texture,
+                    false,
+                    Specular::DEFAULT,
+                    [0.0; 3],
+                    Texture::from_color(device, queue, [u8::MAX; 4])?,
+                    Texture::from_color(device, queue, [u8::MAX; 4])?,
+                ));
+                materials.len() - 1
+            }
+        };
+
+        meshes.push(Mesh::new(device, name, vertices, indices, material));
+    }
+
+    Ok(Model::new(meshes, materials))
+}
+
+// Data URIs are decoded in place, anything else is a file relative to the glTF.
+async fn load_gltf_uri(directory: &Path, uri: &str) -> anyhow::Result<Vec<u8>> {
+    match gltf::decode_data_uri(uri) {
+        Some(data) => data,
+        None => load_binary(directory.join(uri)).await,
+    }
+}
+
+async fn load_gltf_image(
+    document: &gltf::Document,
+    buffers: &[Vec<u8>],
+    directory: &Path,
+    image: &gltf::GltfImage,
+) -> anyhow::Result<Vec<u8>> {
+    match (&image.uri, image.buffer_view) {
+        (Some(uri), _) => load_gltf_uri(directory, uri).await,
+        (None, Some(view)) => Ok(document.view(buffers, view)?.to_vec()),
+        (None, None) => anyhow::bail!("Image has no data"),
+    }
+}
+
+// Loads the positions and normals of `file_name` to blend `base` towards.
+// Returns one vertex buffer per mesh. The meshes must line up with `base` vertex for vertex.
+pub async fn load_morph_target(
+    file_name: &str,
+    base: &Model,
+    device: &wgpu::Device,
+) -> anyhow::Result<Vec<wgpu::Buffer>> {
+    let obj_text = load_string(file_name).await?;
+    let obj_cursor = Cursor::new(obj_text);
+    let mut obj_reader = BufReader::new(obj_cursor);
+
+    // Only the shape matters, so any materials are skipped
+    let (models, _) = tobj::load_obj_buf_async(
+        &mut obj_reader,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |_| async move { Ok(Default::default()) },
+    )
+    .await?;
+
+    if models.len() != base.meshes.len() {
+        anyhow::bail!(
+            "Morph target {} has {} meshes but the base model has {}",
+            file_name,
+            models.len(),
+            base.meshes.len()
+        );
+    }
+
+    models
+        .into_iter()
+        .zip(&base.meshes)
+        .map(|(m, base_mesh)| {
+            let num_vertices = m.mesh.positions.len() / 3;
+
+            if num_vertices != base_mesh.num_vertices as usize {
+                anyhow::bail!(
+                    "Morph target mesh {:?} has {} vertices but {:?} has {}",
+                    m.name,
+                    num_vertices,
+                    base_mesh.name,
+                    base_mesh.num_vertices
+                );
+            }
+            // The normals get blended too, so every vertex needs one
+            if m.mesh.normals.len() != num_vertices * 3 {
+                anyhow::bail!(
+                    "Morph target mesh {:?} has {} normals for {} vertices",
+                    m.name,
+                    m.mesh.normals.len() / 3,
+                    num_vertices
+                );
+            }
+
+            let vertices = (0..num_vertices)
+                .map(|i| MorphVertex {
+                    position: [
+                        m.mesh.positions[i * 3],
+                        m.mesh.positions[i * 3 + 1],
+                        m.mesh.positions[i * 3 + 2],
+                    ],
+                    normal: [
+                        m.mesh.normals[i * 3],
+                        m.mesh.normals[i * 3 + 1],
+                        m.mesh.normals[i * 3 + 2],
+                    ],
+                })
+                .collect::<Vec<_>>();
+
+            Ok(
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Morph Buffer", file_name)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }),
+            )
+        })
+        .collect()
 }
 
 pub trait DrawModel<'a> {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        material: &'a Material,
-        camera_bind_group: &'a wgpu::BindGroup,
-    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
@@ -151,21 +705,34 @@ pub trait DrawModel<'a> {
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
     );
+    // Draws the whole model moved by `transform`, on top of each instance's own placement.
+    // Only for pipelines whose layout has the object_transform push constant.
+    fn draw_model_with_transform(
+        &mut self,
+        model: &'a Model,
+        transform: Matrix4<f32>,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_model_opaque_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_model_transparent_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        eye: Point3<f32>,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &'b Material,
-        camera_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group);
-    }
-
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
@@ -174,7 +741,7 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
@@ -190,9 +757,152 @@ where
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
     ) {
+        // Each mesh brings its own material
         for mesh in &model.meshes {
             let material = &model.materials[mesh.material];
             self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
         }
     }
+
+    fn draw_model_with_transform(
+        &mut self,
+        model: &'b Model,
+        transform: Matrix4<f32>,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        object_transform::push(self, transform);
+        self.draw_model_instanced(model, instances, camera_bind_group);
+    }
+
+    fn draw_model_opaque_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            if !material.transparent {
+                self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+            }
+        }
+    }
+
+    // Farthest first, so each mesh blends over the ones behind it.
+    // Meshes are sorted by where they sit in the model, so the instances all share one order.
+    fn draw_model_transparent_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        eye: Point3<f32>,
+    ) {
+        let mut meshes = model
+            .meshes
+            .iter()
+            .filter(|mesh| model.materials[mesh.material].transparent)
+            .map(|mesh| (mesh, mesh.aabb.center().distance2(eye)))
+            .collect::<Vec<_>>();
+        meshes.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        for (mesh, _) in meshes {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+        }
+    }
+}
+
+// Draws geometry that isn't loaded as a Model, like the hardcoded pentagon. Indices are 16 bit.
+pub trait DrawShape<'a> {
+    fn draw_shape_instanced(
+        &mut self,
+        vertex_buffer: &'a wgpu::Buffer,
+        index_buffer: &'a wgpu::Buffer,
+        num_indices: u32,
+        instances: Range<u32>,
+        bind_group: &'a wgpu::BindGroup,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+
+    // Skips the index buffer and takes the vertices in order, for points and lines.
+    fn draw_shape_vertices_instanced(
+        &mut self,
+        vertex_buffer: &'a wgpu::Buffer,
+        num_vertices: u32,
+        instances: Range<u32>,
+        bind_group: &'a wgpu::BindGroup,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawShape<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_shape_instanced(
+        &mut self,
+        vertex_buffer: &'b wgpu::Buffer,
+        index_buffer: &'b wgpu::Buffer,
+        num_indices: u32,
+        instances: Range<u32>,
+        bind_group: &'b wgpu::BindGroup,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        // Takes the buffer slot to use the vertex buffer. Also takes the slice of the buffer to use. In this case: all of it.
+        self.set_vertex_buffer(0, vertex_buffer.slice(..));
+        // Can only have one index buffer bound at a time.
+        self.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        self.set_bind_group(0, bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        // This is where @builtin(vertex_index) and @builtin(instance_index) come from
+        self.draw_indexed(0..num_indices, 0, instances);
+    }
+
+    fn draw_shape_vertices_instanced(
+        &mut self,
+        vertex_buffer: &'b wgpu::Buffer,
+        num_vertices: u32,
+        instances: Range<u32>,
+        bind_group: &'b wgpu::BindGroup,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, vertex_buffer.slice(..));
+        self.set_bind_group(0, bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.draw(0..num_vertices, instances);
+    }
+}
+
+// Draws a model without binding any materials. Used by passes that only care about the shape, like the outline.
+pub trait DrawSilhouette<'a> {
+    fn draw_model_silhouette_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        bind_group: &'a wgpu::BindGroup,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawSilhouette<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_model_silhouette_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        bind_group: &'b wgpu::BindGroup,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_bind_group(0, bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+
+        for mesh in &model.meshes {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
 }
@@ -0,0 +1,306 @@
+use std::mem::size_of;
+
+use bytemuck::cast_slice;
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Transform};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Device, Queue, RenderPass,
+};
+
+use crate::{
+    instance::{Instance, InstanceRaw},
+    model::{Aabb, Model},
+    object_transform,
+    resources::DrawModel,
+};
+
+// A model and every copy of it that gets drawn.
+pub struct SceneObject {
+    pub model: Model,
+    pub instances: Vec<Instance>,
+    pub instance_buffer: Buffer,
+    // How many instances fit in instance_buffer
+    instance_capacity: usize,
+    // Where the whole model goes, on top of each instance's own placement
+    transform: Matrix4<f32>,
+    // Without push constants the transform can't be given per draw, so it's baked into the instance buffer
+    bake_transform: bool,
+}
+
+impl SceneObject {
+    pub fn new(
+        device: &Device,
+        model: Model,
+        instances: Vec<Instance>,
+        bake_transform: bool,
+    ) -> Self {
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+
+        // Room for at least one, since an empty buffer can't be bound
+        let instance_capacity = instances.len().max(1);
+        let instance_buffer = if instances.is_empty() {
+            create_instance_buffer(device, instance_capacity)
+        } else {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: cast_slice(&instance_data),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            })
+        };
+
+        Self {
+            model,
+            instances,
+            instance_buffer,
+            instance_capacity,
+            transform: Matrix4::identity(),
+            bake_transform,
+        }
+    }
+
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+
+    // Moves every instance along with the object. Their own positions stay relative to it.
+    pub fn set_transform(&mut self, device: &Device, queue: &Queue, transform: Matrix4<f32>) {
+        self.transform = transform;
+
+        if self.bake_transform && !self.instances.is_empty() {
+            let order = (0..self.instances.len()).collect::<Vec<_>>();
+            self.upload_instances(device, queue, &order);
+        }
+    }
+
+    pub fn num_instances(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    // Bounds of every instance of the model, in world space.
+    pub fn aabb(&self) -> Aabb {
+        let corners = self.model.aabb().corners();
+
+        Aabb::from_points(self.instances.iter().flat_map(|instance| {
+            corners.map(|corner| {
+                self.transform.transform_point(Point3::from_vec(
+                    instance.position + instance.rotation * corner.to_vec(),
+                ))
+            })
+        }))
+    }
+
+    // Writes the instances into the instance buffer in `order`, which holds indices into `instances`.
+    pub fn upload_instances(&mut self, device: &Device, queue: &Queue, order: &[usize]) {
+        let capacity = grown_capacity(self.instance_capacity, self.instances.len());
+        if capacity != self.instance_capacity {
+            self.instance_capacity = capacity;
+            self.instance_buffer = create_instance_buffer(device, capacity);
+        }
+
+        let mut instance_data = order
+            .iter()
+            .map(|&i| self.instances[i].to_raw())
+            .collect::<Vec<_>>();
+
+        if self.bake_transform {
+            for raw in &mut instance_data {
+                raw.model = (self.transform * Matrix4::from(raw.model)).into();
+            }
+        }
+
+        queue.write_buffer(&self.instance_buffer, 0, cast_slice(&instance_data));
+    }
+}
+
+// How many instances the buffer needs room for once there are `len` of them. Stays the same while they fit.
+fn grown_capacity(capacity: usize, len: usize) -> usize {
+    if len > capacity {
+        // Leave room so spawning more doesn't recreate the buffer every time
+        len * 2
+    } else {
+        capacity
+    }
+}
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: (capacity * size_of::<InstanceRaw>()) as BufferAddress,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// Everything drawn with the model pipeline.
+// The first object is the primary one, which picking, selection, sorting and the instance keys work on.
+pub struct Scene {
+    pub objects: Vec<SceneObject>,
+    // Whether object transforms go to the model pipeline as push constants
+    push_constants: bool,
+}
+
+impl Scene {
+    pub fn new(push_constants: bool) -> Self {
+        Self {
+            objects: Vec::new(),
+            push_constants,
+        }
+    }
+
+    // Returns the index of the new object.
+    pub fn add(&mut self, device: &Device, model: Model, instances: Vec<Instance>) -> usize {
+        self.objects.push(SceneObject::new(
+            device,
+            model,
+            instances,
+            !self.push_constants,
+        ));
+        self.objects.len() - 1
+    }
+
+    pub fn push_constants(&self) -> bool {
+        self.push_constants
+    }
+
+    pub fn primary(&self) -> &SceneObject {
+        self.objects.first().expect("Scene has no objects")
+    }
+
+    pub fn primary_mut(&mut self) -> &mut SceneObject {
+        self.objects.first_mut().expect("Scene has no objects")
+    }
+
+    // Every object after the primary one. These only ever get drawn plainly.
+    pub fn others(&self) -> impl Iterator<Item = &SceneObject> {
+        self.objects.iter().skip(1)
+    }
+
+    // Draws the objects after the primary one with whatever pipeline is bound.
+    // With `skip_transparent` their transparent meshes are left for draw_transparent.
+    // Leaves the last object's instance buffer and, with push constants, its transform bound.
+    pub fn draw_others<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        skip_transparent: bool,
+    ) {
+        for object in self.others() {
+            render_pass.set_vertex_buffer(1, object.instance_buffer.slice(..));
+            if skip_transparent {
+                if self.push_constants {
+                    object_transform::push(render_pass, object.transform);
+                }
+                render_pass.draw_model_opaque_instanced(
+                    &object.model,
+                    0..object.num_instances(),
+                    camera_bind_group,
+                );
+            } else if self.push_constants {
+                render_pass.draw_model_with_transform(
+                    &object.model,
+                    object.transform,
+                    0..object.num_instances(),
+                    camera_bind_group,
+                );
+            } else {
+                render_pass.draw_model_instanced(
+                    &object.model,
+                    0..object.num_instances(),
+                    camera_bind_group,
+                );
+            }
+        }
+    }
+
+    // Bounds of every object that has instances, in world space.
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .filter(|object| !object.instances.is_empty())
+            .map(SceneObject::aabb)
+            .reduce(|a, b| Aabb::from_points([a.min, a.max, b.min, b.max]))
+    }
+
+    pub fn has_transparency(&self) -> bool {
+        self.objects
+            .iter()
+            .any(|object| object.model.has_transparency())
+    }
+
+    // Draws the transparent meshes of every object with whatever pipeline is bound, farthest first within each object.
+    // `include_primary` is false when the primary object was already drawn whole by another pipeline.
+    // Leaves the instance buffer and transform of the last object drawn bound.
+    pub fn draw_transparent<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        eye: Point3<f32>,
+        include_primary: bool,
+    ) {
+        let skip = if include_primary { 0 } else { 1 };
+
+        for object in self.objects.iter().skip(skip) {
+            if !object.model.has_transparency() {
+                continue;
+            }
+
+            // Meshes are sorted in the object's own space
+            let eye = match object.transform.inverse_transform() {
+                Some(inverse) => inverse.transform_point(eye),
+                None => eye,
+            };
+
+            if self.push_constants {
+                object_transform::push(render_pass, object.transform);
+            }
+            render_pass.set_vertex_buffer(1, object.instance_buffer.slice(..));
+            render_pass.draw_model_transparent_instanced(
+                &object.model,
+                0..object.num_instances(),
+                camera_bind_group,
+                eye,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity_grows_to_fit_added_instances() {
+        // The grid starts out with 100 instances in a buffer just big enough for them
+        let mut len = 100;
+        let mut capacity = len;
+        let mut reallocations = 0;
+
+        for _ in 0..200 {
+            len += 1;
+
+            let grown = grown_capacity(capacity, len);
+            if grown != capacity {
+                reallocations += 1;
+                capacity = grown;
+            }
+
+            // write_buffer panics if the instances don't fit
+            assert!(
+                len <= capacity,
+                "{} instances in room for {}",
+                len,
+                capacity
+            );
+        }
+
+        // Drawn as 0..instances.len()
+        assert_eq!(len, 300);
+        assert_eq!(reallocations, 2);
+    }
+
+    #[test]
+    fn capacity_is_kept_when_instances_fit() {
+        assert_eq!(grown_capacity(64, 64), 64);
+        assert_eq!(grown_capacity(64, 0), 64);
+    }
+}
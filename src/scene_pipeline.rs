@@ -0,0 +1,20 @@
+use wgpu::{CompareFunction, DepthBiasState, Face, PolygonMode, PrimitiveTopology, TextureFormat};
+
+// How to build one of the main scene pipelines. Morphing and wind build theirs from one too.
+#[derive(Copy, Clone)]
+pub struct ScenePipelineConfig {
+    pub format: TextureFormat,
+    pub depth_format: TextureFormat,
+    // Fill for the normal view, Line for wireframe
+    pub polygon_mode: PolygonMode,
+    pub cull_mode: Option<Face>,
+    // Draws both sides whatever `cull_mode` says, and lights the back as if it were a front
+    pub double_sided: bool,
+    // Blends by alpha and leaves the depth buffer alone, so meshes behind it still draw
+    pub transparent: bool,
+    pub sample_count: u32,
+    pub depth_compare: CompareFunction,
+    pub depth_bias: DepthBiasState,
+    // TriangleList for everything but the pentagon, which can be drawn as points, lines or strips
+    pub topology: PrimitiveTopology,
+}
@@ -0,0 +1,94 @@
+use std::{num::NonZeroU32, path::Path, sync::mpsc};
+
+use anyhow::*;
+use wgpu::{
+    BufferAddress, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, Extent3d,
+    ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Maintain, MapMode, Origin3d, Queue,
+    TextureAspect, TextureFormat, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::texture::Texture;
+
+// Copies an 8 bit RGBA or BGRA texture back to the CPU and saves it as an image. Blocks until the GPU is done.
+// The texture needs COPY_SRC, which render targets from `Texture::create_render_target` have.
+pub fn save_texture(
+    device: &Device,
+    queue: &Queue,
+    texture: &Texture,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    path: &Path,
+) -> Result<()> {
+    let bgra = match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+        _ => bail!("Can't save {:?} textures as images", format),
+    };
+
+    // Rows in the copy have to start on a multiple of 256 bytes
+    let unpadded_bytes_per_row = width * 4;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+        * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Screenshot Buffer"),
+        size: (padded_bytes_per_row * height) as BufferAddress,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Screenshot Encoder"),
+    });
+
+    encoder.copy_texture_to_buffer(
+        ImageCopyTexture {
+            texture: &texture.texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: NonZeroU32::new(height),
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(MapMode::Read, move |result| {
+        sender.send(result).ok();
+    });
+    device.poll(Maintain::Wait);
+    receiver.recv()??;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let view = slice.get_mapped_range();
+        for row in view.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+    buffer.unmap();
+
+    if bgra {
+        for pixel in pixels.chunks_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    image::save_buffer(path, &pixels, width, height, image::ColorType::Rgba8)
+        .with_context(|| format!("Couldn't save {}", path.display()))
+}
@@ -0,0 +1,19 @@
+// Resamples an environment cubemap into a small latitude-longitude grid, for environment.rs to read back
+// and project onto spherical harmonics.
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+let PI: f32 = 3.14159265359;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    // The same layout environment.wgsl reads equirectangular images with
+    let theta = uv.y * PI;
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let direction = vec3<f32>(cos(phi) * sin(theta), cos(theta), sin(phi) * sin(theta));
+
+    return vec4<f32>(textureSampleLevel(t_environment, s_environment, direction, 0.0).rgb, 1.0);
+}
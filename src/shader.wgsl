@@ -3,45 +3,181 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    // Multiplies the material's color. One of MATERIAL_TINTS, picked by the instance's material_index
+    @location(11) tint: vec3<f32>,
 };
 
 struct CameraUniform {
-    view_proj: mat4x4<f32>
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
 }
 
 @group(1) @binding(0) // The number is specified by the render_pipeline_layout. camera bind group is second so it is group(1)
 var<uniform> camera: CameraUniform;
 
+struct Time {
+    // Seconds the scene has run for. Stops while paused.
+    elapsed: f32,
+}
+
+@group(1) @binding(1)
+var<uniform> time: Time;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+    // 5 to 11 are taken by InstanceInput and MorphInput
+    @location(12) color: vec3<f32>,
+    // For the lightmap
+    @location(13) tex_coords1: vec2<f32>,
 };
 
+// The shape vs_morph blends towards. Lines up with VertexInput vertex for vertex.
+struct MorphInput {
+    @location(9) position: vec3<f32>,
+    @location(10) normal: vec3<f32>,
+};
+
+struct MorphUniform {
+    // 0 = base mesh, 1 = morph target
+    weight: f32,
+}
+
+@group(3) @binding(0) // Only bound by the morph pipeline
+var<uniform> morph: MorphUniform;
+
+struct WindUniform {
+    // Horizontal (xz) direction the wind blows towards
+    direction: vec2<f32>,
+    // How far the tops of objects get pushed, in world units
+    strength: f32,
+    // Gusts per second
+    frequency: f32,
+    time: f32,
+    // Local height where the sway starts (the roots) and how tall the swaying part is
+    root_height: f32,
+    sway_height: f32,
+}
+
+@group(3) @binding(0) // Only bound by the wind pipeline, which never uses the morph uniform
+var<uniform> wind: WindUniform;
+
 // Stores the output of the vertex shader
 struct VertexOutput {
     // tells WGPU this is the value we want to use as the vertex's clip coordinates
     // vec3<f32> = A 3D vector of 32-bit floats
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>
+    @location(0) tex_coords: vec2<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) world_tangent: vec3<f32>,
+    @location(5) world_bitangent: vec3<f32>,
+    @location(6) color: vec3<f32>,
+    @location(7) tex_coords1: vec2<f32>,
 };
 
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+// Instances are only rotated and moved, so the model matrix without its translation turns normals correctly.
+fn world_normal(instance: InstanceInput, normal: vec3<f32>) -> vec3<f32> {
+    return mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz,
+    ) * normal;
+}
+
 // marks it as the entry point for the vertex shader
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
+    // The whole object's placement goes on top of the instance's, see object_transform.wgsl
+    let object = object_transform();
+    let object_normal = mat3x3<f32>(object[0].xyz, object[1].xyz, object[2].xyz);
+    let world_position = object * model_matrix(instance) * vec4<f32>(model.position, 1.0);
 
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.tex_coords1 = model.tex_coords1;
+    out.world_normal = object_normal * world_normal(instance, model.normal);
+    out.world_tangent = object_normal * world_normal(instance, model.tangent);
+    out.world_bitangent = object_normal * world_normal(instance, model.bitangent);
+    out.world_position = world_position.xyz;
+    out.color = model.color * instance.tint;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// Same as vs_main but the position is blended between the base mesh and the morph target.
+@vertex
+fn vs_morph(
+    model: VertexInput,
+    morph_target: MorphInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let position = mix(model.position, morph_target.position, morph.weight);
+    let normal = normalize(mix(model.normal, morph_target.normal, morph.weight));
+    let world_position = model_matrix(instance) * vec4<f32>(position, 1.0);
 
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords1 = model.tex_coords1;
+    out.world_normal = world_normal(instance, normal);
+    // The target has no tangents of its own. The base mesh's are close enough for a bump map.
+    out.world_tangent = world_normal(instance, model.tangent);
+    out.world_bitangent = world_normal(instance, model.bitangent);
+    out.world_position = world_position.xyz;
+    out.color = model.color * instance.tint;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// Same as vs_main but the vertex is pushed around by the wind. Higher vertices move further.
+@vertex
+fn vs_wind(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var world_position = model_matrix(instance) * vec4<f32>(model.position, 1.0);
+
+    // 0 at the roots up to 1 at the top. Squared so the object bends rather than shears.
+    let weight = clamp((model.position.y - wind.root_height) / wind.sway_height, 0.0, 1.0);
+    let bend = weight * weight;
+
+    // Offsetting the wave by position makes gusts roll across the scene instead of everything moving together
+    let phase = dot(world_position.xz, wind.direction) * 0.5 + world_position.x * 0.3 + world_position.z * 0.2;
+    let t = wind.time * wind.frequency;
+
+    // Mostly pushed along the wind direction, with a smaller faster flutter across it
+    let gust = (sin(t - phase) + 0.5 * sin(2.3 * t - 1.7 * phase)) * 0.5 + 0.5;
+    let flutter = sin(3.1 * t + phase) * 0.25;
+    let across = vec2<f32>(-wind.direction.y, wind.direction.x);
+
+    let offset = (wind.direction * gust + across * flutter) * wind.strength * bend;
+    world_position = vec4<f32>(world_position.x + offset.x, world_position.y, world_position.z + offset.y, world_position.w);
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.tex_coords1 = model.tex_coords1;
+    // Bending would tilt the normals a little. Not enough to be worth working out.
+    out.world_normal = world_normal(instance, model.normal);
+    out.world_tangent = world_normal(instance, model.tangent);
+    out.world_bitangent = world_normal(instance, model.bitangent);
+    out.world_position = world_position.xyz;
+    out.color = model.color * instance.tint;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
@@ -54,8 +190,187 @@ var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
 
+// Tangent space normals. Flat (0.5, 0.5, 1.0) for materials without one.
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+
+@group(0) @binding(3)
+var s_normal: sampler;
+
+struct Material {
+    // From the MTL's Ks and Ns
+    specular_color: vec3<f32>,
+    // Higher values make a smaller, sharper highlight
+    shininess: f32,
+    // From the MTL's Ke. Light given off whatever the lighting, multiplied by t_emissive.
+    emissive: vec3<f32>,
+    // Texture sizes the texture slides per second
+    uv_scroll: vec2<f32>,
+}
+
+@group(0) @binding(4)
+var<uniform> material: Material;
+// Sampled with s_diffuse. White when the material has no emissive map.
+@group(0) @binding(5)
+var t_emissive: texture_2d<f32>;
+// Baked lighting, at the second UV set. Also sampled with s_diffuse, and white when there's no lightmap.
+@group(0) @binding(6)
+var t_lightmap: texture_2d<f32>;
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+// Only the first `count` lights are used. MAX_LIGHTS comes from light.rs, see shader_constants.rs.
+struct Lights {
+    count: u32,
+    lights: array<Light, MAX_LIGHTS>,
+}
+
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// The directional light that casts shadows. See shadow.rs.
+struct Shadow {
+    // World space to the light's clip space
+    view_proj: mat4x4<f32>,
+    // Points away from the light
+    direction: vec3<f32>,
+    enabled: u32,
+    color: vec3<f32>,
+}
+
+@group(2) @binding(1)
+var<uniform> shadow: Shadow;
+@group(2) @binding(2)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(3)
+var s_shadow: sampler_comparison;
+
+// How much of the sun reaches `world_position`, from 0 (in shadow) to 1 (lit).
+// Averages a 3x3 block of shadow map comparisons (PCF) to soften the edges.
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    if (shadow.enabled == 0u) {
+        return 1.0;
+    }
+
+    let light_clip = shadow.view_proj * vec4<f32>(world_position, 1.0);
+    let light_ndc = light_clip.xyz / light_clip.w;
+    // Clip space y points up, texture v points down
+    let uv = light_ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+
+    // Outside the map nothing was drawn to block the light
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || light_ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, light_ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Diffuse light from an environment map as spherical harmonics. See irradiance_sh in environment.rs.
+struct Irradiance {
+    // Bands 0 to 2, rgb in xyz
+    coefficients: array<vec4<f32>, 9>,
+    // Without an environment the lights' flat ambient term is used instead
+    enabled: u32,
+}
+
+@group(2) @binding(4)
+var<uniform> irradiance: Irradiance;
+
+// The light the environment gives a white diffuse surface facing `n`.
+fn environment_irradiance(n: vec3<f32>) -> vec3<f32> {
+    let c = irradiance.coefficients;
+    let sum = c[0].rgb * 0.282095
+        + c[1].rgb * 0.488603 * n.y
+        + c[2].rgb * 0.488603 * n.z
+        + c[3].rgb * 0.488603 * n.x
+        + c[4].rgb * 1.092548 * n.x * n.y
+        + c[5].rgb * 1.092548 * n.y * n.z
+        + c[6].rgb * 0.315392 * (3.0 * n.z * n.z - 1.0)
+        + c[7].rgb * 1.092548 * n.x * n.z
+        + c[8].rgb * 0.546274 * (n.x * n.x - n.y * n.y);
+    // Ringing from so few bands can dip below zero opposite a bright spot
+    return max(sum, vec3<f32>(0.0));
+}
+
+// Light that reaches everything, so faces turned away from the light aren't pitch black
+let AMBIENT_STRENGTH: f32 = 0.1;
+
+// Blinn-Phong: ambient + diffuse + specular, added up over every point light and the sun.
+// `facing` is 1, or -1 to light the back of a face.
+fn shade(in: VertexOutput, facing: f32) -> vec4<f32> {
+    // Scrolling wraps like Repeat, whatever the sampler's address mode. The gradients come from the unwrapped
+    // coordinates, so the wrap doesn't leave a seam of the smallest mip level.
+    let scrolled = in.tex_coords + material.uv_scroll * time.elapsed;
+    let scrolling = any(material.uv_scroll != vec2<f32>(0.0));
+    let uv = select(in.tex_coords, fract(scrolled), scrolling);
+    let uv_dx = dpdx(scrolled);
+    let uv_dy = dpdy(scrolled);
+
+    let texture_color = textureSampleGrad(t_diffuse, s_diffuse, uv, uv_dx, uv_dy);
+    // Doesn't scroll with the texture, since the lighting it holds stays put
+    let lightmap = textureSample(t_lightmap, s_diffuse, in.tex_coords1).rgb;
+    let object_color = vec4<f32>(texture_color.rgb * in.color * lightmap, texture_color.a);
+
+    // The map stores -1..1 as 0..1. The tangent, bitangent and normal carry it into world space.
+    let tangent_normal = textureSampleGrad(t_normal, s_normal, uv, uv_dx, uv_dy).xyz * 2.0 - 1.0;
+    let tbn = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let normal = normalize(tbn * tangent_normal) * facing;
+    let view_dir = normalize(camera.view_position.xyz - in.world_position);
+
+    let use_environment = irradiance.enabled != 0u;
+    let ambient_strength = select(AMBIENT_STRENGTH, 0.0, use_environment);
+
+    var lighting = vec3<f32>(0.0);
+    if (use_environment) {
+        lighting = environment_irradiance(normal);
+    }
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i = i + 1u) {
+        let light = lights.lights[i];
+        let light_dir = normalize(light.position - in.world_position);
+        // Halfway between the light and the eye. Lines up with the normal where the highlight is brightest.
+        let half_dir = normalize(view_dir + light_dir);
+
+        let ambient = light.color * ambient_strength;
+        let diffuse = light.color * max(dot(normal, light_dir), 0.0);
+        let highlight = light.color * material.specular_color * pow(max(dot(normal, half_dir), 0.0), material.shininess);
+
+        lighting = lighting + ambient + diffuse + highlight;
+    }
+
+    // The sun has no ambient term, so shadows keep whatever the point lights give them
+    let sun_dir = -normalize(shadow.direction);
+    let sun_half_dir = normalize(view_dir + sun_dir);
+    let sun_diffuse = max(dot(normal, sun_dir), 0.0);
+    let sun_specular = material.specular_color * pow(max(dot(normal, sun_half_dir), 0.0), material.shininess);
+    lighting = lighting + shadow.color * (sun_diffuse + sun_specular) * shadow_factor(in.world_position);
+
+    let emissive = material.emissive * textureSampleGrad(t_emissive, s_diffuse, uv, uv_dx, uv_dy).rgb;
+
+    return vec4<f32>(lighting * object_color.rgb + emissive, object_color.a);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // sets the color of the current fragment to be a dark brown
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return shade(in, 1.0);
+}
+
+// For pipelines that draw both sides. The normal points out of whichever side is being looked at.
+@fragment
+fn fs_double_sided(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    return shade(in, select(-1.0, 1.0, front_facing));
 }
\ No newline at end of file
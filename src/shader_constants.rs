@@ -0,0 +1,14 @@
+// Values the Rust side and a shader both depend on, like array sizes, are written into the shader source
+// as constants before it's compiled, so each one is only defined in Rust.
+//
+// The shader uses them by name without declaring them, e.g. `lights: array<Light, MAX_LIGHTS>`.
+// Each becomes a `let NAME: u32 = value;` line in front of the source. Declaring one in the shader too
+// is a redefinition error when the module is compiled.
+pub fn with_constants(source: &str, constants: &[(&str, u32)]) -> String {
+    let mut prelude = String::new();
+    for (name, value) in constants {
+        prelude.push_str(&format!("let {}: u32 = {}u;\n", name, value));
+    }
+
+    format!("{}\n{}", prelude, source)
+}
@@ -0,0 +1,313 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{ortho, InnerSpace, Matrix4, SquareMatrix, Vector3};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages,
+    CommandEncoder, CompareFunction, DepthBiasState, DepthStencilState, Device, Face, FrontFace,
+    LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, SamplerBindingType, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, StencilState, TextureFormat, TextureSampleType, TextureView,
+    TextureViewDimension, VertexState,
+};
+
+use crate::{
+    camera::OPENGL_TO_WGPU_MATRIX,
+    instance::InstanceRaw,
+    model::{Aabb, ModelVertex, Vertex},
+    object_transform::{self, with_object_transform},
+    post::depth_view,
+    scene::Scene,
+    texture::Texture,
+};
+
+// Width and height of the shadow map in texels
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+// Matches `Shadow` in shader.wgsl and shadow.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ShadowUniform {
+    // World space to the light's clip space
+    view_proj: [[f32; 4]; 4],
+    // The way the sun shines, pointing away from it
+    direction: [f32; 3],
+    // 0 lights everything as if nothing was in the way
+    enabled: u32,
+    color: [f32; 3],
+    _padding: u32,
+}
+
+// A directional "sun" light that casts shadows.
+// Each frame the scene is drawn depth only from the light's point of view into the shadow map,
+// which the main shader compares against to find what the light can't see.
+pub struct Shadow {
+    pub enabled: bool,
+    direction: Vector3<f32>,
+    color: [f32; 3],
+    uniform: ShadowUniform,
+    buffer: Buffer,
+    map: Texture,
+    // The depth aspect of `map`, which is all the main shader can sample
+    map_view: TextureView,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl Shadow {
+    // `map_size` is the width and height of the shadow map. Bigger means sharper shadows.
+    // `push_constants` says whether the device can take each object's transform as a push constant.
+    pub fn new(
+        device: &Device,
+        map_size: u32,
+        depth_format: TextureFormat,
+        push_constants: bool,
+    ) -> Self {
+        let direction = Vector3::new(-0.5, -1.0, -0.3).normalize();
+        let color = [0.6, 0.6, 0.55];
+        let uniform = ShadowUniform {
+            view_proj: Matrix4::identity().into(),
+            direction: direction.into(),
+            // Until the first shadow pass the map holds nothing worth comparing against
+            enabled: 0,
+            color,
+            _padding: 0,
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let map = Texture::create_depth_texture_with_size(
+            device,
+            map_size,
+            map_size,
+            depth_format,
+            "shadow_map",
+        );
+        let map_view = depth_view(&map);
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("shadow_bind_group"),
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: ShaderSource::Wgsl(
+                with_object_transform(include_str!("shadow.wgsl"), push_constants).into(),
+            ),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: object_transform::push_constant_ranges(push_constants),
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            // Depth only
+            fragment: None,
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                polygon_mode: PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilState::default(),
+                // Pushes the stored depth back a little so surfaces don't shadow themselves (shadow acne)
+                bias: DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            enabled: true,
+            direction,
+            color,
+            uniform,
+            buffer,
+            map,
+            map_view,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Entries 1 to 3 of the light bind group layout: the uniform, the shadow map and its comparison sampler.
+    pub fn layout_entries() -> [BindGroupLayoutEntry; 3] {
+        [
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: TextureViewDimension::D2,
+                    sample_type: TextureSampleType::Depth,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                count: None,
+            },
+        ]
+    }
+
+    // Goes with layout_entries.
+    pub fn bind_group_entries(&self) -> [BindGroupEntry<'_>; 3] {
+        [
+            BindGroupEntry {
+                binding: 1,
+                resource: self.buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&self.map_view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                // Compares with LessEqual, so 1 means lit
+                resource: BindingResource::Sampler(&self.map.sampler),
+            },
+        ]
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    // What goes into `buffer` for this frame. Call `update` first.
+    pub fn uniform_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(&self.uniform)
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        self.direction
+    }
+
+    // A zero vector is ignored.
+    pub fn set_direction(&mut self, direction: Vector3<f32>) {
+        if direction.magnitude2() > 0.0 {
+            self.direction = direction.normalize();
+        }
+    }
+
+    // Fits the light's orthographic view around `bounds` so the whole scene lands in the shadow map.
+    pub fn update(&mut self, bounds: Option<Aabb>) {
+        self.uniform.direction = self.direction.into();
+        self.uniform.color = self.color;
+        self.uniform.enabled = self.enabled as u32;
+
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        let center = bounds.center();
+        // A sphere around the box, so the fit doesn't depend on the direction
+        let radius = (bounds.max - bounds.min).magnitude().max(1.0) * 0.5;
+        let eye = center - self.direction * radius * 2.0;
+        // look_at can't handle an up vector parallel to the view direction
+        let up = if self.direction.y.abs() > 0.99 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_y()
+        };
+
+        let view = Matrix4::look_at_rh(eye, center, up);
+        let proj = ortho(-radius, radius, -radius, radius, radius, radius * 3.0);
+        self.uniform.view_proj = (OPENGL_TO_WGPU_MATRIX * proj * view).into();
+    }
+
+    // Draws every object's depth into the shadow map. Expects `update` to have been uploaded.
+    pub fn render(&self, encoder: &mut CommandEncoder, scene: &Scene) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.map.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        if !self.enabled {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+
+        for object in &scene.objects {
+            if object.instances.is_empty() {
+                continue;
+            }
+
+            if scene.push_constants() {
+                object_transform::push(&mut render_pass, object.transform());
+            }
+            render_pass.set_vertex_buffer(1, object.instance_buffer.slice(..));
+            for mesh in &object.model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..object.num_instances());
+            }
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{InnerSpace, SquareMatrix, Vector3};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferUsages,
+    CommandEncoder, Device, Extent3d, LoadOp, Operations, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+use crate::{
+    camera::{hash, Camera},
+    post,
+    texture::Texture,
+};
+
+// Size of the kernel array in ssao.wgsl
+pub const MAX_SSAO_SAMPLES: u32 = 64;
+// The noise texture tiles every NOISE_SIZE pixels. ssao_blur.wgsl blurs over the same size.
+const NOISE_SIZE: u32 = 4;
+// Single channel is all the occlusion factor needs
+const AO_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct SsaoUniform {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    kernel: [[f32; 4]; MAX_SSAO_SAMPLES as usize],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+    reversed_z: u32,
+    _padding: [u32; 3],
+}
+
+// Screen-space ambient occlusion. Darkens creases and spots where objects meet, based only on the depth buffer.
+pub struct Ssao {
+    pub enabled: bool,
+    // Shows the occlusion factor on its own instead of applying it to the scene
+    pub debug_view: bool,
+    uniform: SsaoUniform,
+    buffer: Buffer,
+    noise_texture: wgpu::Texture,
+    ao_texture: Texture,
+    ao_bind_group_layout: BindGroupLayout,
+    ao_bind_group: BindGroup,
+    blur_bind_group_layout: BindGroupLayout,
+    blur_bind_group: BindGroup,
+    ao_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+    debug_pipeline: RenderPipeline,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        depth_texture: &Texture,
+        camera: &Camera,
+    ) -> Self {
+        let mut uniform = SsaoUniform {
+            proj: [[0.0; 4]; 4],
+            inv_proj: [[0.0; 4]; 4],
+            kernel: sample_kernel(),
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+            sample_count: 16,
+            reversed_z: 0,
+            _padding: [0; 3],
+        };
+        set_projection(&mut uniform, camera);
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("SSAO Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let noise_texture = create_noise_texture(device, queue);
+
+        let ao_texture = Texture::create_render_target(device, width, height, AO_FORMAT, "ao");
+
+        let ao_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                post::depth_entry(0),
+                post::texture_entry(1),
+                post::uniform_entry(2),
+            ],
+            label: Some("ssao_bind_group_layout"),
+        });
+
+        let ao_bind_group = create_ao_bind_group(
+            device,
+            &ao_bind_group_layout,
+            depth_texture,
+            &noise_texture,
+            &buffer,
+        );
+
+        let blur_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::texture_entry(0)],
+            label: Some("ssao_blur_bind_group_layout"),
+        });
+
+        let blur_bind_group = create_blur_bind_group(device, &blur_bind_group_layout, &ao_texture);
+
+        let ao_pipeline = post::create_fullscreen_pipeline(
+            device,
+            "SSAO Pipeline",
+            include_str!("ssao.wgsl"),
+            &[&ao_bind_group_layout],
+            AO_FORMAT,
+            None,
+        );
+
+        // Multiplies what is already on screen by the blurred occlusion factor.
+        // This runs on the lit scene, so direct light gets darkened along with the ambient term.
+        // Not physically right, but it saves feeding the occlusion back into the lighting.
+        let multiply = BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::Dst,
+                dst_factor: BlendFactor::Zero,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: BlendFactor::Zero,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        };
+
+        let composite_pipeline = post::create_fullscreen_pipeline(
+            device,
+            "SSAO Composite Pipeline",
+            include_str!("ssao_blur.wgsl"),
+            &[&blur_bind_group_layout],
+            format,
+            Some(multiply),
+        );
+
+        let debug_pipeline = post::create_fullscreen_pipeline(
+            device,
+            "SSAO Debug Pipeline",
+            include_str!("ssao_blur.wgsl"),
+            &[&blur_bind_group_layout],
+            format,
+            Some(BlendState::REPLACE),
+        );
+
+        Self {
+            enabled: false,
+            debug_view: false,
+            uniform,
+            buffer,
+            noise_texture,
+            ao_texture,
+            ao_bind_group_layout,
+            ao_bind_group,
+            blur_bind_group_layout,
+            blur_bind_group,
+            ao_pipeline,
+            composite_pipeline,
+            debug_pipeline,
+        }
+    }
+
+    // The depth texture gets recreated when the window resizes.
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32, depth_texture: &Texture) {
+        self.ao_texture = Texture::create_render_target(device, width, height, AO_FORMAT, "ao");
+
+        self.ao_bind_group = create_ao_bind_group(
+            device,
+            &self.ao_bind_group_layout,
+            depth_texture,
+            &self.noise_texture,
+            &self.buffer,
+        );
+        self.blur_bind_group =
+            create_blur_bind_group(device, &self.blur_bind_group_layout, &self.ao_texture);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.uniform.sample_count
+    }
+
+    // More samples means less noise but a slower pass.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.uniform.sample_count = sample_count.clamp(1, MAX_SSAO_SAMPLES);
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.uniform.radius
+    }
+
+    pub fn set_radius(&mut self, radius: f32) {
+        self.uniform.radius = radius.max(0.01);
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.intensity
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.uniform.intensity = intensity.max(0.0);
+    }
+
+    pub fn update(&mut self, queue: &Queue, camera: &Camera) {
+        set_projection(&mut self.uniform, camera);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    // Works out the occlusion from the depth buffer, then blurs it onto `target`.
+    // Only reads depth, so `target` can be the surface or an offscreen texture.
+    pub fn render(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        {
+            let mut ao_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("SSAO Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &self.ao_texture.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            ao_pass.set_pipeline(&self.ao_pipeline);
+            ao_pass.set_bind_group(0, &self.ao_bind_group, &[]);
+            ao_pass.draw(0..3, 0..1);
+        }
+
+        let mut blur_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("SSAO Blur Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    // Keep the scene so it can be multiplied
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        if self.debug_view {
+            blur_pass.set_pipeline(&self.debug_pipeline);
+        } else {
+            blur_pass.set_pipeline(&self.composite_pipeline);
+        }
+        blur_pass.set_bind_group(0, &self.blur_bind_group, &[]);
+        blur_pass.draw(0..3, 0..1);
+    }
+}
+
+fn set_projection(uniform: &mut SsaoUniform, camera: &Camera) {
+    let proj = camera.build_projection_matrix();
+    uniform.proj = proj.into();
+    uniform.inv_proj = proj.invert().unwrap().into();
+    uniform.reversed_z = camera.reversed_z as u32;
+}
+
+// Random points in a hemisphere, bunched up towards the center so nearby geometry counts for more.
+fn sample_kernel() -> [[f32; 4]; MAX_SSAO_SAMPLES as usize] {
+    let mut kernel = [[0.0; 4]; MAX_SSAO_SAMPLES as usize];
+
+    for (i, sample) in kernel.iter_mut().enumerate() {
+        let i = i as i32;
+        let direction = Vector3::new(hash(1, 0, i), hash(1, 1, i), hash(1, 2, i) * 0.5 + 0.5)
+            .normalize()
+            * (hash(1, 3, i) * 0.5 + 0.5);
+
+        let t = i as f32 / MAX_SSAO_SAMPLES as f32;
+        let scale = 0.1 + 0.9 * t * t;
+
+        let point = direction * scale;
+        *sample = [point.x, point.y, point.z, 0.0];
+    }
+
+    kernel
+}
+
+// Random directions around z, stored as [0, 1] colors.
+fn create_noise_texture(device: &Device, queue: &Queue) -> wgpu::Texture {
+    let data = (0..(NOISE_SIZE * NOISE_SIZE) as i32)
+        .flat_map(|i| {
+            let x = hash(2, 0, i) * 0.5 + 0.5;
+            let y = hash(2, 1, i) * 0.5 + 0.5;
+            [(x * 255.0) as u8, (y * 255.0) as u8, 128, 255]
+        })
+        .collect::<Vec<_>>();
+
+    device.create_texture_with_data(
+        queue,
+        &TextureDescriptor {
+            label: Some("ssao_noise_texture"),
+            size: Extent3d {
+                width: NOISE_SIZE,
+                height: NOISE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            // Not sRGB, these are directions rather than colors
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        },
+        &data,
+    )
+}
+
+fn create_ao_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    depth_texture: &Texture,
+    noise_texture: &wgpu::Texture,
+    buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&post::depth_view(depth_texture)),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(
+                    &noise_texture.create_view(&TextureViewDescriptor::default()),
+                ),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("ssao_bind_group"),
+    })
+}
+
+fn create_blur_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    ao_texture: &Texture,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: BindingResource::TextureView(&ao_texture.view),
+        }],
+        label: Some("ssao_blur_bind_group"),
+    })
+}
@@ -0,0 +1,92 @@
+struct SsaoUniform {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    // Points inside a unit hemisphere facing +z. More of them sit close to the center.
+    kernel: array<vec4<f32>, 64>,
+    // How far around each pixel (in world units) to look for occluders
+    radius: f32,
+    // Stops flat surfaces from occluding themselves
+    bias: f32,
+    // Exponent applied to the result. Higher is darker.
+    intensity: f32,
+    sample_count: u32,
+    // Non-zero when the far plane is at depth 0 instead of 1
+    reversed_z: u32,
+}
+
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+
+// Small tiling texture of random directions for rotating the kernel
+@group(0) @binding(1)
+var t_noise: texture_2d<f32>;
+
+@group(0) @binding(2)
+var<uniform> ssao: SsaoUniform;
+
+let MAX_SAMPLES: u32 = 64u;
+
+fn depth_at(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coords = vec2<i32>(clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0)) * (size - 1.0));
+    return textureLoad(t_depth, coords, 0);
+}
+
+// Turns a point on the screen back into a position relative to the camera.
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = ssao.inv_proj * ndc;
+    return position.xyz / position.w;
+}
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let depth = depth_at(uv);
+    let position = view_position(uv, depth);
+
+    // There is no normal buffer, so the normal comes from how the position changes between neighboring pixels.
+    // This gives flat normals, which is all the cubes need.
+    var normal = normalize(cross(dpdx(position), dpdy(position)));
+    // The camera sits at the origin, so a normal facing it points back along the position
+    if (dot(normal, position) > 0.0) {
+        normal = -normal;
+    }
+
+    // Nothing was drawn here
+    let far_depth = select(1.0, 0.0, ssao.reversed_z != 0u);
+    if (depth == far_depth) {
+        return vec4<f32>(1.0);
+    }
+
+    // Rotating the kernel a different way for each pixel in the tile trades banding for noise, which the blur removes.
+    let noise_size = vec2<i32>(textureDimensions(t_noise));
+    let coords = vec2<i32>(uv * vec2<f32>(textureDimensions(t_depth)));
+    let random = textureLoad(t_noise, coords % noise_size, 0).xyz * 2.0 - 1.0;
+
+    let tangent = normalize(random - normal * dot(random, normal));
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3<f32>(tangent, bitangent, normal);
+
+    let sample_count = clamp(ssao.sample_count, 1u, MAX_SAMPLES);
+    var occlusion = 0.0;
+
+    for (var i = 0u; i < sample_count; i = i + 1u) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+
+        // Project the sample onto the screen to find what is actually drawn there
+        let clip = ssao.proj * vec4<f32>(sample_position, 1.0);
+        let sample_uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+        let scene_z = view_position(sample_uv, depth_at(sample_uv)).z;
+
+        // Geometry far outside the radius shouldn't count. Stops objects in front darkening the background around them.
+        let range_check = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene_z));
+
+        // The camera looks down -z, so a bigger z is closer to the camera
+        if (scene_z >= sample_position.z + ssao.bias) {
+            occlusion = occlusion + range_check;
+        }
+    }
+
+    let ao = pow(1.0 - occlusion / f32(sample_count), ssao.intensity);
+    return vec4<f32>(vec3<f32>(ao), 1.0);
+}
@@ -0,0 +1,23 @@
+@group(0) @binding(0)
+var t_ao: texture_2d<f32>;
+
+// Same size as the SSAO noise texture, so every kernel rotation is averaged together.
+let BLUR_SIZE: i32 = 4;
+
+// Outputs the blurred ambient occlusion factor. Multiply blending applies it to the scene.
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_ao));
+    let center = vec2<i32>(uv * vec2<f32>(size));
+
+    var total = 0.0;
+    for (var y = -BLUR_SIZE / 2; y < BLUR_SIZE / 2; y = y + 1) {
+        for (var x = -BLUR_SIZE / 2; x < BLUR_SIZE / 2; x = x + 1) {
+            let coords = clamp(center + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            total = total + textureLoad(t_ao, coords, 0).r;
+        }
+    }
+
+    let ao = total / f32(BLUR_SIZE * BLUR_SIZE);
+    return vec4<f32>(vec3<f32>(ao), 1.0);
+}
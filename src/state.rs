@@ -1,67 +1,291 @@
-use bytemuck::cast_slice;
-use cgmath::{perspective, Deg, InnerSpace, Matrix4, Point3, Quaternion, Rotation3, Vector3, Zero};
+use std::{
+    f32::consts::TAU,
+    fs,
+    path::Path,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bytemuck::{bytes_of, cast_slice};
+use cgmath::{Deg, InnerSpace, Matrix4, Quaternion, Rotation, Rotation3, Vector3, Zero};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
-    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState,
-    Buffer, BufferBindingType, BufferUsages, ColorTargetState, ColorWrites,
+    util::{BufferInitDescriptor, DeviceExt, StagingBelt},
+    AddressMode, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BlendState, Buffer,
+    BufferAddress, BufferSize, BufferUsages, ColorTargetState, ColorWrites, CommandBuffer,
     CommandEncoderDescriptor, CompareFunction, CompositeAlphaMode, DepthBiasState,
-    DepthStencilState, FragmentState, FrontFace, IndexFormat, Limits, LoadOp, MultisampleState,
-    Operations, PipelineLayoutDescriptor, PolygonMode, PresentMode, PrimitiveState,
-    PrimitiveTopology, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
-    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
-    ShaderSource, ShaderStages, StencilState, SurfaceConfiguration, TextureSampleType,
-    TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexState,
+    DepthStencilState, DownlevelFlags, Face, FragmentState, FrontFace, IndexFormat, Limits, LoadOp,
+    Maintain, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
+    PresentMode, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState,
+    SurfaceConfiguration, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+    VertexState,
+};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::WindowEvent,
+    window::{CursorGrabMode, Window},
 };
-use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
 
 use crate::{
-    model::{Model, Vertex},
-    resources::{load_model, DrawModel},
+    grid::Grid,
+    impostor::Impostors,
+    labels::Labels,
+    layouts::BindGroupLayouts,
+    model::{material_buffer, texture_bind_group, Material, Specular, Vertex},
+    morph::Morph,
+    object_transform::{self, with_object_transform},
+    outline::{Outline, OUTLINE_STENCIL_REFERENCE},
+    particles::Particles,
+    picking::{ObjectIdPass, PickGeometry},
+    profiler::{GpuPass, GpuTimer, RenderStats},
+    resources::{
+        flat_normal_map, load_camera_path, load_hdr_texture, load_model, load_morph_target,
+        load_texture, DrawModel, DrawShape, DrawSilhouette,
+    },
+    scene::{Scene, SceneObject},
+    scene_pipeline::ScenePipelineConfig,
+    screenshot,
+    shader_constants::with_constants,
+    shadow::{Shadow, SHADOW_MAP_SIZE},
 };
 
 use crate::{
-    camera::{Camera, CameraController, CameraUniform},
-    instance::{self, Instance, InstanceRaw},
+    accumulation::Accumulation,
+    background::{Background, BackgroundMode},
+    calibration::Calibration,
+    camera::{
+        self, ease_in_out, Camera, CameraController, CameraMode, CameraState, CameraUniform,
+        KeyBindings, Projection, TimeUniform,
+    },
+    camera_path::CameraPath,
+    color::hsv_to_rgb,
+    debug_lines::DebugLines,
+    depth_view::DepthView,
+    dof::DepthOfField,
+    environment::{self, IrradianceUniform},
+    fog::HeightFog,
+    fxaa::Fxaa,
+    instance::{ground_point, sorted_order, Instance, InstanceRaw, InstanceSort, MATERIAL_TINTS},
+    light::{LightRaw, Lights, MAX_LIGHTS},
     model::ModelVertex,
-    texture::Texture,
+    ssao::Ssao,
+    texture::{SamplerConfig, Texture},
+    tonemap::ToneMapping,
+    upscale::Upscale,
     vertex::{INDICES, VERTICES},
+    wind::Wind,
 };
 
+// How many pixels each fragment shader invocation covers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShadingRate {
+    Full,
+    // One invocation shades a 2x2 block of pixels. Only meaningful with variable rate shading (VRS).
+    Coarse2x2,
+}
+
 pub struct State {
-    surface: wgpu::Surface,
+    // None when headless
+    surface: Option<wgpu::Surface>,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    // Also describes the offscreen target when headless, which nothing presents
     config: wgpu::SurfaceConfiguration,
     pub size: PhysicalSize<u32>,
-    window: Window,
+    window: Option<Window>,
+    // What the title bar starts with, before the stats
+    window_title: String,
+    // What `render` draws into when there's no surface
+    headless_target: Option<Texture>,
+    // Kept to rebuild the scene pipelines when the sample count changes
+    shader: ShaderModule,
+    render_pipeline_layout: PipelineLayout,
     render_pipeline: RenderPipeline,
+    // Blends transparent materials over the opaque scene without writing depth
+    transparent_pipeline: RenderPipeline,
+    // None when the adapter can't draw lines
+    wireframe_pipeline: Option<RenderPipeline>,
+    wireframe: bool,
+    // How the pentagon's vertices are assembled into primitives
+    topology: PrimitiveTopology,
+    // Draws the pentagon with `topology`. None while that's TriangleList, which render_pipeline covers.
+    shape_pipeline: Option<RenderPipeline>,
+    // What the main pass clears the background to
+    clear_color: wgpu::Color,
+    // Off leaves the last frame underneath the next one, for trails
+    clear_enabled: bool,
+    accumulation: Accumulation,
     vertex_buffer: Buffer,
     num_vertices: u32,
+    // How many times the pentagon's texture repeats across it
+    texture_tiling: f32,
     index_buffer: Buffer,
     num_indices: u32,
     diffuse_bind_group: BindGroup,
     diffuse_texture: Texture,
+    // Kept so texture bind groups can be rebuilt when their samplers change
+    layouts: BindGroupLayouts,
+    flat_normal_texture: Texture,
+    // The pentagon doesn't glow, so this only needs to be something to bind
+    white_texture: Texture,
+    diffuse_material_buffer: Buffer,
     camera: Camera,
     camera_uniform: CameraUniform,
     camera_buffer: Buffer,
+    // Scene time for shaders, next to the camera in its bind group
+    time_buffer: Buffer,
     camera_bind_group: BindGroup,
     camera_controller: CameraController,
-    instances: Vec<Instance>,
-    instance_buffer: Buffer,
+    // The first one is the main light, which the hue wheel and `set_light_position` change
+    lights: Lights,
+    light_buffer: Buffer,
+    light_bind_group: BindGroup,
+    shadow: Shadow,
+    // Spherical harmonics of `environment`'s diffuse light, which replaces the lights' flat ambient term
+    irradiance_buffer: Buffer,
+    environment: Option<Texture>,
+    // In degrees. None keeps the light's own color instead of one from the hue wheel.
+    light_hue: Option<f32>,
+    // Copies the camera and light uniforms over at the start of every frame.
+    // Behind a mutex since the belt can't be shared between the encoding threads otherwise.
+    staging_belt: Mutex<StagingBelt>,
+    // The cube model and its instances come first, as the primary object
+    scene: Scene,
+    // How fast every instance spins around the y axis, per second
+    rotation_speed: Deg<f32>,
+    // How far they've spun so far, wrapped to a full turn
+    spin_angle: Deg<f32>,
     depth_texture: Texture,
-    obj_model: Model,
+    // Samples per pixel in the main pass. 1 turns MSAA off.
+    sample_count: u32,
+    // Which faces the scene pipelines skip
+    cull_mode: Option<Face>,
+    // Draws both sides of every face and lights the back ones too, overriding cull_mode
+    double_sided: bool,
+    // Offset added to the depth the scene pipelines write and test
+    depth_bias: DepthBiasState,
+    // None when MSAA is off
+    msaa_targets: Option<MsaaTargets>,
+    supports_vrs: bool,
+    shading_rate: ShadingRate,
+    // Index of the instance that gets a selection outline
+    selected: Option<usize>,
+    outline: Outline,
+    // Format for offscreen color targets (post-processing, screenshots, render-to-texture)
+    render_target_format: TextureFormat,
+    // Picked once from Texture::DEPTH_FORMATS
+    depth_format: TextureFormat,
+    impostors: Impostors,
+    labels: Labels,
+    grid: Grid,
+    debug_lines: DebugLines,
+    background: Background,
+    // Draws instance indices for `pick`
+    object_ids: ObjectIdPass,
+    // None when the adapter can't run compute shaders
+    particles: Option<Particles>,
+    sort_mode: InstanceSort,
+    // Size of the square grid the cubes were last laid out in
+    instances_per_row: u32,
+    // instance_order[slot] is the index in `instances` of whatever sits at that slot of the instance buffer
+    instance_order: Vec<usize>,
+    morph: Morph,
+    wind: Wind,
+    // The scene is drawn here instead of the surface when a post-processing pass needs to read it
+    scene_texture: Texture,
+    dof: DepthOfField,
+    ssao: Ssao,
+    fog: HeightFog,
+    depth_view: DepthView,
+    // Where the scene ends up before tone mapping, after depth of field if that's on too
+    post_texture: Texture,
+    tone_mapping: ToneMapping,
+    fxaa: Fxaa,
+    // The scene's resolution relative to the window's
+    render_scale: f32,
+    upscale: Upscale,
+    // Gamma test pattern drawn over the whole frame
+    calibration: Calibration,
+    // Records the scene and post-processing on separate threads
+    threaded_encoding: bool,
+    // CPU time spent recording commands last frame
+    encode_time: Duration,
+    // None when the adapter has no timestamp queries
+    gpu_timer: Option<GpuTimer>,
+    render_stats: Option<RenderStats>,
+    // Frames per second and milliseconds per frame, from the last FrameTimer tick
+    frame_rate: Option<(f32, f32)>,
+    cursor_position: PhysicalPosition<f64>,
+    // Hidden and held by the window for mouse look
+    mouse_captured: bool,
+    // None if camera-path.json couldn't be loaded
+    camera_path: Option<CameraPath>,
+    // Plays camera_path instead of using the CameraController
+    follow_path: bool,
+    path_time: f32,
+    // Where reset_camera or load_camera is easing the camera to
+    camera_transition: Option<CameraTransition>,
+    camera_transition_duration: Duration,
+    // Circles the camera around its target by itself until a movement key is pressed
+    auto_orbit: bool,
+    // Radians around the Y axis, and the distance from the target along the ground
+    orbit_angle: f32,
+    orbit_radius: f32,
+    // Freezes the scene's animations. The camera still moves.
+    paused: bool,
+    // How far the animations have run, not counting time spent paused
+    scene_time: Duration,
+}
+
+// The camera easing from wherever it was to `target`, over State's camera_transition_duration.
+struct CameraTransition {
+    target: CameraState,
+    elapsed: Duration,
+}
+
+// Multisampled targets the main pass draws into when MSAA is on. The color gets resolved into the
+// scene view at the end of the pass, and the depth is thrown away.
+struct MsaaTargets {
+    color: Texture,
+    depth: Texture,
 }
 
+// How long reset_camera and load_camera take to ease the camera over, to begin with
+const CAMERA_TRANSITION_DURATION: Duration = Duration::from_millis(600);
+// Radians per second the auto orbit turns the camera
+const AUTO_ORBIT_SPEED: f32 = 0.3;
+
+// Until set_window_title is called
+const WINDOW_TITLE: &str = "wgpu-learning";
 const NUM_INSTANCES_PER_ROW: u32 = 10;
-const INSTANCE_DISPLACEMENT: Vector3<f32> = Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-    0.0,
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-);
+// 40000 instances. Enough to stress instancing without running out of memory.
+const MAX_INSTANCES_PER_ROW: u32 = 200;
+// The surface never gets smaller than this. Windows are kept from going smaller too, so they match.
+pub const MIN_SIZE: PhysicalSize<u32> = PhysicalSize::new(64, 64);
+// Offscreen color format used unless one is asked for, and by headless states. HDR, so tone mapping has range to work with.
+pub const DEFAULT_RENDER_TARGET_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+// Range of set_render_scale. 2 renders 4 times the pixels, 0.5 a quarter.
+const MIN_RENDER_SCALE: f32 = 0.5;
+const MAX_RENDER_SCALE: f32 = 2.0;
+// Block compress textures as they load to save VRAM. Falls back to uncompressed if the GPU can't use BC formats.
+const COMPRESS_TEXTURES: bool = true;
+// Every level has 4 times the triangles of the last
+const MAX_SUBDIVISION: u32 = 4;
+// Time that passes between turntable frames, for anything animated
+const TURNTABLE_FRAME_TIME: Duration = Duration::from_millis(1000 / 30);
+// Bytes in each of the staging belt's buffers. Plenty for the camera and light uniforms together.
+const STAGING_CHUNK_SIZE: BufferAddress = 1024;
+// How fast the pentagon's texture slides, in texture widths per second
+const PENTAGON_UV_SCROLL: [f32; 2] = [0.1, 0.0];
 
 impl State {
-    pub async fn new(window: Window) -> Self {
+    // `render_target_format` is what the scene and post-processing draw in, e.g. Rgba8UnormSrgb, Rgba16Float or
+    // Rgba32Float. Formats the adapter can't render to and filter fall back to Rgba8UnormSrgb with a warning.
+    pub async fn new(window: Window, render_target_format: TextureFormat) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
         // A handle to the GPU
@@ -82,42 +306,147 @@ impl State {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    // https://docs.rs/wgpu/latest/wgpu/struct.Features.html
-                    features: wgpu::Features::empty(),
-                    limits: Limits::default(),
-                    label: None,
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+            .context("No suitable GPU adapter found")?;
+
+        let (device, queue) = request_device(&adapter).await?;
+
+        let format = *surface
+            .get_supported_formats(&adapter)
+            .first()
+            .context("The GPU adapter can't draw to this window")?;
 
         let config = SurfaceConfiguration {
             // Means we want textures to write to the screen
             usage: TextureUsages::RENDER_ATTACHMENT,
             // Preferred format the GPU wants to store the SurfaceTexture(s)
-            format: surface.get_supported_formats(&adapter)[0],
+            format,
             // width and height of the SurfaceTexture
             width: size.width,
             height: size.height,
-            // Forces Vsync to monitor refresh rate.
+            // Forces Vsync to monitor refresh rate. Can be changed later with `set_present_mode`.
             // https://docs.rs/wgpu/latest/wgpu/enum.PresentMode.html
-            // To allow user selection: `let modes = surface.get_supported_modes(&adapter);`
             present_mode: PresentMode::Fifo,
             alpha_mode: CompositeAlphaMode::Auto,
         };
 
         surface.configure(&device, &config);
+        log::info!(
+            "Surface configured: {}x{} {:?}, {:?}",
+            config.width,
+            config.height,
+            config.format,
+            config.present_mode
+        );
+
+        Self::with_device(
+            adapter,
+            device,
+            queue,
+            config,
+            render_target_format,
+            Some(surface),
+            Some(window),
+        )
+        .await
+    }
+
+    // Renders without a window, e.g. for tests or CI. `render` draws offscreen and `capture_frame` saves the result.
+    // Things that need a window, like the title and mouse capture, do nothing or return an error.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(Backends::all());
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .context("No suitable GPU adapter found")?;
+
+        let (device, queue) = request_device(&adapter).await?;
+
+        // Stands in for the surface. Nothing presents it, but everything sized or formatted after the surface reads it.
+        let config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            // Encodes to sRGB like most surfaces do, and can be saved as a PNG
+            format: Texture::FALLBACK_RENDER_TARGET_FORMAT,
+            width: width.max(MIN_SIZE.width),
+            height: height.max(MIN_SIZE.height),
+            present_mode: PresentMode::Fifo,
+            alpha_mode: CompositeAlphaMode::Auto,
+        };
+
+        Self::with_device(
+            adapter,
+            device,
+            queue,
+            config,
+            DEFAULT_RENDER_TARGET_FORMAT,
+            None,
+            None,
+        )
+        .await
+    }
+
+    // Everything after the adapter and surface are picked, shared by the windowed and headless states.
+    async fn with_device(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: SurfaceConfiguration,
+        render_target_format: TextureFormat,
+        surface: Option<wgpu::Surface>,
+        window: Option<Window>,
+    ) -> anyhow::Result<Self> {
+        let size = PhysicalSize::new(config.width, config.height);
+
+        let info = adapter.get_info();
+        log::info!(
+            "Adapter: {} ({:?}, {:?})",
+            info.name,
+            info.backend,
+            info.device_type
+        );
+
+        let supports_vrs = supports_variable_rate_shading(&adapter);
+
+        let headless_target = surface.is_none().then(|| {
+            Texture::create_render_target(
+                &device,
+                config.width,
+                config.height,
+                config.format,
+                "headless_target",
+            )
+        });
+
+        // The scene and post-processing draw in this, and the last pass converts to the surface's format
+        let render_target_format =
+            Texture::render_target_format(&adapter, &device, render_target_format);
+        log::info!("Render target format: {:?}", render_target_format);
+        // Every depth texture and every pipeline that draws into one uses this
+        let depth_format = Texture::depth_format(&adapter, &device);
+        log::info!("Depth format: {:?}", depth_format);
+        // Without them each object's transform is baked into its instances instead
+        let push_constants = device.features().contains(wgpu::Features::PUSH_CONSTANTS);
+        log::info!("Push constants: {}", push_constants);
 
         // Textures
         let diffuse_bytes = include_bytes!("assets/happy-tree.png");
-        let diffuse_texture =
-            Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
+        let diffuse_image =
+            image::load_from_memory(diffuse_bytes).context("Couldn't decode happy-tree.png")?;
+        let diffuse_texture = Texture::from_image(
+            &device,
+            &queue,
+            &diffuse_image,
+            Some("happy-tree.png"),
+            COMPRESS_TEXTURES,
+            true,
+            false,
+            SamplerConfig::DEFAULT,
+        )
+        .context("Couldn't create the pentagon's texture")?;
 
         // let diffuse_rgba = diffuse_image.to_rgba8();
 
@@ -182,169 +511,188 @@ impl State {
         //     ..Default::default()
         // });
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[
-                    // For sampled texture
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: TextureViewDimension::D2,
-                            sample_type: TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    // For sampler
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let layouts = BindGroupLayouts::new(&device);
+
+        // The pentagon is flat
+        let flat_normal_texture =
+            flat_normal_map(&device, &queue).context("Couldn't create the flat normal map")?;
+
+        let white_texture = Texture::from_color(&device, &queue, [u8::MAX; 4])
+            .context("Couldn't create the white texture")?;
+
+        let diffuse_material_buffer =
+            material_buffer(&device, Specular::DEFAULT, [0.0; 3], PENTAGON_UV_SCROLL);
 
         // BindGroup is a more specific decleration of the BindGroupLayout
-        let diffuse_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
+        let diffuse_bind_group = texture_bind_group(
+            &device,
+            &layouts.texture,
+            &diffuse_texture,
+            &flat_normal_texture,
+            &white_texture,
+            &white_texture,
+            &diffuse_material_buffer,
+        );
+
+        let camera = Camera::default_for(&config);
+
+        let mut camera_uniform = CameraUniform::new();
+
+        camera_uniform.update_view_proj(&camera);
+
+        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Camera Uniform Buffer"),
+            contents: cast_slice(&[camera_uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let time_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Time Uniform Buffer"),
+            contents: bytes_of(&TimeUniform::new(Duration::ZERO)),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &layouts.camera,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: BindingResource::TextureView(&diffuse_texture.view),
+                    resource: camera_buffer.as_entire_binding(),
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: BindingResource::Sampler(&diffuse_texture.sampler),
+                    resource: time_buffer.as_entire_binding(),
                 },
             ],
-            label: Some("diffuse_bind_group"),
+            label: Some("camera_bind_group"),
         });
 
-        let camera = Camera {
-            // Camera is 1 unit up and 2 units back
-            eye: (0.0, 1.0, 2.0).into(),
-            // This looks at the origin point
-            target: (0.0, 0.0, 0.0).into(),
-            // This says which way is "up"
-            up: Vector3::unit_y(),
-            aspect: config.width as f32 / config.height as f32,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 100.0,
-        };
-
-        let mut camera_uniform = CameraUniform::new();
+        let camera_controller = CameraController::new(6.0, CameraMode::Fly);
 
-        camera_uniform.update_view_proj(&camera);
+        // Above the middle of the grid, slightly warm
+        let mut lights = Lights::new();
+        lights.push(LightRaw::new([4.0, 6.0, 4.0], [1.0, 0.95, 0.9]));
 
-        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Camera Uniform Buffer"),
-            contents: cast_slice(&[camera_uniform]),
+        let light_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Light Uniform Buffer"),
+            contents: bytes_of(&lights),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                // Only accessing vertex index of shader because the camera can use that to manipulate the vertices
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        // Will the buffer change size or not?
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
+        let shadow = Shadow::new(&device, SHADOW_MAP_SIZE, depth_format, push_constants);
 
-        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
+        // Flat ambient until an environment is set
+        let irradiance_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Irradiance Uniform Buffer"),
+            contents: bytes_of(&IrradianceUniform::flat()),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let camera_controller = CameraController::new(0.1);
+        let [shadow_uniform, shadow_map, shadow_sampler] = shadow.bind_group_entries();
+        let light_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &layouts.light,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                shadow_uniform,
+                shadow_map,
+                shadow_sampler,
+                BindGroupEntry {
+                    binding: 4,
+                    resource: irradiance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_bind_group"),
+        });
 
         // shortcut
         // let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
 
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Shader"),
-            source: ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                with_object_transform(
+                    &with_constants(
+                        include_str!("shader.wgsl"),
+                        &[("MAX_LIGHTS", MAX_LIGHTS as u32)],
+                    ),
+                    push_constants,
+                )
+                .into(),
+            ),
         });
 
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-            push_constant_ranges: &[],
+            bind_group_layouts: &[&layouts.texture, &layouts.camera, &layouts.light],
+            // Only vs_main moves by the object transform. Morphing and wind only ever draw the primary object.
+            push_constant_ranges: object_transform::push_constant_ranges(push_constants),
         });
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                // references the entry point for the vertex shader
-                entry_point: "vs_main",
-                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                // references the entry point for the fragment shader
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    format: config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            // how to interpret the vertices when converting to triangles
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                // Tells WGPU if a triangle is facing the camera or not.
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less, // Tells us when to discard a pixel. LESS means pixels will be drawn front to back.
-                stencil: StencilState::default(), // Controls values for stencil testing. We are not using this.
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState {
-                // how many samples the pipeline will use
-                count: 1,
-                // Specifies which samples are used. Here we are using all.
-                mask: !0,
-                // Anti-Aliasing related
-                alpha_to_coverage_enabled: false,
+        let outline = Outline::new(
+            &device,
+            render_target_format,
+            depth_format,
+            &layouts.camera,
+            [1.0, 0.6, 0.0, 1.0],
+        );
+
+        let depth_texture =
+            Texture::create_depth_texture(&device, &config, depth_format, "depth_texture");
+
+        // Back faces of closed meshes are never seen, so they're skipped
+        let cull_mode = Some(Face::Back);
+        let pipeline_config = ScenePipelineConfig {
+            format: render_target_format,
+            depth_format,
+            polygon_mode: PolygonMode::Fill,
+            cull_mode,
+            double_sided: false,
+            transparent: false,
+            sample_count: 1,
+            depth_compare: CompareFunction::Less,
+            depth_bias: DepthBiasState::default(),
+            topology: PrimitiveTopology::TriangleList,
+        };
+        let render_pipeline =
+            create_render_pipeline(&device, &render_pipeline_layout, &shader, &pipeline_config);
+        let transparent_pipeline = create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            &ScenePipelineConfig {
+                transparent: true,
+                ..pipeline_config
             },
-            // Indicates how many array layers the render attachments can have. We are not rendering any to array textures (None)
-            multiview: None,
-        });
+        );
+
+        // Only some adapters can rasterize lines, so there might not be a wireframe view
+        let wireframe_pipeline = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| {
+                create_render_pipeline(
+                    &device,
+                    &render_pipeline_layout,
+                    &shader,
+                    &ScenePipelineConfig {
+                        polygon_mode: PolygonMode::Line,
+                        ..pipeline_config
+                    },
+                )
+            });
 
+        let vertices = VERTICES
+            .iter()
+            .map(|vertex| vertex.to_model_vertex())
+            .collect::<Vec<_>>();
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&vertices),
+            // Rewritten when the texture tiling changes
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
         });
 
         let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -357,171 +705,2518 @@ impl State {
 
         let num_vertices = VERTICES.len() as u32;
 
-        const SPACE_BETWEEN: f32 = 3.0;
-
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0))
-                    } else {
-                        Quaternion::from_axis_angle(position.normalize(), Deg(45.0))
-                    };
-
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-
-        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: cast_slice(&instance_data),
-            usage: BufferUsages::VERTEX,
-        });
-
-        let obj_model = load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
+        let instances = grid_instances(NUM_INSTANCES_PER_ROW);
+
+        let obj_model = load_model(
+            "cube.obj",
+            COMPRESS_TEXTURES,
+            None,
+            &device,
+            &queue,
+            &layouts,
+        )
+        .await
+        .context("Couldn't load cube.obj")?;
+
+        // cube-morph.obj is the cube pushed out into a sphere
+        let morph_targets = load_morph_target("cube-morph.obj", &obj_model, &device)
             .await
-            .unwrap();
+            .context("Couldn't load cube-morph.obj")?;
+        let morph = Morph::new(&device, &shader, pipeline_config, &layouts, morph_targets);
+
+        // The cube goes from -1 to 1, so its bottom face stays planted
+        let wind = Wind::new(&device, &shader, pipeline_config, &layouts, -1.0, 2.0);
+
+        let scene_texture = Texture::create_render_target(
+            &device,
+            config.width,
+            config.height,
+            render_target_format,
+            "scene_texture",
+        );
+
+        let dof = DepthOfField::new(
+            &device,
+            render_target_format,
+            &scene_texture,
+            &depth_texture,
+            &camera,
+        );
+
+        let ssao = Ssao::new(
+            &device,
+            &queue,
+            render_target_format,
+            config.width,
+            config.height,
+            &depth_texture,
+            &camera,
+        );
+
+        let fog = HeightFog::new(&device, render_target_format, &depth_texture, &camera);
+        let depth_view = DepthView::new(&device, render_target_format, &depth_texture, &camera);
+
+        let post_texture = Texture::create_render_target(
+            &device,
+            config.width,
+            config.height,
+            render_target_format,
+            "post_texture",
+        );
+
+        let tone_mapping = ToneMapping::new(&device, render_target_format, &post_texture);
+        let fxaa = Fxaa::new(&device, render_target_format, config.width, config.height);
+        let upscale = Upscale::new(
+            &device,
+            render_target_format,
+            config.format,
+            config.width,
+            config.height,
+        );
+        let accumulation =
+            Accumulation::new(&device, render_target_format, config.width, config.height);
+
+        let calibration = Calibration::new(&device, config.format);
+
+        let impostors = Impostors::new(
+            &device,
+            &queue,
+            render_target_format,
+            depth_format,
+            &layouts.camera,
+            &render_pipeline,
+            &light_bind_group,
+            &obj_model,
+            instances.len(),
+        );
+
+        let labels = Labels::new(
+            &device,
+            &queue,
+            render_target_format,
+            depth_format,
+            &layouts.camera,
+            instances.len(),
+        );
+
+        let grid = Grid::new(&device, render_target_format, depth_format, &layouts.camera);
+        let debug_lines =
+            DebugLines::new(&device, render_target_format, depth_format, &layouts.camera);
+        let object_ids = ObjectIdPass::new(
+            &device,
+            depth_format,
+            &layouts.camera,
+            config.width,
+            config.height,
+        );
+        let background = Background::new(
+            &device,
+            render_target_format,
+            depth_format,
+            config.width,
+            config.height,
+        );
+
+        let particles = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(DownlevelFlags::COMPUTE_SHADERS)
+            .then(|| Particles::new(&device, render_target_format, depth_format, &layouts.camera));
+
+        let instance_order = (0..instances.len()).collect();
+        let mut scene = Scene::new(push_constants);
+        scene.add(&device, obj_model, instances);
+
+        let gpu_timer = GpuTimer::new(&device, &queue);
+
+        let camera_path = match load_camera_path("camera-path.json").await {
+            Ok(path) => Some(path),
+            Err(e) => {
+                log::warn!("Couldn't load the camera path: {}", e);
+                None
+            }
+        };
 
-        Self {
+        // Everything starts out single sampled so the impostors above could be baked with the main pipeline
+        Ok(Self {
             surface,
+            adapter,
             device,
             queue,
             config,
             size,
             window,
+            window_title: WINDOW_TITLE.to_string(),
+            headless_target,
+            shader,
+            render_pipeline_layout,
             render_pipeline,
+            transparent_pipeline,
+            wireframe_pipeline,
+            topology: PrimitiveTopology::TriangleList,
+            shape_pipeline: None,
+            wireframe: false,
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            clear_enabled: true,
+            accumulation,
             vertex_buffer,
             index_buffer,
             num_indices,
             num_vertices,
+            texture_tiling: 1.0,
             diffuse_bind_group,
             camera_controller,
+            lights,
+            light_buffer,
+            light_hue: None,
+            light_bind_group,
+            shadow,
+            irradiance_buffer,
+            environment: None,
             diffuse_texture,
+            layouts,
+            flat_normal_texture,
+            white_texture,
+            diffuse_material_buffer,
             camera,
             camera_uniform,
             camera_buffer,
+            time_buffer,
             camera_bind_group,
-            instance_buffer,
-            instances,
+            rotation_speed: Deg(0.0),
+            spin_angle: Deg(0.0),
             depth_texture,
-            obj_model,
-        }
+            sample_count: 1,
+            cull_mode,
+            double_sided: false,
+            depth_bias: DepthBiasState::default(),
+            msaa_targets: None,
+            supports_vrs,
+            shading_rate: ShadingRate::Full,
+            selected: None,
+            outline,
+            render_target_format,
+            depth_format,
+            impostors,
+            labels,
+            grid,
+            debug_lines,
+            background,
+            object_ids,
+            particles,
+            sort_mode: InstanceSort::None,
+            instances_per_row: NUM_INSTANCES_PER_ROW,
+            instance_order,
+            scene,
+            morph,
+            wind,
+            scene_texture,
+            dof,
+            ssao,
+            fog,
+            depth_view,
+            post_texture,
+            tone_mapping,
+            fxaa,
+            render_scale: 1.0,
+            upscale,
+            calibration,
+            threaded_encoding: false,
+            encode_time: Duration::ZERO,
+            staging_belt: Mutex::new(StagingBelt::new(STAGING_CHUNK_SIZE)),
+            gpu_timer,
+            render_stats: None,
+            frame_rate: None,
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            mouse_captured: false,
+            camera_path,
+            follow_path: false,
+            path_time: 0.0,
+            camera_transition: None,
+            camera_transition_duration: CAMERA_TRANSITION_DURATION,
+            auto_orbit: false,
+            orbit_angle: 0.0,
+            orbit_radius: 0.0,
+            paused: false,
+            scene_time: Duration::ZERO,
+        })
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    // None when headless.
+    pub fn window(&self) -> Option<&Window> {
+        self.window.as_ref()
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
+        let Some(new_size) = surface_size(new_size) else {
+            return;
+        };
+
+        if new_size == self.size {
+            return;
+        }
+
+        self.size = new_size;
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        log::info!("Resized to {}x{}", new_size.width, new_size.height);
 
-            self.depth_texture =
-                Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.resize_targets();
+        self.object_ids
+            .resize(&self.device, new_size.width, new_size.height);
 
-            self.surface.configure(&self.device, &self.config);
+        if self.headless_target.is_some() {
+            self.headless_target = Some(self.create_output_target("headless_target"));
         }
+
+        self.reconfigure_surface();
     }
 
-    // Returns a bool based on wether an event has been fully processed or not.
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+    // The size everything the scene and post-processing draw into gets, the window's times the render scale.
+    fn internal_size(&self) -> PhysicalSize<u32> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        let scale =
+            |length: u32| ((length as f32 * self.render_scale).round() as u32).clamp(1, max);
+        PhysicalSize::new(scale(self.size.width), scale(self.size.height))
     }
 
-    pub fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue
-            .write_buffer(&self.camera_buffer, 0, cast_slice(&[self.camera_uniform]));
+    // Recreates the window sized targets at internal_size.
+    fn resize_targets(&mut self) {
+        let size = self.internal_size();
+
+        self.depth_texture = Texture::create_depth_texture_with_size(
+            &self.device,
+            size.width,
+            size.height,
+            self.depth_format,
+            "depth_texture",
+        );
+        self.scene_texture = self.create_render_target("scene_texture");
+        self.dof
+            .resize(&self.device, &self.scene_texture, &self.depth_texture);
+        self.ssao
+            .resize(&self.device, size.width, size.height, &self.depth_texture);
+        self.fog.resize(&self.device, &self.depth_texture);
+        self.depth_view.resize(&self.device, &self.depth_texture);
+        self.post_texture = self.create_render_target("post_texture");
+        self.tone_mapping.resize(&self.device, &self.post_texture);
+        self.fxaa.resize(&self.device, size.width, size.height);
+        self.accumulation
+            .resize(&self.device, size.width, size.height);
+        self.background.resize(&self.queue, size.width, size.height);
+        self.msaa_targets = create_msaa_targets(
+            &self.device,
+            size,
+            self.render_target_format,
+            self.depth_format,
+            self.sample_count,
+        );
+        self.upscale.resize(&self.device, size.width, size.height);
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+    // Configures the surface again without changing its size, e.g. after it was lost.
+    pub fn reconfigure_surface(&mut self) {
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
 
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
+    pub fn shading_rate(&self) -> ShadingRate {
+        self.shading_rate
+    }
 
-        // Creates a command encoder that sends commands to the GPU.
-        let mut encoder = self
-            .device
-            .create_command_encoder(&CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+    // Coarse rates need VRS. Without it we stay at full rate and let the caller know.
+    pub fn set_shading_rate(&mut self, rate: ShadingRate) -> anyhow::Result<()> {
+        if rate != ShadingRate::Full && !self.supports_vrs {
+            anyhow::bail!("Variable rate shading is unsupported on this adapter");
+        }
 
-        // Rust Tip: Releases any variables once block is done. Releases mut encoder.
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render Pass"),
-                // Draws color to the view (TextureView)
-                // This is what @location(0) in the fragment shader targets
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
+        self.shading_rate = rate;
+
+        Ok(())
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    // Outlines the given instance. Indices past the end of `instances` clear the selection.
+    pub fn select(&mut self, instance: Option<usize>) {
+        self.selected = instance.filter(|&i| i < self.scene.primary().instances.len());
+    }
+
+    // Moves the selection to the next instance, wrapping back around to nothing after the last one.
+    pub fn select_next(&mut self) {
+        let next = match self.selected {
+            Some(i) => i + 1,
+            None => 0,
+        };
+
+        self.select(Some(next));
+    }
+
+    pub fn set_outline_color(&mut self, color: [f32; 4]) {
+        self.outline.set_color(&self.queue, color);
+    }
+
+    // What the scene and post-processing draw in, picked from RENDER_TARGET_FORMAT when the state is made.
+    // Every pipeline drawing into it is built for it, so it can't change afterwards.
+    pub fn render_target_format(&self) -> TextureFormat {
+        self.render_target_format
+    }
+
+    // Offscreen color target for the scene and post-processing, at internal_size in the render target format.
+    fn create_render_target(&self, label: &str) -> Texture {
+        let size = self.internal_size();
+        Texture::create_render_target(
+            &self.device,
+            size.width,
+            size.height,
+            self.render_target_format,
+            label,
+        )
+    }
+
+    // Offscreen stand-in for the surface, e.g. to save a frame from. Same size and format as the surface,
+    // so the last pass draws into it like it would the screen, and screenshot.rs can encode it.
+    fn create_output_target(&self, label: &str) -> Texture {
+        Texture::create_render_target(
+            &self.device,
+            self.config.width,
+            self.config.height,
+            self.config.format,
+            label,
+        )
+    }
+
+    // Far away instances are drawn as flat pictures of the model instead of full geometry.
+    pub fn toggle_impostors(&mut self) {
+        self.impostors.set_enabled(!self.impostors.enabled());
+        self.update_title();
+    }
+
+    pub fn set_impostor_distance(&mut self, distance: f32) {
+        self.impostors.distance = distance;
+    }
+
+    // Shows each instance's index above it.
+    pub fn toggle_labels(&mut self) {
+        self.labels.enabled = !self.labels.enabled;
+    }
+
+    pub fn set_label_distance(&mut self, distance: f32) {
+        self.labels.max_distance = distance;
+    }
+
+    // Points the camera at the middle of the scene from far enough back to see all of it.
+    pub fn frame_scene(&mut self) {
+        if let Some(aabb) = self.scene.aabb() {
+            self.camera.frame_aabb(aabb.min, aabb.max);
+        }
+    }
+
+    // Puts the camera back where it started, dropping any camera path playback.
+    pub fn reset_camera(&mut self) {
+        let start = Camera::default_for(&self.config).state();
+        self.move_camera_to(start);
+    }
+
+    pub fn save_camera(&self, path: &Path) -> anyhow::Result<()> {
+        self.camera.save(path)
+    }
+
+    // Like reset_camera, but to wherever `path` says.
+    pub fn load_camera(&mut self, path: &Path) -> anyhow::Result<()> {
+        let state = CameraState::load(path)?;
+        self.move_camera_to(state);
+
+        Ok(())
+    }
+
+    pub fn camera_transition_duration(&self) -> Duration {
+        self.camera_transition_duration
+    }
+
+    // How long reset_camera and load_camera take to get there. Zero jumps straight there.
+    pub fn set_camera_transition_duration(&mut self, duration: Duration) {
+        self.camera_transition_duration = duration;
+    }
+
+    // Eases the camera over to `state`, or jumps if transitions are off. Stops any camera path playing.
+    fn move_camera_to(&mut self, state: CameraState) {
+        self.camera_controller.clear_shake(&mut self.camera);
+        self.camera_controller.reset();
+        self.follow_path = false;
+        self.auto_orbit = false;
+
+        if self.camera_transition_duration.is_zero() {
+            self.camera.set_state(state);
+            self.camera_transition = None;
+            self.camera_uniform.update_view_proj(&self.camera);
+        } else {
+            self.camera_transition = Some(CameraTransition {
+                target: state,
+                elapsed: Duration::ZERO,
             });
+        }
+    }
+
+    // Takes the camera one step further along the transition.
+    fn advance_camera_transition(&mut self, dt: Duration) {
+        let Some(transition) = &mut self.camera_transition else {
+            return;
+        };
+
+        let duration = self.camera_transition_duration.as_secs_f32();
+        let before = ease_in_out(transition.elapsed.as_secs_f32() / duration);
+        transition.elapsed += dt;
+        let after = ease_in_out(transition.elapsed.as_secs_f32() / duration);
 
-            render_pass.set_pipeline(&self.render_pipeline);
+        // lerp_to works from wherever the camera is now, so it gets the share of what's left that this step covers
+        let t = if before < 1.0 {
+            (after - before) / (1.0 - before)
+        } else {
+            1.0
+        };
+        self.camera.lerp_to(&transition.target, t);
 
-            // Takes the buffer slot to use the vertex buffer. Also takes the slice of the buffer to use. In this case: all of it.
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        if after >= 1.0 {
+            self.camera_transition = None;
+        }
+    }
 
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+    // Launches `n` sparks from the middle of the scene.
+    pub fn spawn_particles(&mut self, n: u32) -> anyhow::Result<()> {
+        let particles = self
+            .particles
+            .as_mut()
+            .context("This adapter can't run the compute shader particles need")?;
+        particles.spawn(&self.queue, n);
+        Ok(())
+    }
 
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+    // Draws a line in world space until the next update, so call it every frame to keep one up.
+    pub fn debug_line(&mut self, start: [f32; 3], end: [f32; 3], color: [f32; 3]) {
+        self.debug_lines.push(start, end, color);
+    }
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+    // Lines on the ground plane to judge positions by.
+    pub fn toggle_grid(&mut self) {
+        self.grid.enabled = !self.grid.enabled;
+    }
 
-            // Can only have one index buffer per render pass.
-            render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
+    pub fn sort_mode(&self) -> InstanceSort {
+        self.sort_mode
+    }
 
-            // Drawing something with 3 vertices and 1 instance. This is where @builtin(vertex_index) comes from.
-            // Draw ignores the index buffer
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+    pub fn set_sort_mode(&mut self, sort_mode: InstanceSort) {
+        self.sort_mode = sort_mode;
+        self.upload_instances();
+    }
 
-            let mesh = &self.obj_model.meshes[0];
-            let material = &self.obj_model.materials[mesh.material];
+    // Instance covering pixel (x, y), if any. Reads back from the GPU, so it waits for it to finish.
+    pub fn pick(&self, x: u32, y: u32) -> Option<usize> {
+        let primary = self.scene.primary();
+        let geometry = primary
+            .model
+            .meshes
+            .iter()
+            .map(|mesh| PickGeometry {
+                vertex_buffer: &mesh.vertex_buffer,
+                index_buffer: &mesh.index_buffer,
+                index_format: mesh.index_format,
+                num_indices: mesh.num_elements,
+            })
+            .collect::<Vec<_>>();
 
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
+        let slot = self
+            .object_ids
+            .pick(
+                &self.device,
+                &self.queue,
                 &self.camera_bind_group,
+                &geometry,
+                &primary.instance_buffer,
+                0..primary.instances.len() as u32,
+                x,
+                y,
+            )
+            .map_err(|e| log::warn!("Picking failed: {}", e))
+            .ok()??;
+
+        // Sorting can put instances in a different order in the buffer
+        self.instance_order.get(slot as usize).copied()
+    }
+
+    // Instance under the cursor, if any.
+    pub fn pick_under_cursor(&self) -> Option<usize> {
+        self.pick(self.cursor_position.x as u32, self.cursor_position.y as u32)
+    }
+
+    // Point on the ground (where the instance grid sits) under the cursor.
+    pub fn pick_ground(&self) -> Option<Vector3<f32>> {
+        let (origin, direction) = self.camera.screen_ray(self.cursor_uv())?;
+        ground_point(origin, direction, 0.0)
+    }
+
+    // Later instances move down an index to fill the gap.
+    pub fn remove_instance(&mut self, index: usize) {
+        if index >= self.scene.primary().instances.len() {
+            return;
+        }
+
+        self.scene.primary_mut().instances.remove(index);
+
+        // Keep the selection on the same instance
+        self.selected = match self.selected {
+            Some(selected) if selected == index => None,
+            Some(selected) if selected > index => Some(selected - 1),
+            selected => selected,
+        };
+
+        self.upload_instances();
+    }
+
+    pub fn rotation_speed(&self) -> Deg<f32> {
+        self.rotation_speed
+    }
+
+    // Spins every instance around the y axis by `speed` per second. Deg(0.0) stops them.
+    pub fn set_rotation_speed(&mut self, speed: Deg<f32>) {
+        self.rotation_speed = speed;
+    }
+
+    pub fn spin_angle(&self) -> Deg<f32> {
+        self.spin_angle
+    }
+
+    // Turns each instance a little further on top of whatever rotation it already has.
+    fn spin_instances(&mut self, dt: Duration) {
+        let step = self.rotation_speed * dt.as_secs_f32();
+        self.spin_angle = Deg((self.spin_angle + step).0.rem_euclid(360.0));
+
+        let spin = Quaternion::from_angle_y(step);
+        for instance in &mut self.scene.primary_mut().instances {
+            // Renormalized so rounding errors don't build up into a scale over many frames
+            instance.rotation = (spin * instance.rotation).normalize();
+        }
+    }
+
+    pub fn spawn_instance(&mut self, position: Vector3<f32>) {
+        let material_index =
+            self.scene.primary().instances.len() as u32 % MATERIAL_TINTS.len() as u32;
+        self.add_instance(new_instance(position, material_index));
+    }
+
+    // The instance buffer grows to fit, so there's no limit on how many can be added.
+    pub fn add_instance(&mut self, instance: Instance) {
+        self.scene.primary_mut().instances.push(instance);
+        self.upload_instances();
+    }
+
+    // Loads another model (OBJ or glTF) into the scene alongside the cubes. Returns its index in the scene.
+    pub async fn add_model(
+        &mut self,
+        file_name: &str,
+        instances: Vec<Instance>,
+    ) -> anyhow::Result<usize> {
+        let mut model = load_model(
+            file_name,
+            COMPRESS_TEXTURES,
+            None,
+            &self.device,
+            &self.queue,
+            &self.layouts,
+        )
+        .await?;
+
+        // Match the filtering everything else has been switched to
+        let sampler_config = self.texture_filter();
+        if sampler_config != SamplerConfig::DEFAULT {
+            set_material_filters(
+                &self.device,
+                &self.layouts,
+                &mut model.materials,
+                sampler_config,
             );
         }
 
-        // Builds command buffer and sends to GPU render queue.
-        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(self.scene.add(&self.device, model, instances))
+    }
 
-        output.present();
+    pub fn object_transform(&self, object: usize) -> Option<Matrix4<f32>> {
+        self.scene.objects.get(object).map(SceneObject::transform)
+    }
+
+    // Moves, turns or scales a whole object added with add_model, instances and all.
+    // The cubes (object 0) can't be moved this way, since picking and the outline work on their instances alone.
+    // Normals are only turned, so a non-uniform scale shades a little off.
+    pub fn set_object_transform(
+        &mut self,
+        object: usize,
+        transform: Matrix4<f32>,
+    ) -> anyhow::Result<()> {
+        if object == 0 {
+            anyhow::bail!("The primary object is placed by its instances");
+        }
+
+        let scene_object = self
+            .scene
+            .objects
+            .get_mut(object)
+            .with_context(|| format!("No object {}", object))?;
+        scene_object.set_transform(&self.device, &self.queue, transform);
+
+        Ok(())
+    }
+
+    // Multiplies a material's diffuse color by baked lighting from `file_name`, at the second UV set.
+    // `object` is the index add_model returned, 0 for the cubes.
+    pub async fn set_lightmap(
+        &mut self,
+        object: usize,
+        material: usize,
+        file_name: &str,
+    ) -> anyhow::Result<()> {
+        let texture = load_texture(file_name, COMPRESS_TEXTURES, &self.device, &self.queue).await?;
+
+        let material = self
+            .scene
+            .objects
+            .get_mut(object)
+            .and_then(|object| object.model.materials.get_mut(material))
+            .with_context(|| format!("No material {} on object {}", material, object))?;
+        material.set_lightmap(&self.device, &self.layouts, texture);
 
         Ok(())
     }
+
+    pub fn instances_per_row(&self) -> u32 {
+        self.instances_per_row
+    }
+
+    // Replaces the cubes with a fresh `n` by `n` grid, clamped to 1..=MAX_INSTANCES_PER_ROW.
+    // Spawned instances, removals and the selection are lost.
+    pub fn set_instances_per_row(&mut self, n: u32) {
+        self.instances_per_row = n.clamp(1, MAX_INSTANCES_PER_ROW);
+        self.scene.primary_mut().instances = grid_instances(self.instances_per_row);
+        self.selected = None;
+        self.upload_instances();
+    }
+
+    // Removes every instance. The instance buffer keeps its size for whatever gets added next.
+    pub fn clear_instances(&mut self) {
+        self.scene.primary_mut().instances.clear();
+        self.selected = None;
+        self.upload_instances();
+    }
+
+    // Writes the instances into the instance buffer in `sort_mode` order.
+    fn upload_instances(&mut self) {
+        self.instance_order = sorted_order(
+            &self.scene.primary().instances,
+            self.camera.eye,
+            self.sort_mode,
+        );
+
+        self.scene
+            .primary_mut()
+            .upload_instances(&self.device, &self.queue, &self.instance_order);
+    }
+
+    // Starts or stops blending the model between its base shape and the morph target.
+    pub fn toggle_morph(&mut self) {
+        self.morph.toggle_animation();
+    }
+
+    pub fn set_morph_weight(&mut self, weight: f32) {
+        self.morph.set_weight(&self.queue, weight);
+    }
+
+    pub fn subdivision(&self) -> u32 {
+        self.scene.primary().model.subdivision()
+    }
+
+    // Smooths out the model by splitting its triangles `levels` times.
+    pub fn set_subdivision(&mut self, levels: u32) {
+        let levels = levels.min(MAX_SUBDIVISION);
+        if levels == self.scene.primary().model.subdivision() {
+            return;
+        }
+
+        self.scene
+            .primary_mut()
+            .model
+            .subdivide(&self.device, levels);
+        self.update_title();
+    }
+
+    pub fn wind(&mut self) -> &mut Wind {
+        &mut self.wind
+    }
+
+    pub fn toggle_depth_of_field(&mut self) {
+        self.dof.enabled = !self.dof.enabled;
+        self.warn_if_depth_unreadable(self.dof.enabled);
+    }
+
+    pub fn depth_of_field(&mut self) -> &mut DepthOfField {
+        &mut self.dof
+    }
+
+    pub fn toggle_ssao(&mut self) {
+        self.ssao.enabled = !self.ssao.enabled;
+        self.warn_if_depth_unreadable(self.ssao.enabled);
+    }
+
+    pub fn ssao(&mut self) -> &mut Ssao {
+        &mut self.ssao
+    }
+
+    pub fn toggle_fog(&mut self) {
+        self.fog.enabled = !self.fog.enabled;
+        self.warn_if_depth_unreadable(self.fog.enabled);
+    }
+
+    pub fn fog(&mut self) -> &mut HeightFog {
+        &mut self.fog
+    }
+
+    // Replaces the scene with the depth buffer, black up close and white at the far plane.
+    pub fn toggle_depth_view(&mut self) {
+        self.depth_view.enabled = !self.depth_view.enabled;
+        self.warn_if_depth_unreadable(self.depth_view.enabled);
+    }
+
+    pub fn fxaa(&self) -> bool {
+        self.fxaa.enabled
+    }
+
+    // Cheap anti-aliasing as a post pass. Works alongside MSAA or instead of it, e.g. where MSAA isn't supported.
+    pub fn set_fxaa(&mut self, enabled: bool) {
+        self.fxaa.enabled = enabled;
+    }
+
+    pub fn toggle_tone_mapping(&mut self) {
+        self.tone_mapping.enabled = !self.tone_mapping.enabled;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.tone_mapping.exposure()
+    }
+
+    // Only has an effect while tone mapping is on.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.tone_mapping.set_exposure(&self.queue, exposure);
+    }
+
+    pub fn tone_mapping_gamma(&self) -> f32 {
+        self.tone_mapping.gamma()
+    }
+
+    pub fn set_tone_mapping_gamma(&mut self, gamma: f32) {
+        self.tone_mapping.set_gamma(&self.queue, gamma);
+    }
+
+    // Replaces the frame with a gamma test pattern.
+    pub fn toggle_calibration(&mut self) {
+        self.calibration.enabled = !self.calibration.enabled;
+
+        if self.calibration.enabled {
+            log::info!(
+                "sRGB surface: {}, gamma: {}",
+                self.calibration.srgb_surface(),
+                self.calibration.gamma()
+            );
+        }
+    }
+
+    pub fn calibration_gamma(&self) -> f32 {
+        self.calibration.gamma()
+    }
+
+    pub fn set_calibration_gamma(&mut self, gamma: f32) {
+        self.calibration.set_gamma(&self.queue, gamma);
+    }
+
+    // Autofocus uses whatever is under the cursor.
+    pub fn set_cursor_position(&mut self, position: PhysicalPosition<f64>) {
+        self.cursor_position = position;
+        self.dof.set_focus_point(self.cursor_uv());
+    }
+
+    // (0, 0) is the top left of the window.
+    fn cursor_uv(&self) -> [f32; 2] {
+        [
+            self.cursor_position.x as f32 / self.size.width as f32,
+            self.cursor_position.y as f32 / self.size.height as f32,
+        ]
+    }
+
+    pub fn toggle_threaded_encoding(&mut self) {
+        self.threaded_encoding = !self.threaded_encoding;
+    }
+
+    pub fn threaded_encoding(&self) -> bool {
+        self.threaded_encoding
+    }
+
+    pub fn encode_time(&self) -> Duration {
+        self.encode_time
+    }
+
+    // Shows how long each render pass takes on the GPU in the title bar.
+    pub fn toggle_gpu_timing(&mut self) -> anyhow::Result<()> {
+        let Some(timer) = &mut self.gpu_timer else {
+            anyhow::bail!("Timestamp queries are unsupported on this adapter");
+        };
+
+        timer.set_enabled(!timer.enabled());
+
+        if !timer.enabled() {
+            self.render_stats = None;
+            self.update_title();
+        }
+
+        Ok(())
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.config.present_mode
+    }
+
+    // What the GPU is and which API it's driven through. Worth pasting into rendering bug reports.
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    // The format frames are presented in. Headless states report the offscreen target's.
+    pub fn surface_format(&self) -> TextureFormat {
+        self.config.format
+    }
+
+    // Reconfigures the surface to present with `mode`. Modes the surface doesn't support fall back to Fifo,
+    // which is always available, and return an error.
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> anyhow::Result<()> {
+        let Some(surface) = &self.surface else {
+            anyhow::bail!("There's no surface to present to when headless");
+        };
+
+        let supported = surface
+            .get_supported_present_modes(&self.adapter)
+            .contains(&mode);
+
+        self.config.present_mode = if supported { mode } else { PresentMode::Fifo };
+        surface.configure(&self.device, &self.config);
+
+        if !supported {
+            anyhow::bail!("{:?} isn't supported by this surface, using Fifo", mode);
+        }
+
+        Ok(())
+    }
+
+    pub fn toggle_wireframe(&mut self) -> anyhow::Result<()> {
+        if self.wireframe_pipeline.is_none() {
+            anyhow::bail!("Wireframe needs line polygon mode, which this adapter doesn't support");
+        }
+
+        self.wireframe = !self.wireframe;
+
+        Ok(())
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    // Draws `file_name` behind the scene instead of the clear color.
+    pub async fn set_background_image(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let texture = load_texture(file_name, COMPRESS_TEXTURES, &self.device, &self.queue).await?;
+        self.background
+            .set_image(&self.device, &self.queue, Some(texture));
+        Ok(())
+    }
+
+    // Loads an equirectangular Radiance .hdr environment as a cubemap, e.g. for image-based lighting.
+    pub async fn load_environment(&self, file_name: &str) -> anyhow::Result<Texture> {
+        let equirect = load_hdr_texture(file_name, &self.device, &self.queue).await?;
+        Ok(environment::equirect_to_cubemap(
+            &self.device,
+            &self.queue,
+            &equirect,
+        ))
+    }
+
+    // Lights the scene's ambient term from an environment cubemap, such as one from load_environment.
+    pub fn set_environment(&mut self, environment: Texture) -> anyhow::Result<()> {
+        let coefficients = environment::irradiance_sh(&self.device, &self.queue, &environment)?;
+        self.queue.write_buffer(
+            &self.irradiance_buffer,
+            0,
+            bytes_of(&IrradianceUniform::from_coefficients(coefficients)),
+        );
+        self.environment = Some(environment);
+        Ok(())
+    }
+
+    pub fn environment(&self) -> Option<&Texture> {
+        self.environment.as_ref()
+    }
+
+    // Goes back to the lights' flat ambient term.
+    pub fn clear_environment(&mut self) {
+        self.queue.write_buffer(
+            &self.irradiance_buffer,
+            0,
+            bytes_of(&IrradianceUniform::flat()),
+        );
+        self.environment = None;
+    }
+
+    pub fn clear_background_image(&mut self) {
+        self.background.set_image(&self.device, &self.queue, None);
+    }
+
+    pub fn background_mode(&self) -> BackgroundMode {
+        self.background.mode()
+    }
+
+    pub fn set_background_mode(&mut self, mode: BackgroundMode) {
+        self.background.set_mode(&self.queue, mode);
+    }
+
+    pub fn clear_enabled(&self) -> bool {
+        self.clear_enabled
+    }
+
+    // Off draws each frame over the last one instead of the clear color, so anything that moves leaves a trail.
+    // Surface textures don't keep their contents between frames with most present modes, so without
+    // post-processing to draw into first, the scene goes into a texture of its own and gets copied over.
+    pub fn set_clear_enabled(&mut self, clear_enabled: bool) {
+        self.clear_enabled = clear_enabled;
+    }
+
+    // Whether the scene draws into the accumulation texture this frame. The post-processing
+    // targets already keep their contents, so it's only needed when none of them are in use.
+    fn accumulating(&self) -> bool {
+        let post_processing = (self.dof.enabled && self.depth_readable())
+            || self.tone_mapping.enabled
+            || self.fxaa.enabled;
+        !self.clear_enabled && !post_processing
+    }
+
+    // The stats shown in the title bar go after `title`.
+    pub fn set_window_title(&mut self, title: &str) {
+        self.window_title = title.to_string();
+        self.update_title();
+    }
+
+    pub fn texture_filter(&self) -> SamplerConfig {
+        self.diffuse_texture.sampler_config
+    }
+
+    // Refilters the pentagon's texture and every material in the scene.
+    // Materials that can't use `sampler_config`, like anisotropy on a texture without mipmaps, keep what they had.
+    pub fn set_texture_filter(&mut self, sampler_config: SamplerConfig) -> anyhow::Result<()> {
+        self.diffuse_texture
+            .set_sampler_config(&self.device, sampler_config)?;
+        self.diffuse_bind_group = texture_bind_group(
+            &self.device,
+            &self.layouts.texture,
+            &self.diffuse_texture,
+            &self.flat_normal_texture,
+            &self.white_texture,
+            &self.white_texture,
+            &self.diffuse_material_buffer,
+        );
+
+        for object in &mut self.scene.objects {
+            set_material_filters(
+                &self.device,
+                &self.layouts,
+                &mut object.model.materials,
+                sampler_config,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn texture_address_mode(&self) -> AddressMode {
+        self.texture_filter().address_mode
+    }
+
+    // Keeps the current filtering. Repeat and MirrorRepeat only show once tex_coords go past 1, see set_texture_tiling.
+    pub fn set_texture_address_mode(&mut self, address_mode: AddressMode) -> anyhow::Result<()> {
+        self.set_texture_filter(SamplerConfig {
+            address_mode,
+            ..self.texture_filter()
+        })
+    }
+
+    pub fn texture_tiling(&self) -> f32 {
+        self.texture_tiling
+    }
+
+    // Scales the pentagon's tex_coords so its texture fits `tiling` times across.
+    // Past 1 the texture address mode decides what fills the rest.
+    pub fn set_texture_tiling(&mut self, tiling: f32) {
+        self.texture_tiling = tiling;
+
+        let vertices = VERTICES
+            .iter()
+            .map(|vertex| vertex.tiled(tiling).to_model_vertex())
+            .collect::<Vec<_>>();
+        self.queue
+            .write_buffer(&self.vertex_buffer, 0, cast_slice(&vertices));
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Rebuilds everything drawn in the main pass to use `sample_count` samples per pixel, e.g. 4 for 4x MSAA.
+    // Counts the surface or depth format can't handle are lowered to the most they can. Returns the count used.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> u32 {
+        let supported = [self.render_target_format, self.depth_format]
+            .into_iter()
+            .map(|format| Texture::max_supported_sample_count(&self.adapter, &self.device, format))
+            .min()
+            .unwrap_or(1);
+        // On WebGL an unsupported count otherwise only shows up as an opaque configure error
+        if sample_count > supported {
+            log::warn!(
+                "{}x MSAA isn't supported by {:?} and {:?}, using {}x",
+                sample_count,
+                self.render_target_format,
+                self.depth_format,
+                supported
+            );
+        }
+        let sample_count = sample_count.min(supported);
+        log::info!("MSAA samples: {}", sample_count);
+
+        self.sample_count = sample_count;
+
+        self.rebuild_scene_pipelines();
+
+        self.morph
+            .set_sample_count(&self.device, &self.shader, sample_count);
+        self.wind
+            .set_sample_count(&self.device, &self.shader, sample_count);
+        self.impostors.set_sample_count(&self.device, sample_count);
+        self.outline.set_sample_count(&self.device, sample_count);
+        self.labels.set_sample_count(&self.device, sample_count);
+        self.grid.set_sample_count(&self.device, sample_count);
+        self.debug_lines
+            .set_sample_count(&self.device, sample_count);
+        self.background.set_sample_count(&self.device, sample_count);
+        if let Some(particles) = &mut self.particles {
+            particles.set_sample_count(&self.device, sample_count);
+        }
+
+        self.msaa_targets = create_msaa_targets(
+            &self.device,
+            self.internal_size(),
+            self.render_target_format,
+            self.depth_format,
+            sample_count,
+        );
+
+        self.warn_if_depth_unreadable(
+            self.ssao.enabled || self.fog.enabled || self.dof.enabled || self.depth_view.enabled,
+        );
+
+        sample_count
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // Renders the scene at `scale` times the window's resolution, clamped to 0.5..=2, and stretches it over
+    // the window. Above 1 smooths edges like supersampling, below 1 trades sharpness for speed.
+    // Returns the scale used.
+    pub fn set_render_scale(&mut self, scale: f32) -> f32 {
+        self.render_scale = scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+
+        self.resize_targets();
+
+        let size = self.internal_size();
+        log::info!(
+            "Render scale {}: {}x{}",
+            self.render_scale,
+            size.width,
+            size.height
+        );
+        self.update_title();
+
+        self.render_scale
+    }
+
+    pub fn reversed_z(&self) -> bool {
+        self.camera.reversed_z
+    }
+
+    // Puts the near plane at depth 1 and the far plane at 0, for much better depth precision far from
+    // the camera. Rebuilds everything that tests against the main pass's depth buffer to keep the nearest.
+    pub fn set_reversed_z(&mut self, reversed_z: bool) {
+        self.camera.reversed_z = reversed_z;
+        self.camera_uniform.update_view_proj(&self.camera);
+
+        self.rebuild_scene_pipelines();
+
+        let depth_compare = camera::depth_compare(reversed_z);
+        self.morph
+            .set_depth_compare(&self.device, &self.shader, depth_compare);
+        self.wind
+            .set_depth_compare(&self.device, &self.shader, depth_compare);
+        self.impostors
+            .set_depth_compare(&self.device, depth_compare);
+        self.grid.set_depth_compare(&self.device, depth_compare);
+        self.debug_lines
+            .set_depth_compare(&self.device, depth_compare);
+        if let Some(particles) = &mut self.particles {
+            particles.set_depth_compare(&self.device, depth_compare);
+        }
+        self.object_ids.set_reversed_z(&self.device, reversed_z);
+    }
+
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.camera.znear, self.camera.zfar)
+    }
+
+    // Distances from the camera to the near and far clip planes. Pulling the near plane in costs far more
+    // depth precision than pushing the far plane out, unless reversed-Z is on.
+    pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) -> anyhow::Result<()> {
+        if !(znear > 0.0 && zfar > znear) {
+            anyhow::bail!(
+                "The clip planes need 0 < near < far, got {} and {}",
+                znear,
+                zfar
+            );
+        }
+
+        self.camera.znear = znear;
+        self.camera.zfar = zfar;
+
+        Ok(())
+    }
+
+    pub fn cull_mode(&self) -> Option<Face> {
+        self.cull_mode
+    }
+
+    // Which faces the scene pipelines skip. None draws both sides, which helps spot meshes wound the wrong way.
+    pub fn set_cull_mode(&mut self, cull_mode: Option<Face>) {
+        self.cull_mode = cull_mode;
+        self.rebuild_scene_pipelines();
+
+        self.morph
+            .set_cull_mode(&self.device, &self.shader, cull_mode);
+        self.wind
+            .set_cull_mode(&self.device, &self.shader, cull_mode);
+    }
+
+    pub fn double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    // For thin geometry like leaves or flags. Both sides get drawn and lit, with back faces using the
+    // normal flipped around.
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        self.double_sided = double_sided;
+        self.rebuild_scene_pipelines();
+
+        self.morph
+            .set_double_sided(&self.device, &self.shader, double_sided);
+        self.wind
+            .set_double_sided(&self.device, &self.shader, double_sided);
+    }
+
+    pub fn depth_bias(&self) -> DepthBiasState {
+        self.depth_bias
+    }
+
+    // Shifts the depth of everything the scene, morph and wind pipelines draw, so a decal drawn flat on a
+    // surface wins the depth test instead of flickering against it (z-fighting). `constant` is in the depth
+    // buffer's smallest steps, `slope_scale` multiplies how steeply the triangle faces away from the camera.
+    // Negative values pull towards the camera, or positive ones with reversed-Z. Around -1 to -4 and -1.0
+    // to -2.0 is typical for decals; 0 and 0.0 turn it off. Impostors aren't biased, since they're flat
+    // stand-ins for far away models rather than surfaces anything gets layered on.
+    pub fn set_depth_bias(&mut self, constant: i32, slope_scale: f32) {
+        self.depth_bias = DepthBiasState {
+            constant,
+            slope_scale,
+            clamp: 0.0,
+        };
+        self.rebuild_scene_pipelines();
+
+        self.morph
+            .set_depth_bias(&self.device, &self.shader, self.depth_bias);
+        self.wind
+            .set_depth_bias(&self.device, &self.shader, self.depth_bias);
+    }
+
+    pub fn topology(&self) -> PrimitiveTopology {
+        self.topology
+    }
+
+    // How the pentagon's vertices are put together, to show what primitive assembly does with the same
+    // five vertices. Points and lines use the vertices in order rather than the index buffer, which only
+    // makes sense as triangles. Models are always drawn as triangle lists.
+    pub fn set_topology(&mut self, topology: PrimitiveTopology) {
+        self.topology = topology;
+        self.rebuild_scene_pipelines();
+    }
+
+    // The fill, transparent, wireframe and pentagon pipelines, for the current sample count, cull mode,
+    // sidedness, depth direction and depth bias.
+    fn rebuild_scene_pipelines(&mut self) {
+        let config = self.scene_pipeline_config();
+
+        self.render_pipeline = create_render_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            &config,
+        );
+        self.transparent_pipeline = create_render_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            &ScenePipelineConfig {
+                transparent: true,
+                ..config
+            },
+        );
+        if self.wireframe_pipeline.is_some() {
+            self.wireframe_pipeline = Some(create_render_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                &self.shader,
+                &ScenePipelineConfig {
+                    polygon_mode: PolygonMode::Line,
+                    ..config
+                },
+            ));
+        }
+        self.shape_pipeline = (self.topology != PrimitiveTopology::TriangleList).then(|| {
+            create_render_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                &self.shader,
+                &ScenePipelineConfig {
+                    topology: self.topology,
+                    ..config
+                },
+            )
+        });
+    }
+
+    // The opaque, filled triangle list pipeline with the current settings. The other scene pipelines differ from it
+    // in one field.
+    fn scene_pipeline_config(&self) -> ScenePipelineConfig {
+        ScenePipelineConfig {
+            format: self.render_target_format,
+            depth_format: self.depth_format,
+            polygon_mode: PolygonMode::Fill,
+            cull_mode: self.cull_mode,
+            double_sided: self.double_sided,
+            transparent: false,
+            sample_count: self.sample_count,
+            depth_compare: camera::depth_compare(self.camera.reversed_z),
+            depth_bias: self.depth_bias,
+            topology: PrimitiveTopology::TriangleList,
+        }
+    }
+
+    fn warn_if_depth_unreadable(&self, enabled: bool) {
+        if enabled && !self.depth_readable() {
+            log::warn!("Depth based post-processing is skipped while MSAA is on");
+        }
+    }
+
+    // SSAO, fog, depth of field and the depth view sample the depth buffer, which only gets written without MSAA.
+    // A multisampled depth buffer can't be read like a regular texture.
+    fn depth_readable(&self) -> bool {
+        self.msaa_targets.is_none()
+    }
+
+    pub fn set_frame_rate(&mut self, fps: f32, ms: f32) {
+        self.frame_rate = Some((fps, ms));
+        self.update_title();
+    }
+
+    pub fn render_stats(&self) -> Option<&RenderStats> {
+        self.render_stats.as_ref()
+    }
+
+    // Returns a bool based on wether an event has been fully processed or not.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.camera_controller.process_events(event)
+    }
+
+    // Plays or pauses the camera path. Playing again after it finishes starts it over.
+    pub fn toggle_camera_path(&mut self) -> anyhow::Result<()> {
+        let Some(path) = &self.camera_path else {
+            anyhow::bail!("No camera path loaded");
+        };
+
+        self.follow_path = !self.follow_path;
+
+        if self.follow_path {
+            self.auto_orbit = false;
+            if self.path_time >= path.duration() {
+                self.path_time = 0.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn following_camera_path(&self) -> bool {
+        self.follow_path
+    }
+
+    // Starts or stops circling the camera around `camera.target`, keeping its current distance and height.
+    // Any movement key stops it too.
+    pub fn toggle_auto_orbit(&mut self) {
+        self.auto_orbit = !self.auto_orbit;
+        if !self.auto_orbit {
+            return;
+        }
+
+        self.follow_path = false;
+        self.camera_transition = None;
+
+        let offset = self.camera.eye - self.camera.target;
+        self.orbit_angle = offset.z.atan2(offset.x);
+        // Straight above the target there's no circle to go around, so step back a little
+        self.orbit_radius = offset.x.hypot(offset.z).max(1.0);
+    }
+
+    pub fn auto_orbiting(&self) -> bool {
+        self.auto_orbit
+    }
+
+    pub fn camera_mode(&self) -> CameraMode {
+        self.camera_controller.mode()
+    }
+
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_controller.set_mode(mode);
+    }
+
+    pub fn key_bindings(&self) -> &KeyBindings {
+        self.camera_controller.key_bindings()
+    }
+
+    // E.g. ZQSD for AZERTY keyboards. Keys bound here stop reaching the window adapter's toggles.
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.camera_controller.set_key_bindings(key_bindings);
+    }
+
+    pub fn mouse_captured(&self) -> bool {
+        self.mouse_captured
+    }
+
+    // Hides the cursor and keeps it in the window, so mouse movement turns the camera.
+    // Locking the cursor in place isn't supported everywhere, so confining it to the window is the fallback.
+    pub fn set_mouse_captured(&mut self, captured: bool) -> anyhow::Result<()> {
+        let Some(window) = &self.window else {
+            anyhow::bail!("There's no window to capture the mouse in when headless");
+        };
+
+        if captured {
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))?;
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)?;
+        }
+
+        window.set_cursor_visible(!captured);
+        self.mouse_captured = captured;
+        self.camera_controller.set_mouse_look(captured);
+
+        Ok(())
+    }
+
+    // Called once as the app closes, before the State is dropped.
+    pub fn on_exit(&mut self) {
+        // Lets readbacks still in flight, like the profiler's, finish so dropping their buffers doesn't hang
+        self.device.poll(Maintain::Wait);
+    }
+
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        self.camera_controller.process_mouse_motion(dx, dy);
+    }
+
+    // Where the main light is. None once the lights have been cleared.
+    pub fn light_position(&self) -> Option<[f32; 3]> {
+        self.lights.as_slice().first().map(|light| light.position)
+    }
+
+    // Moves the main light. Picked up on the next frame.
+    pub fn set_light_position(&mut self, position: [f32; 3]) {
+        if let Some(light) = self.lights.as_mut_slice().first_mut() {
+            light.position = position;
+        }
+    }
+
+    // The way the sun shines, pointing away from it. Picked up on the next update.
+    pub fn set_light_direction(&mut self, direction: [f32; 3]) {
+        self.shadow.set_direction(direction.into());
+    }
+
+    pub fn light_direction(&self) -> [f32; 3] {
+        self.shadow.direction().into()
+    }
+
+    pub fn toggle_shadows(&mut self) {
+        self.shadow.enabled = !self.shadow.enabled;
+    }
+
+    pub fn num_lights(&self) -> usize {
+        self.lights.len()
+    }
+
+    // Adds a point light, up to MAX_LIGHTS of them. Picked up on the next frame.
+    pub fn add_light(&mut self, position: [f32; 3], color: [f32; 3]) -> anyhow::Result<()> {
+        if !self.lights.push(LightRaw::new(position, color)) {
+            anyhow::bail!("There are already {} lights", MAX_LIGHTS);
+        }
+
+        Ok(())
+    }
+
+    // Removes every light, leaving the scene black.
+    pub fn clear_lights(&mut self) {
+        self.lights.clear();
+    }
+
+    pub fn light_hue(&self) -> Option<f32> {
+        self.light_hue
+    }
+
+    // Colors the main light from the hue wheel, at full saturation. Picked up on the next update.
+    pub fn set_light_hue(&mut self, hue: Option<f32>) {
+        self.light_hue = hue.map(|hue| hue.rem_euclid(360.0));
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_paused(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn scene_time(&self) -> Duration {
+        self.scene_time
+    }
+
+    pub fn add_camera_shake(&mut self, intensity: f32, duration: Duration) {
+        self.camera_controller.add_shake(intensity, duration);
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        if self.auto_orbit && self.camera_controller.is_moving() {
+            // Hand the camera back to the user
+            self.auto_orbit = false;
+        }
+
+        match self.camera_path.as_ref().filter(|_| self.follow_path) {
+            Some(path) => {
+                self.camera_controller.clear_shake(&mut self.camera);
+                self.path_time += dt.as_secs_f32();
+
+                let sample = path.sample(self.path_time);
+                self.camera.eye = sample.eye;
+                self.camera.target = sample.target;
+                // Paths are made for the perspective camera. An orthographic one keeps its size.
+                if let Projection::Perspective { fovy } = &mut self.camera.projection {
+                    *fovy = sample.fovy;
+                }
+
+                if self.path_time >= path.duration() {
+                    self.follow_path = false;
+                }
+            }
+            None if self.auto_orbit => {
+                self.camera_controller.clear_shake(&mut self.camera);
+                self.orbit_angle = (self.orbit_angle + AUTO_ORBIT_SPEED * dt.as_secs_f32()) % TAU;
+
+                let height = self.camera.eye.y - self.camera.target.y;
+                self.camera.eye = self.camera.target
+                    + Vector3::new(
+                        self.orbit_radius * self.orbit_angle.cos(),
+                        height,
+                        self.orbit_radius * self.orbit_angle.sin(),
+                    );
+            }
+            None if self.camera_transition.is_some() && !self.camera_controller.is_moving() => {
+                self.camera_controller.clear_shake(&mut self.camera);
+                self.advance_camera_transition(dt);
+            }
+            None => {
+                // Moving the camera by hand cancels a transition
+                self.camera_transition = None;
+                self.camera_controller.update_camera(&mut self.camera, dt);
+            }
+        }
+
+        self.update_scene(dt);
+    }
+
+    // Everything update does besides moving the camera, for wherever it's been put.
+    fn update_scene(&mut self, dt: Duration) {
+        // Lines from the last frame are gone, anything still wanted gets pushed again
+        self.debug_lines.clear();
+
+        // These get uploaded by draw_frame
+        self.camera_uniform.update_view_proj(&self.camera);
+
+        if let Some(hue) = self.light_hue {
+            if let Some(light) = self.lights.as_mut_slice().first_mut() {
+                light.color = hsv_to_rgb(hue, 1.0, 1.0);
+            }
+        }
+
+        self.shadow.update(self.scene.aabb());
+
+        // Animations only see the time that passed while not paused, so they pick up where they stopped
+        let scene_dt = if self.paused { Duration::ZERO } else { dt };
+        self.scene_time += scene_dt;
+
+        self.queue.write_buffer(
+            &self.time_buffer,
+            0,
+            bytes_of(&TimeUniform::new(self.scene_time)),
+        );
+
+        self.morph.update(&self.queue, scene_dt);
+
+        if self.wind.enabled {
+            self.wind.update(&self.queue, scene_dt);
+        }
+
+        if let Some(particles) = &mut self.particles {
+            particles.update(&self.queue, &self.camera, scene_dt);
+        }
+
+        if self.dof.enabled {
+            self.dof.update(&self.queue, &self.camera);
+        }
+
+        if self.ssao.enabled {
+            self.ssao.update(&self.queue, &self.camera);
+        }
+
+        if self.fog.enabled {
+            self.fog.update(&self.queue, &self.camera);
+        }
+
+        if self.depth_view.enabled {
+            self.depth_view.update(&self.queue, &self.camera);
+        }
+
+        let spinning = self.rotation_speed != Deg(0.0) && !self.paused;
+        if spinning {
+            self.spin_instances(scene_dt);
+        }
+
+        // The camera moved, so the distances changed. Spinning changed every matrix.
+        if self.sort_mode != InstanceSort::None || spinning {
+            self.upload_instances();
+        }
+
+        if self.impostors.enabled()
+            && self.impostors.update(
+                &self.device,
+                &self.queue,
+                &self.camera,
+                &self.scene.primary().instances,
+            )
+        {
+            self.update_title();
+        }
+
+        if self.labels.enabled {
+            self.labels.update(
+                &self.device,
+                &self.queue,
+                &self.camera,
+                &self.scene.primary().instances,
+            );
+        }
+
+        if let Some(stats) = self
+            .gpu_timer
+            .as_mut()
+            .and_then(|timer| timer.poll(&self.device))
+        {
+            self.render_stats = Some(stats);
+            self.update_title();
+        }
+    }
+
+    // The title bar doubles as a stats overlay.
+    fn update_title(&self) {
+        let Some(window) = &self.window else {
+            return;
+        };
+
+        let mut title = self.window_title.clone();
+
+        if let Some((fps, ms)) = self.frame_rate {
+            title += &format!(" - {:.0} fps / {:.2} ms", fps, ms);
+        }
+
+        if self.impostors.enabled() {
+            let (full, impostor) = self.impostors.counts();
+            title += &format!(" - {} full / {} impostor", full, impostor);
+        }
+
+        if self.scene.primary().model.subdivision() > 0 {
+            title += &format!(
+                " - subdivision {}: {} triangles",
+                self.scene.primary().model.subdivision(),
+                self.scene.primary().model.num_triangles()
+            );
+        }
+
+        if self.render_scale != 1.0 {
+            let size = self.internal_size();
+            title += &format!(
+                " - {}x{} at {:.2}x",
+                size.width, size.height, self.render_scale
+            );
+        }
+
+        if let Some(stats) = &self.render_stats {
+            title += &format!(" - {}", stats);
+        }
+
+        window.set_title(&title);
+    }
+
+    // Draws into the offscreen target when headless, since there's nothing to present to.
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let Some(surface) = &self.surface else {
+            // Taken out for the frame so drawing can borrow `self` mutably
+            if let Some(target) = self.headless_target.take() {
+                self.draw_frame(&target.view);
+                self.headless_target = Some(target);
+            }
+            return Ok(());
+        };
+
+        let output = surface.get_current_texture()?;
+
+        let view = output
+            .texture
+            .create_view(&TextureViewDescriptor::default());
+
+        self.draw_frame(&view);
+
+        output.present();
+
+        Ok(())
+    }
+
+    // Renders the current frame offscreen at the window's size and saves it as a PNG.
+    pub fn capture_frame(&mut self, path: &Path) -> anyhow::Result<()> {
+        let target = self.create_output_target("capture_target");
+
+        self.draw_frame(&target.view);
+
+        screenshot::save_texture(
+            &self.device,
+            &self.queue,
+            &target,
+            self.config.width,
+            self.config.height,
+            self.config.format,
+            path,
+        )
+    }
+
+    // Orbits the camera a full turn around its target over `frames` frames, saving each one to
+    // `output_dir` as frame_0000.png, frame_0001.png, ...
+    // Frames are rendered offscreen, and time steps by a fixed amount between them so they're evenly spaced.
+    // The controller, camera path, auto-orbit and transitions are left alone so they can't move the camera off it.
+    pub fn capture_turntable(&mut self, frames: u32, output_dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(output_dir)?;
+
+        let target = self.create_output_target("turntable_target");
+
+        let start_eye = self.camera.eye;
+        let offset = start_eye - self.camera.target;
+        let axis = self.camera.up.normalize();
+
+        let mut result = Ok(());
+
+        for frame in 0..frames {
+            let angle = Deg(360.0 * frame as f32 / frames as f32);
+            self.camera.eye =
+                self.camera.target + Quaternion::from_axis_angle(axis, angle).rotate_vector(offset);
+            self.update_scene(TURNTABLE_FRAME_TIME);
+            self.draw_frame(&target.view);
+
+            let path = output_dir.join(format!("frame_{:04}.png", frame));
+            result = screenshot::save_texture(
+                &self.device,
+                &self.queue,
+                &target,
+                self.config.width,
+                self.config.height,
+                self.config.format,
+                &path,
+            );
+            if result.is_err() {
+                break;
+            }
+
+            log::info!("Turntable frame {}/{}", frame + 1, frames);
+        }
+
+        // Put the camera back where it started, even if a frame failed
+        self.camera.eye = start_eye;
+        self.update_scene(Duration::ZERO);
+
+        result
+    }
+
+    // Renders the scene and post-processing into `view`.
+    fn draw_frame(&mut self, view: &TextureView) {
+        // Everything pushed since the last update
+        self.debug_lines.upload(&self.device, &self.queue);
+
+        // Post-processing reads the scene back, so it can't go straight to the surface.
+        // Depth of field reads the scene texture, tone mapping reads the post texture after it,
+        // and FXAA reads its own target after that.
+        let scene_view = if self.dof.enabled && self.depth_readable() {
+            &self.scene_texture.view
+        } else if self.tone_mapping.enabled {
+            &self.post_texture.view
+        } else if self.fxaa.enabled {
+            self.fxaa.target()
+        } else if self.accumulating() {
+            self.accumulation.target()
+        } else {
+            self.frame_target(view)
+        };
+
+        // Decided once up front so both encoders agree on whether timestamps get written
+        let timer = self.gpu_timer.as_ref().filter(|timer| timer.recording());
+
+        let encode_start = Instant::now();
+
+        let upload_commands = self.encode_uniform_uploads();
+
+        // Each half gets its own encoder, so they can be recorded at the same time.
+        // Encoding only reads from `self`, which the borrow checker holds us to while the threads run.
+        let (scene_commands, post_commands) = if self.threaded_encoding {
+            thread::scope(|scope| {
+                let post = scope.spawn(|| self.encode_post_processing(scene_view, view, timer));
+                let scene = self.encode_scene(scene_view, timer);
+                (scene, post.join().unwrap())
+            })
+        } else {
+            (
+                self.encode_scene(scene_view, timer),
+                self.encode_post_processing(scene_view, view, timer),
+            )
+        };
+
+        self.encode_time = encode_start.elapsed();
+        // Stopping and starting again shouldn't bring back an old frame
+        self.accumulation.started = self.accumulating();
+
+        let mut command_buffers = vec![upload_commands, scene_commands, post_commands];
+
+        let recording = timer.is_some();
+        if recording {
+            let timed = self.timed_passes();
+            if let Some(timer) = &mut self.gpu_timer {
+                command_buffers.push(timer.resolve(&self.device, timed));
+            }
+        }
+
+        // Command buffers run in the order they're submitted, no matter which was recorded first.
+        self.queue.submit(command_buffers);
+        // The belt's buffers can be mapped again once the GPU is done copying out of them
+        self.staging_belt.lock().unwrap().recall();
+
+        if let Some(timer) = self.gpu_timer.as_mut().filter(|_| recording) {
+            timer.map();
+        }
+    }
+
+    // Whether the frame has to be copied over to the screen at the end: at a render scale other than 1, or when
+    // the render target format isn't the surface's.
+    fn needs_upscale(&self) -> bool {
+        self.render_scale != 1.0 || self.render_target_format != self.config.format
+    }
+
+    // Where the post-processing passes finish the frame. That's the upscale's target when it needs_upscale,
+    // which gets stretched over `view` at the end.
+    fn frame_target<'a>(&'a self, view: &'a TextureView) -> &'a TextureView {
+        if self.needs_upscale() {
+            self.upscale.target()
+        } else {
+            view
+        }
+    }
+
+    // Copies the camera and light uniforms into their buffers through the staging belt.
+    // Goes in the first command buffer of the frame so everything after sees the new values.
+    fn encode_uniform_uploads(&self) -> CommandBuffer {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Uniform Upload Encoder"),
+            });
+
+        let mut staging_belt = self.staging_belt.lock().unwrap();
+
+        let uploads: [(&Buffer, &[u8]); 3] = [
+            (&self.camera_buffer, bytes_of(&self.camera_uniform)),
+            (&self.light_buffer, bytes_of(&self.lights)),
+            (self.shadow.buffer(), self.shadow.uniform_bytes()),
+        ];
+        for (buffer, data) in uploads {
+            // Uniforms are never empty
+            let size = BufferSize::new(data.len() as BufferAddress).unwrap();
+            staging_belt
+                .write_buffer(&mut encoder, buffer, 0, size, &self.device)
+                .copy_from_slice(data);
+        }
+
+        // Unmaps the belt's buffers so the copies can run
+        staging_belt.finish();
+
+        encoder.finish()
+    }
+
+    // Passes that run this frame, in order.
+    fn timed_passes(&self) -> Vec<GpuPass> {
+        let mut passes = vec![GpuPass::Main];
+
+        if !self.depth_readable() {
+            return passes;
+        }
+
+        if self.ssao.enabled {
+            passes.push(GpuPass::Ssao);
+        }
+
+        if self.dof.enabled {
+            passes.push(GpuPass::DepthOfField);
+        }
+
+        passes
+    }
+
+    // The main pass: everything that draws geometry into `scene_view`.
+    fn encode_scene(&self, scene_view: &TextureView, timer: Option<&GpuTimer>) -> CommandBuffer {
+        // Creates a command encoder that sends commands to the GPU.
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Scene Encoder"),
+            });
+
+        if let Some(timer) = timer {
+            timer.begin(&mut encoder, GpuPass::Main);
+        }
+
+        // Moved before they're drawn, so the main pass sees this frame's positions
+        if let Some(particles) = &self.particles {
+            particles.simulate(&mut encoder);
+        }
+
+        // The main pass samples the shadow map, so it has to be filled first
+        self.shadow.render(&mut encoder, &self.scene);
+
+        // With MSAA the pass draws into the multisampled targets and resolves into `scene_view` at the end
+        let (color_view, resolve_target, depth_view) = match &self.msaa_targets {
+            Some(msaa) => (&msaa.color.view, Some(scene_view), &msaa.depth.view),
+            None => (scene_view, None, &self.depth_texture.view),
+        };
+
+        // Rust Tip: Releases any variables once block is done. Releases mut encoder.
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Render Pass"),
+                // Draws color to the view (TextureView)
+                // This is what @location(0) in the fragment shader targets
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: Operations {
+                        load: if self.clear_enabled
+                            || (self.accumulating() && !self.accumulation.started)
+                        {
+                            wgpu::LoadOp::Clear(self.clear_color)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(camera::depth_clear_value(self.camera.reversed_z)),
+                        store: true,
+                    }),
+                    // The outline pass masks the selected object here
+                    stencil_ops: Some(Operations {
+                        load: LoadOp::Clear(0),
+                        store: true,
+                    }),
+                }),
+            });
+
+            // Behind everything, so it goes first
+            self.background.draw(&mut render_pass);
+
+            // Morphing, wind and impostors have pipelines of their own, which always fill
+            // The wireframe draws transparent meshes as lines like everything else
+            let (pipeline, blend_transparent) = match &self.wireframe_pipeline {
+                Some(wireframe_pipeline) if self.wireframe => (wireframe_pipeline, false),
+                _ => (&self.render_pipeline, true),
+            };
+            render_pass.set_pipeline(pipeline);
+
+            // Stays bound for the morph, wind and impostor pipelines, which share the layout up to here
+            render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+
+            render_pass.set_vertex_buffer(1, self.scene.primary().instance_buffer.slice(..));
+
+            if let Some(shape_pipeline) = &self.shape_pipeline {
+                render_pass.set_pipeline(shape_pipeline);
+            }
+            if matches!(
+                self.topology,
+                PrimitiveTopology::TriangleList | PrimitiveTopology::TriangleStrip
+            ) {
+                render_pass.draw_shape_instanced(
+                    &self.vertex_buffer,
+                    &self.index_buffer,
+                    self.num_indices,
+                    0..self.scene.primary().instances.len() as u32,
+                    &self.diffuse_bind_group,
+                    &self.camera_bind_group,
+                );
+            } else {
+                render_pass.draw_shape_vertices_instanced(
+                    &self.vertex_buffer,
+                    self.num_vertices,
+                    0..self.scene.primary().instances.len() as u32,
+                    &self.diffuse_bind_group,
+                    &self.camera_bind_group,
+                );
+            }
+            if self.shape_pipeline.is_some() {
+                render_pass.set_pipeline(pipeline);
+            }
+
+            // Morph targets line up with the loaded vertices, so they can't be used on a subdivided model
+            let morphing = self.morph.is_active() && self.scene.primary().model.subdivision() == 0;
+            if morphing {
+                // Impostors show the base shape, so morphing always uses the full geometry
+                self.morph.draw(
+                    &mut render_pass,
+                    &self.scene.primary().model,
+                    0..self.scene.primary().instances.len() as u32,
+                    &self.camera_bind_group,
+                );
+            } else if self.wind.enabled {
+                self.wind.draw(
+                    &mut render_pass,
+                    &self.scene.primary().model,
+                    0..self.scene.primary().instances.len() as u32,
+                    &self.camera_bind_group,
+                );
+            } else if self.impostors.enabled() {
+                self.impostors.draw(
+                    &mut render_pass,
+                    &self.scene.primary().model,
+                    &self.camera_bind_group,
+                );
+                // The impostors swapped out the instance buffer
+                render_pass.set_vertex_buffer(1, self.scene.primary().instance_buffer.slice(..));
+            } else if blend_transparent {
+                render_pass.draw_model_opaque_instanced(
+                    &self.scene.primary().model,
+                    0..self.scene.primary().instances.len() as u32,
+                    &self.camera_bind_group,
+                );
+            } else {
+                render_pass.draw_model_instanced(
+                    &self.scene.primary().model,
+                    0..self.scene.primary().instances.len() as u32,
+                    &self.camera_bind_group,
+                );
+            }
+
+            if self.scene.objects.len() > 1 {
+                render_pass.set_pipeline(pipeline);
+                self.scene.draw_others(
+                    &mut render_pass,
+                    &self.camera_bind_group,
+                    blend_transparent,
+                );
+            }
+
+            // Before the transparent meshes so it shows through them
+            if self.grid.enabled {
+                self.grid.draw(&mut render_pass, &self.camera_bind_group);
+                // The grid's layout only has the camera, so the light has to be bound again
+                render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+            }
+
+            if !self.debug_lines.is_empty() {
+                self.debug_lines
+                    .draw(&mut render_pass, &self.camera_bind_group);
+                render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+            }
+
+            // Transparent meshes go last so there's something behind them to blend with.
+            // Morphing, wind and impostors already drew the primary object whole.
+            if blend_transparent && self.scene.has_transparency() {
+                let primary_plain = !(morphing || self.wind.enabled || self.impostors.enabled());
+
+                render_pass.set_pipeline(&self.transparent_pipeline);
+                self.scene.draw_transparent(
+                    &mut render_pass,
+                    &self.camera_bind_group,
+                    self.camera.eye,
+                    primary_plain,
+                );
+            }
+
+            // Added on top of everything, so they don't need sorting with the transparent meshes
+            if let Some(particles) = &self.particles {
+                particles.draw(&mut render_pass, &self.camera_bind_group);
+            }
+
+            if self.scene.objects.len() > 1 || self.scene.has_transparency() {
+                // The outline below draws from the primary object's instances
+                render_pass.set_vertex_buffer(1, self.scene.primary().instance_buffer.slice(..));
+            }
+
+            // Sorting may have moved the selected instance to another slot
+            let selected_slot = self
+                .selected
+                .and_then(|selected| self.instance_order.iter().position(|&i| i == selected));
+
+            if let Some(slot) = selected_slot {
+                let selected = slot as u32..slot as u32 + 1;
+
+                render_pass.set_stencil_reference(OUTLINE_STENCIL_REFERENCE);
+
+                render_pass.set_pipeline(&self.outline.mask_pipeline);
+                render_pass.draw_model_silhouette_instanced(
+                    &self.scene.primary().model,
+                    selected.clone(),
+                    &self.outline.bind_group,
+                    &self.camera_bind_group,
+                );
+
+                render_pass.set_pipeline(&self.outline.outline_pipeline);
+                render_pass.draw_model_silhouette_instanced(
+                    &self.scene.primary().model,
+                    selected,
+                    &self.outline.bind_group,
+                    &self.camera_bind_group,
+                );
+            }
+
+            if self.labels.enabled {
+                self.labels.draw(&mut render_pass, &self.camera_bind_group);
+            }
+        }
+
+        if let Some(timer) = timer {
+            timer.end(&mut encoder, GpuPass::Main);
+        }
+
+        encoder.finish()
+    }
+
+    // Fullscreen passes that read back what `encode_scene` drew.
+    fn encode_post_processing(
+        &self,
+        scene_view: &TextureView,
+        view: &TextureView,
+        timer: Option<&GpuTimer>,
+    ) -> CommandBuffer {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Post-processing Encoder"),
+            });
+
+        let depth_readable = self.depth_readable();
+
+        // The screen's own view is only drawn to by the upscale and the calibration pattern
+        let screen_view = view;
+        let view = self.frame_target(screen_view);
+
+        // Where the passes that would otherwise finish the frame draw, so FXAA can smooth the result
+        let aa_view = if self.fxaa.enabled {
+            self.fxaa.target()
+        } else {
+            view
+        };
+
+        // Only needs the depth buffer, so it can darken the scene wherever it was drawn
+        if self.ssao.enabled && depth_readable {
+            if let Some(timer) = timer {
+                timer.begin(&mut encoder, GpuPass::Ssao);
+            }
+
+            self.ssao.render(&mut encoder, scene_view);
+
+            if let Some(timer) = timer {
+                timer.end(&mut encoder, GpuPass::Ssao);
+            }
+        }
+
+        // After SSAO so the fog covers up occlusion in the distance, and before DoF so it gets blurred with the scene
+        if self.fog.enabled && depth_readable {
+            self.fog.render(&mut encoder, scene_view);
+        }
+
+        if self.dof.enabled && depth_readable {
+            if let Some(timer) = timer {
+                timer.begin(&mut encoder, GpuPass::DepthOfField);
+            }
+
+            // Tone mapping still has to run on the result
+            let dof_target = if self.tone_mapping.enabled {
+                &self.post_texture.view
+            } else {
+                aa_view
+            };
+
+            {
+                let mut dof_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: Some("DoF Pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: dof_target,
+                        resolve_target: None,
+                        ops: Operations {
+                            // Every pixel gets overwritten anyway
+                            load: LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+
+                dof_pass.set_pipeline(&self.dof.pipeline);
+                dof_pass.set_bind_group(0, &self.dof.bind_group, &[]);
+                dof_pass.draw(0..3, 0..1);
+            }
+
+            if let Some(timer) = timer {
+                timer.end(&mut encoder, GpuPass::DepthOfField);
+            }
+        }
+
+        if self.tone_mapping.enabled {
+            self.tone_mapping.render(&mut encoder, aa_view);
+        }
+
+        // After tone mapping, so edges are found by the brightness that ends up on screen
+        if self.fxaa.enabled {
+            self.fxaa.render(&mut encoder, view);
+        }
+
+        if self.accumulating() {
+            self.accumulation.render(&mut encoder, view);
+        }
+
+        // Covers up everything drawn so far
+        if self.depth_view.enabled && depth_readable {
+            self.depth_view.render(&mut encoder, view);
+        }
+
+        if self.needs_upscale() {
+            self.upscale.render(&mut encoder, screen_view);
+        }
+
+        // Last and at the screen's resolution, so nothing else touches the pattern
+        if self.calibration.enabled {
+            self.calibration.render(&mut encoder, screen_view);
+        }
+
+        // Builds command buffer for the GPU render queue.
+        encoder.finish()
+    }
+}
+
+fn set_material_filters(
+    device: &wgpu::Device,
+    layouts: &BindGroupLayouts,
+    materials: &mut [Material],
+    sampler_config: SamplerConfig,
+) {
+    for material in materials {
+        if let Err(e) = material.set_diffuse_sampler(device, layouts, sampler_config) {
+            log::warn!("Kept the filtering on {}: {}", material.name, e);
+        }
+    }
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    // https://docs.rs/wgpu/latest/wgpu/struct.Features.html
+    // Lets us use every texture format capability the adapter has, not just the WebGPU guaranteed ones.
+    // BC compression lets textures be compressed at load time (COMPRESS_TEXTURES).
+    // Timestamp queries time each render pass for the overlay.
+    // Line polygon mode is for the wireframe view.
+    // Depth32FloatStencil8 is the more precise depth format, see Texture::depth_format.
+    // Push constants carry each object's transform, see object_transform.rs.
+    let mut features = adapter.features()
+        & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::POLYGON_MODE_LINE
+            | wgpu::Features::TIMESTAMP_QUERY
+            | wgpu::Features::DEPTH32FLOAT_STENCIL8
+            | wgpu::Features::PUSH_CONSTANTS);
+
+    // The GL backend reads push constants from unaligned memory, so objects bake their transforms there instead
+    if adapter.get_info().backend == wgpu::Backend::Gl {
+        features.remove(wgpu::Features::PUSH_CONSTANTS);
+    }
+
+    // Push constants have no room by default
+    let limits = if features.contains(wgpu::Features::PUSH_CONSTANTS) {
+        Limits {
+            max_push_constant_size: object_transform::PUSH_CONSTANT_SIZE,
+            ..Limits::default()
+        }
+    } else {
+        Limits::default()
+    };
+
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features,
+                limits,
+                label: None,
+            },
+            None, // Trace path
+        )
+        .await
+        .context("Couldn't open the GPU device")
+}
+
+// The main scene pipeline, see ScenePipelineConfig for what can vary.
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &ScenePipelineConfig,
+) -> RenderPipeline {
+    let &ScenePipelineConfig {
+        format,
+        depth_format,
+        polygon_mode,
+        cull_mode,
+        double_sided,
+        transparent,
+        sample_count,
+        depth_compare,
+        depth_bias,
+        topology,
+    } = config;
+
+    let label = match (polygon_mode, transparent) {
+        _ if topology != PrimitiveTopology::TriangleList => "Shape Render Pipeline",
+        (PolygonMode::Fill, true) => "Transparent Render Pipeline",
+        (PolygonMode::Fill, false) => "Render Pipeline",
+        _ => "Wireframe Render Pipeline",
+    };
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            // references the entry point for the vertex shader
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            // references the entry point for the fragment shader
+            entry_point: if double_sided {
+                "fs_double_sided"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(if transparent {
+                    BlendState::ALPHA_BLENDING
+                } else {
+                    BlendState::REPLACE
+                }),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        // how to interpret the vertices when converting to triangles
+        primitive: PrimitiveState {
+            topology,
+            // Only the pentagon is drawn as a strip, and its indices are 16 bit
+            strip_index_format: topology.is_strip().then_some(IndexFormat::Uint16),
+            // Tells WGPU if a triangle is facing the camera or not.
+            front_face: FrontFace::Ccw,
+            cull_mode: if double_sided { None } else { cull_mode },
+            // Line draws just the edges of each triangle
+            polygon_mode,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: !transparent,
+            depth_compare, // Tells us when to discard a pixel. LESS means pixels will be drawn front to back.
+            stencil: StencilState::default(), // Controls values for stencil testing. We are not using this.
+            bias: depth_bias,
+        }),
+        multisample: MultisampleState {
+            // how many samples the pipeline will use. Has to match the pass it draws in.
+            count: sample_count,
+            // Specifies which samples are used. Here we are using all.
+            mask: !0,
+            // Anti-Aliasing related
+            alpha_to_coverage_enabled: false,
+        },
+        // Indicates how many array layers the render attachments can have. We are not rendering any to array textures (None)
+        multiview: None,
+    })
+}
+
+// None when `sample_count` is 1, since the main pass can then draw straight into the scene view.
+fn create_msaa_targets(
+    device: &wgpu::Device,
+    size: PhysicalSize<u32>,
+    format: TextureFormat,
+    depth_format: TextureFormat,
+    sample_count: u32,
+) -> Option<MsaaTargets> {
+    if sample_count == 1 {
+        return None;
+    }
+
+    let create = |format, label| {
+        Texture::create_multisampled(device, size.width, size.height, format, sample_count, label)
+    };
+
+    Some(MsaaTargets {
+        color: create(format, "msaa_texture"),
+        depth: create(depth_format, "msaa_depth_texture"),
+    })
+}
+
+// Everything but the center one gets tilted away from the middle of the scene.
+// A square of cubes centered on the origin.
+fn grid_instances(per_row: u32) -> Vec<Instance> {
+    const SPACE_BETWEEN: f32 = 3.0;
+
+    (0..per_row)
+        .flat_map(|z| {
+            (0..per_row).map(move |x| {
+                // Neighbors never share a material
+                let material_index = (x + z * 2) % MATERIAL_TINTS.len() as u32;
+
+                let x = SPACE_BETWEEN * (x as f32 - per_row as f32 / 2.0);
+                let z = SPACE_BETWEEN * (z as f32 - per_row as f32 / 2.0);
+
+                new_instance(Vector3 { x, y: 0.0, z }, material_index)
+            })
+        })
+        .collect()
+}
+
+fn new_instance(position: Vector3<f32>, material_index: u32) -> Instance {
+    let rotation = if position.is_zero() {
+        Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0))
+    } else {
+        Quaternion::from_axis_angle(position.normalize(), Deg(45.0))
+    };
+
+    Instance {
+        position,
+        rotation,
+        material_index,
+    }
+}
+
+// The size to make the surface for a window of `window_size`, or None to keep the old one.
+fn surface_size(window_size: PhysicalSize<u32>) -> Option<PhysicalSize<u32>> {
+    // Minimized windows report 0x0. Nothing gets shown, so the old surface is kept until they come back.
+    if window_size.width == 0 || window_size.height == 0 {
+        return None;
+    }
+
+    // Anything smaller gets the surface stretched over it instead of tiny, degenerate textures
+    Some(PhysicalSize::new(
+        window_size.width.max(MIN_SIZE.width),
+        window_size.height.max(MIN_SIZE.height),
+    ))
+}
+
+// wgpu has no variable rate shading feature yet, so there is nothing on the adapter to query.
+// Once one lands this is the only place that needs to check `adapter.features()`.
+fn supports_variable_rate_shading(_adapter: &wgpu::Adapter) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimized_windows_keep_the_surface() {
+        assert_eq!(surface_size(PhysicalSize::new(0, 0)), None);
+        assert_eq!(surface_size(PhysicalSize::new(0, 600)), None);
+        assert_eq!(surface_size(PhysicalSize::new(800, 0)), None);
+    }
+
+    #[test]
+    fn small_windows_clamp_to_the_minimum() {
+        assert_eq!(surface_size(PhysicalSize::new(10, 10)), Some(MIN_SIZE));
+        assert_eq!(
+            surface_size(PhysicalSize::new(10, 600)),
+            Some(PhysicalSize::new(MIN_SIZE.width, 600))
+        );
+        assert_eq!(
+            surface_size(PhysicalSize::new(800, 600)),
+            Some(PhysicalSize::new(800, 600))
+        );
+    }
 }
@@ -1,32 +1,362 @@
-use std::num::NonZeroU32;
+use std::{
+    num::{NonZeroU32, NonZeroU8},
+    path::Path,
+};
 
 use anyhow::*;
-use image::{DynamicImage, GenericImageView};
+use ddsfile::{D3DFormat, Dds, DxgiFormat};
+use image::{codecs::hdr::HdrDecoder, imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
 use wgpu::{
-    AddressMode, CompareFunction, Device, Extent3d, FilterMode, ImageCopyTexture, ImageDataLayout,
-    Origin3d, Queue, SamplerDescriptor, SurfaceConfiguration, TextureAspect, TextureDescriptor,
-    TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+    Adapter, AddressMode, CompareFunction, Device, Extent3d, Features, FilterMode,
+    ImageCopyTexture, ImageDataLayout, Origin3d, Queue, SamplerDescriptor, SurfaceConfiguration,
+    TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureFormatFeatureFlags,
+    TextureFormatFeatures, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor,
+    TextureViewDimension,
 };
 
+use crate::{compress, resources};
+
+// Samples per pixel a render pass can use, most first. wgpu rejects any other count, and WebGL can't always do 4.
+const SAMPLE_COUNTS: [u32; 2] = [4, 1];
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: TextureView,
     pub sampler: wgpu::Sampler,
+    // What `sampler` was made with
+    pub sampler_config: SamplerConfig,
+    // Width and height of the largest mip level
+    size: (u32, u32),
+    mip_level_count: u32,
+}
+
+// How a texture is filtered when it's drawn bigger (mag) or smaller (min) than it is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SamplerConfig {
+    pub mag_filter: FilterMode,
+    pub min_filter: FilterMode,
+    // Samples taken along surfaces seen at a grazing angle, so they don't blur. 1 turns it off.
+    // Has to be a power of two up to 16, and needs linear filtering and mipmaps.
+    // Adapters that can't do anisotropic filtering quietly ignore it.
+    pub anisotropy_clamp: u16,
+    // What happens to tex_coords outside 0..1 on every axis. Repeat and MirrorRepeat tile the texture.
+    // Tiling works on textures of any size, since the native backends and WebGL2 all allow non-power-of-two
+    // repeats, but mipmaps of odd sized textures round down, so the seams can shift slightly at a distance.
+    pub address_mode: AddressMode,
+}
+
+impl SamplerConfig {
+    // Smooth up close, cheap far away. What textures have always loaded with.
+    pub const DEFAULT: Self = Self {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Nearest,
+        anisotropy_clamp: 1,
+        address_mode: AddressMode::ClampToEdge,
+    };
+
+    // Blocky texels at any distance, for pixel art
+    pub const NEAREST: Self = Self {
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        anisotropy_clamp: 1,
+        address_mode: AddressMode::ClampToEdge,
+    };
+
+    pub const LINEAR: Self = Self {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        anisotropy_clamp: 1,
+        address_mode: AddressMode::ClampToEdge,
+    };
+
+    // Stays sharp on floors and walls that run away from the camera
+    pub const ANISOTROPIC: Self = Self {
+        anisotropy_clamp: 16,
+        ..Self::LINEAR
+    };
+
+    // Checks the config can be used on a texture with `mip_level_count` levels.
+    pub fn validate(&self, mip_level_count: u32) -> Result<()> {
+        let clamp = self.anisotropy_clamp;
+        if !clamp.is_power_of_two() || clamp > 16 {
+            bail!("Anisotropy clamp must be 1, 2, 4, 8 or 16, got {}", clamp);
+        }
+
+        if clamp > 1 {
+            if mip_level_count < 2 {
+                bail!("Anisotropic filtering needs a texture with mipmaps");
+            }
+            if self.mag_filter != FilterMode::Linear || self.min_filter != FilterMode::Linear {
+                bail!("Anisotropic filtering needs linear filtering");
+            }
+        }
+
+        if self.address_mode == AddressMode::ClampToBorder {
+            bail!("ClampToBorder needs a device feature that isn't requested");
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 impl Texture {
-    // Used to create the depth stage of the render pipeline. Also for creating the depth texture.
-    pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+    // Depth formats to pick from with `depth_format`, best first. Both have a stencil aspect so passes
+    // like the selection outline can mask pixels. Depth32FloatStencil8 is more precise but needs a device feature.
+    pub const DEPTH_FORMATS: [TextureFormat; 2] = [
+        TextureFormat::Depth32FloatStencil8,
+        TextureFormat::Depth24PlusStencil8,
+    ];
+
+    // Every adapter can render to and sample this one.
+    pub const FALLBACK_DEPTH_FORMAT: TextureFormat = TextureFormat::Depth24PlusStencil8;
+
+    // Depth textures are drawn into and then read by the post effects.
+    pub const DEPTH_USAGES: TextureUsages =
+        TextureUsages::RENDER_ATTACHMENT.union(TextureUsages::TEXTURE_BINDING);
+
+    // Offscreen color targets get rendered to, sampled by a later pass and copied out for screenshots.
+    pub const RENDER_TARGET_USAGES: TextureUsages = TextureUsages::RENDER_ATTACHMENT
+        .union(TextureUsages::TEXTURE_BINDING)
+        .union(TextureUsages::COPY_SRC);
+
+    // Every adapter can render to and filter this one.
+    pub const FALLBACK_RENDER_TARGET_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+    // Returns `desired` if it can be used as an offscreen render target on this device, otherwise the fallback format.
+    // e.g. Rgba32Float usually can't be filtered and Rgba16Float isn't renderable on some constrained adapters.
+    pub fn render_target_format(
+        adapter: &Adapter,
+        device: &Device,
+        desired: TextureFormat,
+    ) -> TextureFormat {
+        let features = format_features(adapter, device, desired);
+
+        if features.allowed_usages.contains(Self::RENDER_TARGET_USAGES)
+            && features
+                .flags
+                .contains(TextureFormatFeatureFlags::FILTERABLE)
+        {
+            desired
+        } else {
+            log::warn!(
+                "{:?} can't be used as a render target on this adapter, falling back to {:?}",
+                desired,
+                Self::FALLBACK_RENDER_TARGET_FORMAT
+            );
+            Self::FALLBACK_RENDER_TARGET_FORMAT
+        }
+    }
+
+    // The first of DEPTH_FORMATS this device can use for depth textures. Every depth texture and every pipeline
+    // that draws into one has to be made with the same format, so State picks it once and hands it around.
+    pub fn depth_format(adapter: &Adapter, device: &Device) -> TextureFormat {
+        Self::DEPTH_FORMATS
+            .into_iter()
+            .find(|&format| {
+                device
+                    .features()
+                    .contains(format.describe().required_features)
+                    && format_features(adapter, device, format)
+                        .allowed_usages
+                        .contains(Self::DEPTH_USAGES)
+            })
+            .unwrap_or(Self::FALLBACK_DEPTH_FORMAT)
+    }
+
+    // Whether textures of `format` can have `sample_count` samples per pixel. Color formats also need
+    // to resolve down to a single sample so the result can be shown.
+    pub fn supports_sample_count(
+        adapter: &Adapter,
+        device: &Device,
+        format: TextureFormat,
+        sample_count: u32,
+    ) -> bool {
+        if sample_count == 1 {
+            return true;
+        }
+
+        let mut required = TextureFormatFeatureFlags::MULTISAMPLE;
+        if format.describe().sample_type != TextureSampleType::Depth {
+            required |= TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE;
+        }
+
+        SAMPLE_COUNTS.contains(&sample_count)
+            && format_features(adapter, device, format)
+                .flags
+                .contains(required)
+    }
+
+    // The most samples per pixel textures of `format` can have, 1 if they can't be multisampled at all.
+    pub fn max_supported_sample_count(
+        adapter: &Adapter,
+        device: &Device,
+        format: TextureFormat,
+    ) -> u32 {
+        SAMPLE_COUNTS
+            .into_iter()
+            .find(|&count| Self::supports_sample_count(adapter, device, format, count))
+            .unwrap_or(1)
+    }
+
+    // A color or depth texture with several samples per pixel, to render into with MSAA.
+    // It can only be drawn to, so color targets get resolved into a regular texture at the end of the pass.
+    pub fn create_multisampled(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        // Never sampled, but every Texture has one
+        let sampler = device.create_sampler(&SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_config: SamplerConfig::NEAREST,
+            size: (width, height),
+            mip_level_count: 1,
+        }
+    }
+
+    // A color texture that passes can render into instead of the surface.
+    // Pick `format` with `render_target_format` first.
+    pub fn create_render_target(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: Self::RENDER_TARGET_USAGES,
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_config: SamplerConfig::LINEAR,
+            size: (width, height),
+            mip_level_count: 1,
+        }
+    }
+
+    // Six `face_size` square faces, in wgpu's +X, -X, +Y, -Y, +Z, -Z order, viewed as a cube.
+    // Each face can be rendered into through a 2D view of its layer.
+    pub fn create_cubemap(
+        device: &Device,
+        face_size: u32,
+        format: TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: Self::RENDER_TARGET_USAGES,
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        });
 
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_config: SamplerConfig::LINEAR,
+            size: (face_size, face_size),
+            mip_level_count: 1,
+        }
+    }
+
+    // `format` should come from `depth_format`.
     pub fn create_depth_texture(
         device: &Device,
         config: &SurfaceConfiguration,
+        format: TextureFormat,
         label: &str,
     ) -> Self {
         // The depth texture needs to be the same size as the screen.
+        Self::create_depth_texture_with_size(device, config.width, config.height, format, label)
+    }
+
+    // For depth textures that don't match the screen, like the ones used when rendering to a texture.
+    pub fn create_depth_texture_with_size(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        label: &str,
+    ) -> Self {
         let size = Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
@@ -36,8 +366,8 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING, // Since we are rendering to the texture we need the RENDER_ATTACHMENT
+            format,
+            usage: Self::DEPTH_USAGES, // Since we are rendering to the texture we need the RENDER_ATTACHMENT
         };
 
         let texture = device.create_texture(&desc);
@@ -62,38 +392,104 @@ impl Texture {
             texture,
             view,
             sampler,
+            sampler_config: SamplerConfig::LINEAR,
+            size: (width, height),
+            mip_level_count: 1,
         }
     }
 
-    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
+    // `compress` block compresses the texture on the CPU when the device supports BC formats.
+    // `generate_mipmaps` adds the full chain of smaller copies, so the texture doesn't shimmer far away.
+    // `is_normal_map` keeps the data linear. Normal maps hold directions, which sRGB decoding would bend.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_bytes(
+        device: &Device,
+        queue: &Queue,
+        bytes: &[u8],
+        label: &str,
+        compress: bool,
+        generate_mipmaps: bool,
+        is_normal_map: bool,
+        sampler_config: SamplerConfig,
+    ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
 
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(
+            device,
+            queue,
+            &img,
+            Some(label),
+            compress,
+            generate_mipmaps,
+            is_normal_map,
+            sampler_config,
+        )
     }
 
-    pub fn from_image(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        img: &DynamicImage,
-        label: Option<&str>,
+    // Reads and decodes an image from the res folder, the same place models are loaded from.
+    // Always uncompressed color with mipmaps. Use `from_bytes` for anything else.
+    pub async fn load(
+        device: &Device,
+        queue: &Queue,
+        path: impl AsRef<Path>,
+        label: &str,
     ) -> Result<Self> {
-        // Using as_rgba8() would cause a panic on jpegs which have no alpha channel
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let path = path.as_ref();
+
+        let bytes = resources::load_binary(path)
+            .await
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let img = image::load_from_memory(&bytes)
+            .with_context(|| format!("Couldn't decode {}", path.display()))?;
+
+        Self::from_image_default(device, queue, &img, Some(label))
+    }
+
+    // A single pixel of `color`, for when there's no image to use.
+    pub fn from_color(device: &wgpu::Device, queue: &wgpu::Queue, color: [u8; 4]) -> Result<Self> {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba(color)));
+        let label = format!("{:?} color", color);
+
+        Self::from_image(
+            device,
+            queue,
+            &img,
+            Some(&label),
+            false,
+            false,
+            false,
+            SamplerConfig::DEFAULT,
+        )
+    }
+
+    // Decodes a Radiance .hdr image, e.g. an equirectangular environment, keeping its full range.
+    // Rgba32Float can't be filtered on most adapters, so the sampler is nearest and shaders should use textureLoad.
+    pub fn from_hdr(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
+        let decoder =
+            HdrDecoder::new(bytes).with_context(|| format!("Couldn't decode {}", label))?;
+        let metadata = decoder.metadata();
+        let (width, height) = (metadata.width, metadata.height);
+
+        let pixels = decoder
+            .read_image_hdr()
+            .with_context(|| format!("Couldn't decode {}", label))?;
+        let data = pixels
+            .iter()
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 1.0])
+            .collect::<Vec<f32>>();
 
         let size = Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
-
         let texture = device.create_texture(&TextureDescriptor {
-            label,
+            label: Some(label),
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8UnormSrgb,
+            format: TextureFormat::Rgba32Float,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
         });
 
@@ -104,22 +500,22 @@ impl Texture {
                 mip_level: 0,
                 origin: Origin3d::ZERO,
             },
-            &rgba,
+            bytemuck::cast_slice(&data),
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: NonZeroU32::new(dimensions.1),
+                // 4 channels of 4 bytes each
+                bytes_per_row: NonZeroU32::new(16 * width),
+                rows_per_image: NonZeroU32::new(height),
             },
             size,
         );
 
         let view = texture.create_view(&TextureViewDescriptor::default());
-
         let sampler = device.create_sampler(&SamplerDescriptor {
             address_mode_u: AddressMode::ClampToEdge,
             address_mode_v: AddressMode::ClampToEdge,
             address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Nearest,
             min_filter: FilterMode::Nearest,
             mipmap_filter: FilterMode::Nearest,
             ..Default::default()
@@ -129,6 +525,457 @@ impl Texture {
             texture,
             view,
             sampler,
+            sampler_config: SamplerConfig::NEAREST,
+            size: (width, height),
+            mip_level_count: 1,
+        })
+    }
+
+    // Uploads BC1 or BC3 data from a DDS file as is, with whatever mipmaps the file has.
+    // The device needs `Features::TEXTURE_COMPRESSION_BC`. Color is treated as sRGB unless the file says otherwise.
+    pub fn from_dds(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
+        if !device.features().contains(Features::TEXTURE_COMPRESSION_BC) {
+            bail!(
+                "Can't load {}: the adapter doesn't support BC texture compression",
+                label
+            );
+        }
+
+        let dds = Dds::read(bytes).with_context(|| format!("Couldn't read {} as DDS", label))?;
+
+        let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(DxgiFormat::BC1_UNorm_sRGB | DxgiFormat::BC1_Typeless), _)
+            | (None, Some(D3DFormat::DXT1)) => TextureFormat::Bc1RgbaUnormSrgb,
+            (Some(DxgiFormat::BC1_UNorm), _) => TextureFormat::Bc1RgbaUnorm,
+            (Some(DxgiFormat::BC3_UNorm_sRGB | DxgiFormat::BC3_Typeless), _)
+            | (None, Some(D3DFormat::DXT5)) => TextureFormat::Bc3RgbaUnormSrgb,
+            (Some(DxgiFormat::BC3_UNorm), _) => TextureFormat::Bc3RgbaUnorm,
+            (dxgi, d3d) => bail!(
+                "{} isn't BC1 or BC3 (DXGI format {:?}, D3D format {:?})",
+                label,
+                dxgi,
+                d3d
+            ),
+        };
+        let bytes_per_block = match format {
+            TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => 8,
+            _ => 16,
+        };
+
+        let width = dds.get_width();
+        let height = dds.get_height();
+        if width % compress::BLOCK_SIZE != 0 || height % compress::BLOCK_SIZE != 0 {
+            bail!(
+                "{} is {}x{}, but BC textures need a width and height that are multiples of {}",
+                label,
+                width,
+                height,
+                compress::BLOCK_SIZE
+            );
+        }
+        let mip_level_count = dds.get_num_mipmap_levels().max(1);
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        // Every level of the first layer, largest first
+        let data = dds
+            .get_data(0)
+            .with_context(|| format!("{} has no image data", label))?;
+        let mut offset = 0;
+
+        for level in 0..mip_level_count {
+            let level_width = (width >> level).max(1);
+            let level_height = (height >> level).max(1);
+            // Levels smaller than a block still take up a whole one
+            let blocks_wide = level_width.div_ceil(compress::BLOCK_SIZE);
+            let blocks_high = level_height.div_ceil(compress::BLOCK_SIZE);
+            let bytes_per_row = blocks_wide * bytes_per_block;
+            let size = (bytes_per_row * blocks_high) as usize;
+
+            let level_data = data
+                .get(offset..offset + size)
+                .with_context(|| format!("{} ends before the end of mip level {}", label, level))?;
+            offset += size;
+
+            queue.write_texture(
+                ImageCopyTexture {
+                    aspect: TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level,
+                    origin: Origin3d::ZERO,
+                },
+                level_data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(bytes_per_row),
+                    rows_per_image: NonZeroU32::new(blocks_high),
+                },
+                // The copy covers whole blocks, even past the edge of the level
+                Extent3d {
+                    width: blocks_wide * compress::BLOCK_SIZE,
+                    height: blocks_high * compress::BLOCK_SIZE,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler_config = SamplerConfig::DEFAULT;
+        let sampler = Self::create_sampler(device, sampler_config, mip_level_count);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            sampler_config,
+            size: (width, height),
+            mip_level_count,
         })
     }
+
+    // Uncompressed color with mipmaps and the default filtering.
+    pub fn from_image_default(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image(
+            device,
+            queue,
+            img,
+            label,
+            false,
+            true,
+            false,
+            SamplerConfig::DEFAULT,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &DynamicImage,
+        label: Option<&str>,
+        compress: bool,
+        generate_mipmaps: bool,
+        is_normal_map: bool,
+        sampler_config: SamplerConfig,
+    ) -> Result<Self> {
+        Self::from_layers(
+            device,
+            queue,
+            std::slice::from_ref(img),
+            label,
+            compress,
+            generate_mipmaps,
+            is_normal_map,
+            sampler_config,
+            TextureViewDimension::D2,
+        )
+    }
+
+    // A texture array with one layer per image. They all need to be the same size.
+    // `view_dimension` is D2Array for shaders that pick the layer, or D2 to only see the first one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[DynamicImage],
+        label: Option<&str>,
+        compress: bool,
+        generate_mipmaps: bool,
+        is_normal_map: bool,
+        sampler_config: SamplerConfig,
+        view_dimension: TextureViewDimension,
+    ) -> Result<Self> {
+        let Some(first) = layers.first() else {
+            bail!("{} has no layers", label.unwrap_or("texture"));
+        };
+        let dimensions = first.dimensions();
+
+        if let Some((index, layer)) = layers
+            .iter()
+            .enumerate()
+            .find(|(_, layer)| layer.dimensions() != dimensions)
+        {
+            bail!(
+                "Layer {} of {} is {}x{}, but layer 0 is {}x{}. Every layer has to be the same size",
+                index,
+                label.unwrap_or("texture"),
+                layer.width(),
+                layer.height(),
+                dimensions.0,
+                dimensions.1
+            );
+        }
+
+        let mip_level_count = if generate_mipmaps {
+            Self::full_mip_level_count(dimensions)
+        } else {
+            1
+        };
+        sampler_config
+            .validate(mip_level_count)
+            .with_context(|| format!("Can't sample {}", label.unwrap_or("texture")))?;
+
+        // Using as_rgba8() would cause a panic on jpegs which have no alpha channel
+        let rgba = layers
+            .iter()
+            .map(|layer| layer.to_rgba8())
+            .collect::<Vec<_>>();
+
+        // mips[level][layer]. Each level is downsampled from the one before it.
+        let mut mips = vec![rgba];
+        for level in 1..mip_level_count {
+            let width = (dimensions.0 >> level).max(1);
+            let height = (dimensions.1 >> level).max(1);
+
+            let smaller = mips[level as usize - 1]
+                .iter()
+                .map(|layer| imageops::resize(layer, width, height, imageops::FilterType::Triangle))
+                .collect();
+            mips.push(smaller);
+        }
+
+        let size = Extent3d {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth_or_array_layers: layers.len() as u32,
+        };
+
+        let compress = compress && Self::can_compress(device, dimensions, label);
+        let opaque = mips[0]
+            .iter()
+            .all(|layer| layer.pixels().all(|p| p[3] == 255));
+
+        // BC1 alpha is all or nothing, BC3 keeps smooth edges
+        let format = match (compress, opaque, is_normal_map) {
+            (false, _, false) => TextureFormat::Rgba8UnormSrgb,
+            (false, _, true) => TextureFormat::Rgba8Unorm,
+            (true, true, false) => TextureFormat::Bc1RgbaUnormSrgb,
+            (true, true, true) => TextureFormat::Bc1RgbaUnorm,
+            (true, false, false) => TextureFormat::Bc3RgbaUnormSrgb,
+            (true, false, true) => TextureFormat::Bc3RgbaUnorm,
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        let mut uncompressed_bytes = 0;
+        let mut compressed_bytes = 0;
+
+        for (level, mip) in mips.iter().enumerate() {
+            let (width, height) = mip[0].dimensions();
+
+            let (data, extent, bytes_per_row, rows_per_image) = if compress {
+                // Levels smaller than a block still take up whole blocks
+                let width = width.next_multiple_of(compress::BLOCK_SIZE);
+                let height = height.next_multiple_of(compress::BLOCK_SIZE);
+
+                // Layers go one after the other
+                let data = mip
+                    .iter()
+                    .flat_map(|layer| {
+                        let layer = pad_image(layer, width, height);
+                        if opaque {
+                            compress::compress_bc1(&layer, width, height)
+                        } else {
+                            compress::compress_bc3(&layer, width, height)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                uncompressed_bytes += mip.iter().map(|layer| layer.len()).sum::<usize>();
+                compressed_bytes += data.len();
+
+                // Compressed textures are copied a row of blocks at a time
+                let bytes_per_block = if opaque { 8 } else { 16 };
+                let blocks_wide = width / compress::BLOCK_SIZE;
+                let blocks_high = height / compress::BLOCK_SIZE;
+                (
+                    data,
+                    (width, height),
+                    bytes_per_block * blocks_wide,
+                    blocks_high,
+                )
+            } else {
+                (
+                    mip.iter()
+                        .flat_map(|layer| layer.as_raw().iter().copied())
+                        .collect(),
+                    (width, height),
+                    4 * width,
+                    height,
+                )
+            };
+
+            queue.write_texture(
+                ImageCopyTexture {
+                    aspect: TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: Origin3d::ZERO,
+                },
+                &data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(bytes_per_row),
+                    rows_per_image: NonZeroU32::new(rows_per_image),
+                },
+                Extent3d {
+                    width: extent.0,
+                    height: extent.1,
+                    depth_or_array_layers: size.depth_or_array_layers,
+                },
+            );
+        }
+
+        if compress {
+            log::info!(
+                "Compressed {} to {:?}: {} KiB -> {} KiB",
+                label.unwrap_or("texture"),
+                format,
+                uncompressed_bytes / 1024,
+                compressed_bytes / 1024
+            );
+        }
+
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(view_dimension),
+            ..Default::default()
+        });
+
+        let sampler = Self::create_sampler(device, sampler_config, mip_level_count);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            sampler_config,
+            size: dimensions,
+            mip_level_count,
+        })
+    }
+
+    // Swaps the sampler for one with different filtering. Bind groups holding the old one need rebuilding.
+    // Leaves the texture alone if `validate` fails.
+    pub fn set_sampler_config(
+        &mut self,
+        device: &Device,
+        sampler_config: SamplerConfig,
+    ) -> Result<()> {
+        sampler_config.validate(self.mip_level_count)?;
+
+        self.sampler = Self::create_sampler(device, sampler_config, self.mip_level_count);
+        self.sampler_config = sampler_config;
+
+        Ok(())
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn create_sampler(
+        device: &Device,
+        sampler_config: SamplerConfig,
+        mip_level_count: u32,
+    ) -> wgpu::Sampler {
+        device.create_sampler(&SamplerDescriptor {
+            address_mode_u: sampler_config.address_mode,
+            address_mode_v: sampler_config.address_mode,
+            address_mode_w: sampler_config.address_mode,
+            mag_filter: sampler_config.mag_filter,
+            min_filter: sampler_config.min_filter,
+            // Blends between mip levels instead of snapping from one to the next
+            mipmap_filter: if mip_level_count > 1 {
+                FilterMode::Linear
+            } else {
+                FilterMode::Nearest
+            },
+            anisotropy_clamp: NonZeroU8::new(sampler_config.anisotropy_clamp as u8)
+                .filter(|clamp| clamp.get() > 1),
+            ..Default::default()
+        })
+    }
+
+    // Halving the size until it's 1x1, e.g. 256x128 has 9 levels
+    fn full_mip_level_count(dimensions: (u32, u32)) -> u32 {
+        u32::BITS - dimensions.0.max(dimensions.1).leading_zeros()
+    }
+
+    // Compression needs the BC feature, and BC textures have to be whole blocks.
+    fn can_compress(device: &Device, dimensions: (u32, u32), label: Option<&str>) -> bool {
+        if !device.features().contains(Features::TEXTURE_COMPRESSION_BC) {
+            log::warn!("BC compression is unsupported on this device, loading uncompressed");
+            return false;
+        }
+
+        if !dimensions.0.is_multiple_of(compress::BLOCK_SIZE)
+            || !dimensions.1.is_multiple_of(compress::BLOCK_SIZE)
+        {
+            log::warn!(
+                "{} is {}x{}, which isn't a multiple of {}. Loading uncompressed",
+                label.unwrap_or("texture"),
+                dimensions.0,
+                dimensions.1,
+                compress::BLOCK_SIZE
+            );
+            return false;
+        }
+
+        true
+    }
+}
+
+// Copies `image` into the top left of a `width` x `height` image. The rest is never sampled.
+fn pad_image(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if image.dimensions() == (width, height) {
+        return image.clone();
+    }
+
+    let mut padded = RgbaImage::new(width, height);
+    imageops::replace(&mut padded, image, 0, 0);
+    padded
+}
+
+// Without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES the device only allows what the WebGPU spec guarantees,
+// no matter what the adapter can do.
+fn format_features(
+    adapter: &Adapter,
+    device: &Device,
+    format: TextureFormat,
+) -> TextureFormatFeatures {
+    if device
+        .features()
+        .contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+    {
+        adapter.get_texture_format_features(format)
+    } else {
+        format.describe().guaranteed_format_features
+    }
 }
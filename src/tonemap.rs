@@ -0,0 +1,143 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, Buffer, BufferUsages, CommandEncoder, Device, LoadOp, Operations, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{post, texture::Texture};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ToneMapUniform {
+    exposure: f32,
+    gamma: f32,
+    srgb_surface: u32,
+}
+
+// Last pass before presenting. Scales the scene by the exposure, squeezes it back into 0 to 1 and gamma corrects it.
+pub struct ToneMapping {
+    pub enabled: bool,
+    uniform: ToneMapUniform,
+    buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl ToneMapping {
+    // `source` is the offscreen texture the scene gets drawn into while tone mapping is on.
+    pub fn new(device: &Device, format: TextureFormat, source: &Texture) -> Self {
+        let uniform = ToneMapUniform {
+            exposure: 1.0,
+            gamma: 2.2,
+            srgb_surface: format.describe().srgb as u32,
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Tone Map Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                post::texture_entry(0),
+                post::sampler_entry(1),
+                post::uniform_entry(2),
+            ],
+            label: Some("tone_map_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, source, &buffer);
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "Tone Map Pipeline",
+            include_str!("tonemap.wgsl"),
+            &[&bind_group_layout],
+            format,
+            None,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // The source texture gets recreated when the window resizes.
+    pub fn resize(&mut self, device: &Device, source: &Texture) {
+        self.bind_group = create_bind_group(device, &self.bind_group_layout, source, &self.buffer);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.uniform.exposure
+    }
+
+    pub fn set_exposure(&mut self, queue: &Queue, exposure: f32) {
+        self.uniform.exposure = exposure.max(0.0);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.uniform.gamma
+    }
+
+    pub fn set_gamma(&mut self, queue: &Queue, gamma: f32) {
+        self.uniform.gamma = gamma.clamp(1.0, 3.0);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    // Writes the tone mapped source into `target`, covering everything in it.
+    pub fn render(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Tone Map Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    // Every pixel gets overwritten anyway
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    source: &Texture,
+    buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&source.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&source.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("tone_map_bind_group"),
+    })
+}
@@ -0,0 +1,33 @@
+struct ToneMapUniform {
+    // Multiplies the scene's brightness before it gets compressed into 0 to 1
+    exposure: f32,
+    gamma: f32,
+    // Non-zero when the surface converts linear output to sRGB by itself
+    srgb_surface: u32,
+};
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+@group(0) @binding(2)
+var<uniform> tone_map: ToneMapUniform;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(t_scene, s_scene, uv).rgb;
+
+    // Exponential curve: bright parts roll off towards white instead of clipping
+    let mapped = vec3<f32>(1.0) - exp(-color * tone_map.exposure);
+
+    // Same encoding as the calibration pattern. An sRGB surface already encodes, so 2.2 leaves it alone.
+    var encoded: vec3<f32>;
+    if (tone_map.srgb_surface != 0u) {
+        encoded = pow(mapped, vec3<f32>(2.2 / tone_map.gamma));
+    } else {
+        encoded = pow(mapped, vec3<f32>(1.0 / tone_map.gamma));
+    }
+
+    return vec4<f32>(encoded, 1.0);
+}
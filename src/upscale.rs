@@ -0,0 +1,108 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindingResource, CommandEncoder, Device, LoadOp, Operations, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{post, texture::Texture};
+
+// Lets the scene be rendered at a different resolution than the screen's.
+// Above 1x it's supersampling, which smooths edges and fine detail. Below it's cheaper on weak GPUs.
+// Everything draws into `target` at the scaled size, and it gets stretched over the screen at the end.
+// It also converts from the render target format to the screen's when they differ.
+pub struct Upscale {
+    format: TextureFormat,
+    texture: Texture,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl Upscale {
+    // `format` is the target's and `output_format` what `render` draws into.
+    // `width` and `height` are the scaled size, not the screen's.
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        output_format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture =
+            Texture::create_render_target(device, width, height, format, "upscale_texture");
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[post::texture_entry(0), post::sampler_entry(1)],
+            label: Some("upscale_bind_group_layout"),
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, &texture);
+
+        let pipeline = post::create_fullscreen_pipeline(
+            device,
+            "Upscale Pipeline",
+            include_str!("upscale.wgsl"),
+            &[&bind_group_layout],
+            output_format,
+            None,
+        );
+
+        Self {
+            format,
+            texture,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.texture =
+            Texture::create_render_target(device, width, height, self.format, "upscale_texture");
+        self.bind_group = create_bind_group(device, &self.bind_group_layout, &self.texture);
+    }
+
+    // Where the frame needs to be drawn for `render` to scale it.
+    pub fn target(&self) -> &TextureView {
+        &self.texture.view
+    }
+
+    // Stretches the target over `view`, covering everything in it.
+    pub fn render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Upscale Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    // Every pixel gets overwritten anyway
+                    load: LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(device: &Device, layout: &BindGroupLayout, texture: &Texture) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&texture.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                // Linear, so scaling blends neighbouring texels instead of repeating or dropping them
+                resource: BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("upscale_bind_group"),
+    })
+}
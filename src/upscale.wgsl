@@ -0,0 +1,12 @@
+// Stretches the frame rendered at the render scale over the screen. The sampler filters linearly,
+// so going up blends between texels and going down by 2 averages each 2x2 block.
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    return textureSample(t_scene, s_scene, uv);
+}
@@ -1,7 +1,6 @@
-
-
 use bytemuck::{Pod, Zeroable};
-use wgpu::{VertexAttribute, VertexBufferLayout};
+
+use crate::model::ModelVertex;
 
 // POD = Plain Old Data
 // Zeroable = Allows us to use the zeroed() method
@@ -16,37 +15,25 @@ pub struct Vertex {
 }
 
 impl Vertex {
-    const ATTRIBS: [VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
-
-    pub fn desc<'a>() -> VertexBufferLayout<'a> {
-        // VertexBufferLayout {
-        //     // defines how wide the vertex is in bytes
-        //     array_stride: size_of::<Vertex>() as BufferAddress,
-        //     // Tells the pipeline wether each element of the array in this buffer represents per-vertex data or per-instance data
-        //     step_mode: VertexStepMode::Vertex,
-        //     // Describe the individual parts of the vertex. Generally 1:1 mapping with the fields of the struct.
-        //     attributes: &[
-        //         // position of the vertex struct
-        //         VertexAttribute {
-        //             offset: 0,
-        //             shader_location: 0,
-        //             format: wgpu::VertexFormat::Float32x3,
-        //         },
-        //         // color of the vertex struct
-        //         VertexAttribute {
-        //             offset: size_of::<[f32; 3]>() as BufferAddress,
-        //             shader_location: 1,
-        //             // Specifies the shape of the attribute
-        //             format: wgpu::VertexFormat::Float32x3,
-        //         },
-        //     ],
-
-        // Quicker way to do the above
+    // A copy with its tex_coords scaled by `tiling`, so a repeating texture shows up that many times across.
+    pub fn tiled(&self, tiling: f32) -> Self {
+        Self {
+            position: self.position,
+            tex_coords: self.tex_coords.map(|coord| coord * tiling),
+        }
+    }
 
-        VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::ATTRIBS,
+    // The pentagon is drawn with the model pipeline, so its buffer needs the whole model vertex layout.
+    // It lies flat facing +Z, with v running down the texture.
+    pub fn to_model_vertex(self) -> ModelVertex {
+        ModelVertex {
+            position: self.position,
+            tex_coords: self.tex_coords,
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0],
+            bitangent: [0.0, -1.0, 0.0],
+            color: [1.0; 3],
+            tex_coords1: self.tex_coords,
         }
     }
 }
@@ -0,0 +1,280 @@
+use std::{ops::Range, time::Duration};
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{Deg, Rad};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferBindingType, BufferUsages,
+    ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device,
+    Face, FragmentState, FrontFace, MultisampleState, PipelineLayout, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderStages, StencilState, VertexState,
+};
+
+use crate::{
+    instance::InstanceRaw,
+    layouts::BindGroupLayouts,
+    model::{Model, ModelVertex, Vertex},
+    resources::DrawModel,
+    scene_pipeline::ScenePipelineConfig,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct WindUniform {
+    direction: [f32; 2],
+    strength: f32,
+    frequency: f32,
+    time: f32,
+    root_height: f32,
+    sway_height: f32,
+    // Uniforms need to be 16 byte aligned
+    _padding: f32,
+}
+
+// Sways vertices in the wind, like grass or leaves. The bottom of the model stays put and the top moves the most.
+pub struct Wind {
+    pub enabled: bool,
+    // Direction the wind blows towards, measured around the y axis from +x
+    angle: Deg<f32>,
+    uniform: WindUniform,
+    buffer: Buffer,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    // Kept to rebuild the pipeline when the sample count, depth test, culling, sidedness or depth bias changes
+    layout: PipelineLayout,
+    pipeline_config: ScenePipelineConfig,
+}
+
+impl Wind {
+    // `root_height` and `sway_height` are in the model's local units. Below `root_height` nothing moves.
+    pub fn new(
+        device: &Device,
+        shader: &ShaderModule,
+        pipeline_config: ScenePipelineConfig,
+        layouts: &BindGroupLayouts,
+        root_height: f32,
+        sway_height: f32,
+    ) -> Self {
+        let angle = Deg(0.0);
+
+        let uniform = WindUniform {
+            direction: direction(angle),
+            strength: 0.3,
+            frequency: 1.5,
+            time: 0.0,
+            root_height,
+            sway_height,
+            _padding: 0.0,
+        };
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Wind Uniform Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("wind_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("wind_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Wind Pipeline Layout"),
+            bind_group_layouts: &[
+                &layouts.texture,
+                &layouts.camera,
+                &layouts.light,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &layout, shader, &pipeline_config);
+
+        Self {
+            enabled: false,
+            angle,
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+            layout,
+            pipeline_config,
+        }
+    }
+
+    // Rebuilds the pipeline to draw into a pass with `sample_count` samples per pixel.
+    // `shader` is the main shader the pipeline was created with.
+    pub fn set_sample_count(&mut self, device: &Device, shader: &ShaderModule, sample_count: u32) {
+        self.pipeline_config.sample_count = sample_count;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to test depth with `depth_compare`, e.g. Greater for reversed-Z.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &Device,
+        shader: &ShaderModule,
+        depth_compare: CompareFunction,
+    ) {
+        self.pipeline_config.depth_compare = depth_compare;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to cull `cull_mode`, e.g. None to see inside a mesh.
+    pub fn set_cull_mode(
+        &mut self,
+        device: &Device,
+        shader: &ShaderModule,
+        cull_mode: Option<Face>,
+    ) {
+        self.pipeline_config.cull_mode = cull_mode;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to draw and light both sides, like the main pipeline's double sided mode.
+    pub fn set_double_sided(&mut self, device: &Device, shader: &ShaderModule, double_sided: bool) {
+        self.pipeline_config.double_sided = double_sided;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    // Rebuilds the pipeline to shift depth by `depth_bias`, see State::set_depth_bias.
+    pub fn set_depth_bias(
+        &mut self,
+        device: &Device,
+        shader: &ShaderModule,
+        depth_bias: DepthBiasState,
+    ) {
+        self.pipeline_config.depth_bias = depth_bias;
+        self.rebuild_pipeline(device, shader);
+    }
+
+    fn rebuild_pipeline(&mut self, device: &Device, shader: &ShaderModule) {
+        self.pipeline = create_pipeline(device, &self.layout, shader, &self.pipeline_config);
+    }
+
+    pub fn angle(&self) -> Deg<f32> {
+        self.angle
+    }
+
+    pub fn set_angle(&mut self, angle: Deg<f32>) {
+        self.angle = Deg(angle.0.rem_euclid(360.0));
+        self.uniform.direction = direction(self.angle);
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.uniform.strength
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.uniform.strength = strength.max(0.0);
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.uniform.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.uniform.frequency = frequency.max(0.0);
+    }
+
+    pub fn update(&mut self, queue: &Queue, dt: Duration) {
+        self.uniform.time += dt.as_secs_f32();
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        render_pass.draw_model_instanced(model, instances, camera_bind_group);
+    }
+}
+
+fn direction(angle: Deg<f32>) -> [f32; 2] {
+    let angle = Rad::from(angle);
+    [angle.0.cos(), angle.0.sin()]
+}
+
+// Same as the main render pipeline, apart from the vertex entry point.
+// Always an opaque, filled triangle list, whatever `config` says about those.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &ScenePipelineConfig,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Wind Pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_wind",
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: if config.double_sided {
+                "fs_double_sided"
+            } else {
+                "fs_main"
+            },
+            targets: &[Some(ColorTargetState {
+                format: config.format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: if config.double_sided {
+                None
+            } else {
+                config.cull_mode
+            },
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: config.depth_format,
+            depth_write_enabled: true,
+            depth_compare: config.depth_compare,
+            stencil: StencilState::default(),
+            bias: config.depth_bias,
+        }),
+        multisample: MultisampleState {
+            count: config.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -1,74 +1,799 @@
-use wgpu::SurfaceError;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use cgmath::Deg;
+use env_logger::Env;
+use wgpu::{AddressMode, Color, Face, PresentMode, PrimitiveTopology, SurfaceError, TextureFormat};
 use winit::{
-    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    dpi::LogicalSize,
+    event::{
+        DeviceEvent, ElementState, Event, KeyboardInput, ModifiersState, MouseButton,
+        VirtualKeyCode, WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Icon, Window, WindowBuilder},
+};
+
+use crate::{
+    camera::CameraMode,
+    color::hsv_to_rgb,
+    instance::InstanceSort,
+    profiler::FrameTimer,
+    state::{ShadingRate, State, DEFAULT_RENDER_TARGET_FORMAT, MIN_SIZE},
+    texture::SamplerConfig,
 };
 
-use crate::state::State;
+// F7 cycles through these: haze, dusk and swamp
+const FOG_COLORS: [[f32; 3]; 3] = [[0.6, 0.65, 0.7], [0.8, 0.45, 0.3], [0.35, 0.4, 0.25]];
+// F11 cycles the exposure through these
+const EXPOSURES: [f32; 4] = [1.0, 2.0, 4.0, 0.5];
+// F8 cycles the background through these: the default blue, black and white
+const CLEAR_COLORS: [Color; 3] = [
+    Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+    },
+    Color::BLACK,
+    Color::WHITE,
+];
+// Home moves the light this far around the hue wheel
+const LIGHT_HUE_STEP: f32 = 30.0;
+// Slash adds lights on a circle this far out from the middle of the grid, this high up
+const EXTRA_LIGHT_RADIUS: f32 = 15.0;
+const EXTRA_LIGHT_HEIGHT: f32 = 6.0;
+// Backslash cycles texture filtering through these
+const TEXTURE_FILTERS: [SamplerConfig; 4] = [
+    SamplerConfig::DEFAULT,
+    SamplerConfig::NEAREST,
+    SamplerConfig::LINEAR,
+    SamplerConfig::ANISOTROPIC,
+];
+
+const ADDRESS_MODES: [AddressMode; 3] = [
+    AddressMode::ClampToEdge,
+    AddressMode::Repeat,
+    AddressMode::MirrorRepeat,
+];
+
+// Particles each press of Enter launches
+const PARTICLE_BURST: u32 = 500;
+
+// Numpad 0 steps through these
+const CULL_MODES: [Option<Face>; 3] = [None, Some(Face::Front), Some(Face::Back)];
+
+// Numpad Enter steps the pentagon through these
+const TOPOLOGIES: [PrimitiveTopology; 5] = [
+    PrimitiveTopology::TriangleList,
+    PrimitiveTopology::TriangleStrip,
+    PrimitiveTopology::LineList,
+    PrimitiveTopology::LineStrip,
+    PrimitiveTopology::PointList,
+];
+
+// Numpad 7 saves the camera here and numpad 9 loads it back
+const CAMERA_FILE: &str = "camera.json";
+
+// How many times the pentagon's texture fits across it
+const TEXTURE_TILINGS: [f32; 3] = [1.0, 2.0, 4.0];
+
+// Scroll Lock cycles the render scale through these: native, supersampled and half resolution
+const RENDER_SCALES: [f32; 3] = [1.0, 2.0, 0.5];
 
-pub async fn run() {
-    // WGPU will fail silently if not enabled
-    env_logger::init();
+// Used when RUST_LOG isn't set. wgpu's info messages are too chatty to show by default.
+const DEFAULT_LOG_FILTER: &str = "warn,wgpu_learning=info";
+
+// How the window looks when it opens.
+pub struct WindowConfig {
+    // Stats get added after it while running
+    pub title: String,
+    // Logical pixels, so it's the same size on high DPI screens
+    pub width: u32,
+    pub height: u32,
+    // An encoded PNG. None keeps the platform's default icon.
+    pub icon_bytes: Option<&'static [u8]>,
+    // What the scene and post-processing draw in before the frame is copied to the window,
+    // e.g. Rgba8UnormSrgb, Rgba16Float or Rgba32Float
+    pub render_target_format: TextureFormat,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "wgpu-learning".to_string(),
+            width: 800,
+            height: 600,
+            icon_bytes: Some(include_bytes!("../res/icon.png")),
+            render_target_format: DEFAULT_RENDER_TARGET_FORMAT,
+        }
+    }
+}
+
+// Decodes a PNG into a window icon.
+fn load_icon(bytes: &[u8]) -> anyhow::Result<Icon> {
+    let image = image::load_from_memory(bytes)?.to_rgba8();
+    let (width, height) = image.dimensions();
+
+    Ok(Icon::from_rgba(image.into_raw(), width, height)?)
+}
+
+pub async fn run(config: WindowConfig) {
+    // WGPU will fail silently if not enabled. Without RUST_LOG set, warnings from wgpu (like validation
+    // errors) and this app's own info messages are shown.
+    env_logger::Builder::from_env(Env::default().default_filter_or(DEFAULT_LOG_FILTER)).init();
 
     // Window Setup
     let event_loop = EventLoop::new();
 
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    // A broken icon isn't worth not starting over
+    let icon = config.icon_bytes.and_then(|bytes| match load_icon(bytes) {
+        Ok(icon) => Some(icon),
+        Err(e) => {
+            log::warn!("Couldn't load the window icon: {}", e);
+            None
+        }
+    });
 
-    let mut state = State::new(window).await;
+    let window = WindowBuilder::new()
+        .with_title(&config.title)
+        .with_inner_size(LogicalSize::new(config.width, config.height))
+        // Otherwise the surface gets clamped and stops matching the window, which throws off the cursor
+        .with_min_inner_size(MIN_SIZE)
+        .with_window_icon(icon)
+        .build(&event_loop)
+        .unwrap();
+
+    let mut state = match State::new(window, config.render_target_format).await {
+        Ok(state) => state,
+        Err(e) => {
+            // {:#} prints the whole chain of context, e.g. what failed and why
+            log::error!("Couldn't start: {:#}", e);
+            return;
+        }
+    };
+    state.set_window_title(&config.title);
+
+    let mut last_render_time = Instant::now();
+    let mut frame_timer = FrameTimer::new();
+    // Shift and Ctrl move the camera, so only Alt changes what a click does
+    let mut modifiers = ModifiersState::empty();
 
     // Event Loop
 
     event_loop.run(move |event, _, control_flow| match event {
+        // Prioritize the state over the event loop
         Event::WindowEvent {
             window_id,
             ref event,
-        } if window_id == state.window().id() => {
-            // Prioritize the state over the event loop
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        // new_inner_size is &&mut so we have to dereference it twice
-                        state.resize(**new_inner_size);
+        } if Some(window_id) == state.window().map(Window::id) && !state.input(event) => {
+            match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+
+                // Scene toggles. Camera movement keys are handled by the CameraController.
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(keycode),
+                            ..
+                        },
+                    ..
+                } => match keycode {
+                    // Lets go of the mouse if it's grabbed, otherwise quits
+                    VirtualKeyCode::Escape if state.mouse_captured() => {
+                        if let Err(e) = state.set_mouse_captured(false) {
+                            log::warn!("Couldn't let go of the mouse: {}", e);
+                        }
+                        log::info!("Mouse captured: {}", state.mouse_captured());
+                    }
+                    VirtualKeyCode::Escape => *control_flow = ControlFlow::Exit,
+
+                    VirtualKeyCode::V => {
+                        let rate = match state.shading_rate() {
+                            ShadingRate::Full => ShadingRate::Coarse2x2,
+                            ShadingRate::Coarse2x2 => ShadingRate::Full,
+                        };
+
+                        match state.set_shading_rate(rate) {
+                            Ok(_) => log::info!("Shading rate: {:?}", rate),
+                            Err(e) => log::warn!("{}", e),
+                        }
+                    }
+
+                    VirtualKeyCode::Return => {
+                        if let Err(e) = state.spawn_particles(PARTICLE_BURST) {
+                            log::warn!("Couldn't spawn particles: {}", e);
+                        }
+                    }
+
+                    VirtualKeyCode::Space => {
+                        state.toggle_paused();
+                        log::info!("Paused: {}", state.paused());
+                    }
+
+                    VirtualKeyCode::Tab => {
+                        state.select_next();
+                        log::info!("Selected instance: {:?}", state.selected());
+                    }
+
+                    VirtualKeyCode::X => state.add_camera_shake(0.15, Duration::from_millis(500)),
+
+                    VirtualKeyCode::I => state.toggle_impostors(),
+
+                    VirtualKeyCode::O => {
+                        let sort_mode = match state.sort_mode() {
+                            InstanceSort::None => InstanceSort::FrontToBack,
+                            InstanceSort::FrontToBack => InstanceSort::BackToFront,
+                            InstanceSort::BackToFront => InstanceSort::None,
+                        };
+
+                        state.set_sort_mode(sort_mode);
+                        log::info!("Instance sort: {:?}", sort_mode);
+                    }
+
+                    VirtualKeyCode::M => state.toggle_morph(),
+
+                    VirtualKeyCode::F => state.toggle_depth_of_field(),
+
+                    VirtualKeyCode::G => {
+                        let dof = state.depth_of_field();
+                        dof.set_autofocus(!dof.autofocus());
+                        log::info!("Autofocus: {}", dof.autofocus());
+                    }
+
+                    VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                        let step = if *keycode == VirtualKeyCode::LBracket {
+                            -0.5
+                        } else {
+                            0.5
+                        };
+
+                        let dof = state.depth_of_field();
+                        dof.set_focus_distance(dof.focus_distance() + step);
+                        log::info!("Focus distance: {}", dof.focus_distance());
+                    }
+
+                    VirtualKeyCode::Semicolon | VirtualKeyCode::Apostrophe => {
+                        let step = if *keycode == VirtualKeyCode::Semicolon {
+                            -0.1
+                        } else {
+                            0.1
+                        };
+
+                        let dof = state.depth_of_field();
+                        dof.set_aperture(dof.aperture() + step);
+                        log::info!("Aperture: {}", dof.aperture());
+                    }
+
+                    VirtualKeyCode::K => state.toggle_ssao(),
+
+                    VirtualKeyCode::B => {
+                        let wind = state.wind();
+                        wind.enabled = !wind.enabled;
+                    }
+
+                    VirtualKeyCode::Comma | VirtualKeyCode::Period => {
+                        let step = if *keycode == VirtualKeyCode::Comma {
+                            -15.0
+                        } else {
+                            15.0
+                        };
+
+                        let wind = state.wind();
+                        wind.set_angle(wind.angle() + Deg(step));
+                        log::info!("Wind direction: {:?}", wind.angle());
+                    }
+
+                    VirtualKeyCode::Key7 | VirtualKeyCode::Key8 => {
+                        let step = if *keycode == VirtualKeyCode::Key7 {
+                            -0.05
+                        } else {
+                            0.05
+                        };
+
+                        let wind = state.wind();
+                        wind.set_strength(wind.strength() + step);
+                        log::info!("Wind strength: {}", wind.strength());
+                    }
+
+                    VirtualKeyCode::Key9 | VirtualKeyCode::Key0 => {
+                        let step = if *keycode == VirtualKeyCode::Key9 {
+                            -0.25
+                        } else {
+                            0.25
+                        };
+
+                        let wind = state.wind();
+                        wind.set_frequency(wind.frequency() + step);
+                        log::info!("Wind frequency: {}", wind.frequency());
+                    }
+
+                    VirtualKeyCode::L => state.toggle_labels(),
+
+                    VirtualKeyCode::C => state.toggle_calibration(),
+
+                    VirtualKeyCode::H => state.toggle_fog(),
+                    VirtualKeyCode::End => state.toggle_depth_view(),
+                    VirtualKeyCode::Grave => state.toggle_grid(),
+                    VirtualKeyCode::Insert => state.frame_scene(),
+                    VirtualKeyCode::Numpad5 => state.reset_camera(),
+                    VirtualKeyCode::Numpad4 => {
+                        state.set_fxaa(!state.fxaa());
+                        log::info!("FXAA: {}", state.fxaa());
+                    }
+                    VirtualKeyCode::Numpad8 => {
+                        state.set_background_mode(state.background_mode().next());
+                        log::info!("Background mode: {:?}", state.background_mode());
+                    }
+                    VirtualKeyCode::Numpad6 => {
+                        state.set_clear_enabled(!state.clear_enabled());
+                        log::info!("Clear each frame: {}", state.clear_enabled());
+                    }
+                    VirtualKeyCode::Numpad3 => {
+                        let info = state.adapter_info();
+                        log::info!(
+                            "Adapter: {} ({:?}, {:?}), vendor {:#x}, device {:#x}",
+                            info.name,
+                            info.backend,
+                            info.device_type,
+                            info.vendor,
+                            info.device
+                        );
+                        log::info!(
+                            "Surface: {:?}, {:?}",
+                            state.surface_format(),
+                            state.present_mode()
+                        );
+                    }
+                    VirtualKeyCode::Numpad2 => {
+                        state.set_double_sided(!state.double_sided());
+                        log::info!("Double sided: {}", state.double_sided());
+                    }
+                    VirtualKeyCode::Numpad1 => {
+                        state.toggle_auto_orbit();
+                        log::info!("Auto orbit: {}", state.auto_orbiting());
+                    }
+
+                    VirtualKeyCode::Numpad7 => match state.save_camera(Path::new(CAMERA_FILE)) {
+                        Ok(()) => log::info!("Saved the camera to {}", CAMERA_FILE),
+                        Err(e) => log::warn!("Couldn't save the camera: {:#}", e),
+                    },
+
+                    VirtualKeyCode::Numpad9 => match state.load_camera(Path::new(CAMERA_FILE)) {
+                        Ok(()) => log::info!("Loaded the camera from {}", CAMERA_FILE),
+                        Err(e) => log::warn!("Couldn't load the camera: {:#}", e),
+                    },
+                    VirtualKeyCode::NumpadMultiply => state.toggle_shadows(),
+
+                    VirtualKeyCode::E => {
+                        let mode = match state.camera_mode() {
+                            CameraMode::Fly => CameraMode::Orbit,
+                            CameraMode::Orbit => CameraMode::Fly,
+                        };
+
+                        state.set_camera_mode(mode);
+                        log::info!("Camera mode: {:?}", mode);
+                    }
+
+                    VirtualKeyCode::N => {
+                        let mode = match state.present_mode() {
+                            PresentMode::Fifo => PresentMode::Mailbox,
+                            PresentMode::Mailbox => PresentMode::Immediate,
+                            _ => PresentMode::Fifo,
+                        };
+
+                        if let Err(e) = state.set_present_mode(mode) {
+                            log::warn!("{}", e);
+                        }
+                        log::info!("Present mode: {:?}", state.present_mode());
+                    }
+
+                    VirtualKeyCode::Q => match state.toggle_camera_path() {
+                        Ok(_) => {
+                            log::info!("Following camera path: {}", state.following_camera_path())
+                        }
+                        Err(e) => log::warn!("{}", e),
+                    },
+
+                    VirtualKeyCode::F1 | VirtualKeyCode::F2 => {
+                        let factor = if *keycode == VirtualKeyCode::F1 {
+                            0.8
+                        } else {
+                            1.25
+                        };
+
+                        let fog = state.fog();
+                        fog.set_density(fog.density() * factor);
+                        log::info!("Fog density: {}", fog.density());
+                    }
+
+                    VirtualKeyCode::F3 | VirtualKeyCode::F4 => {
+                        let step = if *keycode == VirtualKeyCode::F3 {
+                            -0.5
+                        } else {
+                            0.5
+                        };
+
+                        let fog = state.fog();
+                        fog.set_height(fog.height() + step);
+                        log::info!("Fog height: {}", fog.height());
                     }
+
+                    VirtualKeyCode::F5 | VirtualKeyCode::F6 => {
+                        let step = if *keycode == VirtualKeyCode::F5 {
+                            -0.1
+                        } else {
+                            0.1
+                        };
+
+                        let fog = state.fog();
+                        fog.set_falloff(fog.falloff() + step);
+                        log::info!("Fog falloff: {}", fog.falloff());
+                    }
+
+                    VirtualKeyCode::F7 => {
+                        let fog = state.fog();
+                        let next = FOG_COLORS
+                            .iter()
+                            .position(|&color| color == fog.color())
+                            .map_or(0, |i| (i + 1) % FOG_COLORS.len());
+                        fog.set_color(FOG_COLORS[next]);
+                        log::info!("Fog color: {:?}", fog.color());
+                    }
+
+                    VirtualKeyCode::F8 => {
+                        let next = CLEAR_COLORS
+                            .iter()
+                            .position(|&color| color == state.clear_color())
+                            .map_or(0, |i| (i + 1) % CLEAR_COLORS.len());
+                        state.set_clear_color(CLEAR_COLORS[next]);
+                        log::info!("Clear color: {:?}", state.clear_color());
+                    }
+
+                    // Keeps the address mode, which has a key of its own
+                    VirtualKeyCode::Backslash => {
+                        let current = state.texture_filter();
+                        let next = TEXTURE_FILTERS
+                            .iter()
+                            .position(|&filter| {
+                                SamplerConfig {
+                                    address_mode: current.address_mode,
+                                    ..filter
+                                } == current
+                            })
+                            .map_or(0, |i| (i + 1) % TEXTURE_FILTERS.len());
+                        let filter = SamplerConfig {
+                            address_mode: current.address_mode,
+                            ..TEXTURE_FILTERS[next]
+                        };
+                        match state.set_texture_filter(filter) {
+                            Ok(()) => log::info!("Texture filter: {:?}", state.texture_filter()),
+                            Err(e) => log::warn!("Couldn't change the texture filter: {}", e),
+                        }
+                    }
+
+                    VirtualKeyCode::NumpadDivide => {
+                        let next = ADDRESS_MODES
+                            .iter()
+                            .position(|&mode| mode == state.texture_address_mode())
+                            .map_or(0, |i| (i + 1) % ADDRESS_MODES.len());
+                        match state.set_texture_address_mode(ADDRESS_MODES[next]) {
+                            Ok(()) => log::info!("Texture address mode: {:?}", ADDRESS_MODES[next]),
+                            Err(e) => log::warn!("Couldn't change the texture address mode: {}", e),
+                        }
+                    }
+
+                    VirtualKeyCode::Numpad0 => {
+                        let next = CULL_MODES
+                            .iter()
+                            .position(|&mode| mode == state.cull_mode())
+                            .map_or(0, |i| (i + 1) % CULL_MODES.len());
+                        state.set_cull_mode(CULL_MODES[next]);
+                        log::info!("Cull mode: {:?}", CULL_MODES[next]);
+                    }
+
+                    VirtualKeyCode::NumpadEnter => {
+                        let next = TOPOLOGIES
+                            .iter()
+                            .position(|&topology| topology == state.topology())
+                            .map_or(0, |i| (i + 1) % TOPOLOGIES.len());
+                        state.set_topology(TOPOLOGIES[next]);
+                        log::info!("Pentagon topology: {:?}", TOPOLOGIES[next]);
+                    }
+
+                    VirtualKeyCode::NumpadDecimal => {
+                        let next = TEXTURE_TILINGS
+                            .iter()
+                            .position(|&tiling| tiling == state.texture_tiling())
+                            .map_or(0, |i| (i + 1) % TEXTURE_TILINGS.len());
+                        state.set_texture_tiling(TEXTURE_TILINGS[next]);
+                        log::info!("Texture tiling: {}x", TEXTURE_TILINGS[next]);
+                    }
+
+                    // Starts at red, then steps around the wheel
+                    VirtualKeyCode::Home => {
+                        let hue = state.light_hue().map_or(0.0, |hue| hue + LIGHT_HUE_STEP);
+                        state.set_light_hue(Some(hue));
+                        log::info!("Light hue: {}°", state.light_hue().unwrap_or_default());
+                    }
+
+                    // Each new light goes a golden angle further around the circle, colored by where it lands
+                    VirtualKeyCode::Slash => {
+                        let angle = 137.5 * state.num_lights() as f32;
+                        let (sin, cos) = angle.to_radians().sin_cos();
+                        let position = [
+                            EXTRA_LIGHT_RADIUS * cos,
+                            EXTRA_LIGHT_HEIGHT,
+                            EXTRA_LIGHT_RADIUS * sin,
+                        ];
+
+                        match state.add_light(position, hsv_to_rgb(angle, 1.0, 1.0)) {
+                            Ok(()) => log::info!("Lights: {}", state.num_lights()),
+                            Err(e) => log::warn!("{}", e),
+                        }
+                    }
+
+                    VirtualKeyCode::Delete => {
+                        state.clear_lights();
+                        log::info!("Lights cleared");
+                    }
+
+                    // Starts and stops the instances spinning
+                    VirtualKeyCode::F9 => {
+                        let speed = if state.rotation_speed() == Deg(0.0) {
+                            Deg(45.0)
+                        } else {
+                            Deg(0.0)
+                        };
+                        state.set_rotation_speed(speed);
+                        log::info!("Rotation speed: {:?} per second", state.rotation_speed());
+                    }
+
+                    VirtualKeyCode::F10 => state.toggle_tone_mapping(),
+
+                    // Cycles the tone mapping exposure through a few stops
+                    VirtualKeyCode::F11 => {
+                        let next = EXPOSURES
+                            .iter()
+                            .position(|&exposure| exposure == state.exposure())
+                            .map_or(0, |i| (i + 1) % EXPOSURES.len());
+                        state.set_exposure(EXPOSURES[next]);
+                        log::info!("Exposure: {}", state.exposure());
+                    }
+
+                    VirtualKeyCode::PageUp => state.set_subdivision(state.subdivision() + 1),
+
+                    VirtualKeyCode::PageDown => {
+                        state.set_subdivision(state.subdivision().saturating_sub(1))
+                    }
+
+                    VirtualKeyCode::Y => {
+                        if let Err(e) = state.capture_turntable(36, Path::new("turntable")) {
+                            log::error!("Turntable capture failed: {:#}", e);
+                        }
+                    }
+
+                    VirtualKeyCode::Z => match state.toggle_wireframe() {
+                        Ok(()) => log::info!("Wireframe: {}", state.wireframe()),
+                        Err(e) => log::warn!("{}", e),
+                    },
+
+                    // Switches 4x MSAA on and off
+                    VirtualKeyCode::U => {
+                        let count = if state.sample_count() == 1 { 4 } else { 1 };
+
+                        state.set_sample_count(count);
+                    }
+
+                    VirtualKeyCode::Scroll => {
+                        let next = RENDER_SCALES
+                            .iter()
+                            .position(|&scale| scale == state.render_scale())
+                            .map_or(0, |i| (i + 1) % RENDER_SCALES.len());
+                        log::info!(
+                            "Render scale: {}",
+                            state.set_render_scale(RENDER_SCALES[next])
+                        );
+                    }
+
+                    VirtualKeyCode::F12 => {
+                        // Seconds since the epoch keep each screenshot from overwriting the last
+                        let time = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs();
+                        let path = PathBuf::from(format!("screenshot_{}.png", time));
+
+                        match state.capture_frame(&path) {
+                            Ok(()) => log::info!("Saved {}", path.display()),
+                            Err(e) => log::error!("Screenshot failed: {:#}", e),
+                        }
+                    }
+
+                    // Grows or shrinks the grid of cubes by a row and a column
+                    VirtualKeyCode::NumpadAdd | VirtualKeyCode::NumpadSubtract => {
+                        let per_row = if *keycode == VirtualKeyCode::NumpadSubtract {
+                            state.instances_per_row().saturating_sub(1)
+                        } else {
+                            state.instances_per_row() + 1
+                        };
+
+                        state.set_instances_per_row(per_row);
+                        let per_row = state.instances_per_row();
+                        log::info!(
+                            "Instances: {} ({}x{} grid)",
+                            per_row * per_row,
+                            per_row,
+                            per_row
+                        );
+                    }
+
+                    VirtualKeyCode::Minus | VirtualKeyCode::Equals => {
+                        let step = if *keycode == VirtualKeyCode::Minus {
+                            -0.1
+                        } else {
+                            0.1
+                        };
+
+                        state.set_calibration_gamma(state.calibration_gamma() + step);
+                        log::info!("Calibration gamma: {}", state.calibration_gamma());
+                    }
+
+                    VirtualKeyCode::P => {
+                        if let Err(e) = state.toggle_gpu_timing() {
+                            log::warn!("{}", e);
+                        }
+                    }
+
+                    VirtualKeyCode::T => {
+                        // Compare against the time printed when toggling back
+                        log::info!("Last frame encoded in {:?}", state.encode_time());
+                        state.toggle_threaded_encoding();
+                        log::info!("Threaded encoding: {}", state.threaded_encoding());
+                    }
+
+                    VirtualKeyCode::J => {
+                        let ssao = state.ssao();
+                        ssao.debug_view = !ssao.debug_view;
+                    }
+
+                    VirtualKeyCode::Key1 | VirtualKeyCode::Key2 => {
+                        let ssao = state.ssao();
+                        if *keycode == VirtualKeyCode::Key1 {
+                            ssao.set_sample_count(ssao.sample_count().saturating_sub(4));
+                        } else {
+                            ssao.set_sample_count(ssao.sample_count() + 4);
+                        }
+                        log::info!("SSAO samples: {}", ssao.sample_count());
+                    }
+
+                    VirtualKeyCode::Key3 | VirtualKeyCode::Key4 => {
+                        let step = if *keycode == VirtualKeyCode::Key3 {
+                            -0.1
+                        } else {
+                            0.1
+                        };
+
+                        let ssao = state.ssao();
+                        ssao.set_radius(ssao.radius() + step);
+                        log::info!("SSAO radius: {}", ssao.radius());
+                    }
+
+                    VirtualKeyCode::Key5 | VirtualKeyCode::Key6 => {
+                        let step = if *keycode == VirtualKeyCode::Key5 {
+                            -0.25
+                        } else {
+                            0.25
+                        };
+
+                        let ssao = state.ssao();
+                        ssao.set_intensity(ssao.intensity() + step);
+                        log::info!("SSAO intensity: {}", ssao.intensity());
+                    }
+
                     _ => {}
+                },
+
+                WindowEvent::CursorMoved { position, .. } => state.set_cursor_position(*position),
+
+                WindowEvent::ModifiersChanged(new_modifiers) => modifiers = *new_modifiers,
+
+                // Alt+click selects the instance under the cursor, or clears the selection over empty space
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } if modifiers.alt() => {
+                    state.select(state.pick_under_cursor());
+                    log::info!("Selected instance: {:?}", state.selected());
+                }
+
+                // Clicking an instance deletes it, clicking the ground spawns a new one there
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } => match state.pick_under_cursor() {
+                    Some(index) => {
+                        state.remove_instance(index);
+                        log::info!("Removed instance {}", index);
+                    }
+                    None => {
+                        if let Some(position) = state.pick_ground() {
+                            state.spawn_instance(position);
+                            log::info!("Spawned instance at {:?}", position);
+                        }
+                    }
+                },
+
+                // Grabs the mouse for looking around. Escape lets go of it.
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Middle,
+                    ..
+                } if !state.mouse_captured() => {
+                    if let Err(e) = state.set_mouse_captured(true) {
+                        log::warn!("Couldn't grab the mouse: {}", e);
+                    }
+                    log::info!("Mouse captured: {}", state.mouse_captured());
+                }
+
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(*physical_size);
                 }
+
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    // new_inner_size is &&mut so we have to dereference it twice
+                    state.resize(**new_inner_size);
+                }
+                _ => {}
             }
         }
 
-        Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-            state.update();
+        Event::RedrawRequested(window_id) if Some(window_id) == state.window().map(Window::id) => {
+            let now = Instant::now();
+            let dt = now - last_render_time;
+            last_render_time = now;
+
+            state.update(dt);
 
             match state.render() {
                 Ok(_) => {}
-                // Try to reconstruct the surface if lost
-                Err(SurfaceError::Lost) => state.resize(state.size),
+                // The surface needs configuring again, e.g. after a resize or the GPU resetting
+                Err(e @ (SurfaceError::Lost | SurfaceError::Outdated)) => {
+                    log::warn!("{}, configuring it again", e);
+                    state.reconfigure_surface();
+                }
                 // The system ran out of memory. We should abandon ship :(
-                Err(SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                // All other errors (Outdated, Timeout) should be resolved by the next frame
-                Err(e) => eprintln!("{:?}", e),
+                Err(SurfaceError::OutOfMemory) => {
+                    log::error!("Out of memory, exiting");
+                    *control_flow = ControlFlow::Exit;
+                }
+                // The frame took too long to come back. Skip it and try again next frame.
+                Err(SurfaceError::Timeout) => log::warn!("Surface timed out, skipping a frame"),
+            }
+
+            if let Some((fps, ms)) = frame_timer.tick() {
+                state.set_frame_rate(fps, ms);
             }
         }
 
+        Event::DeviceEvent {
+            event: DeviceEvent::MouseMotion { delta },
+            ..
+        } => state.process_mouse_motion(delta.0, delta.1),
+
+        // Every way out, closing the window, Escape or running out of memory, ends up here
+        Event::LoopDestroyed => state.on_exit(),
+
         Event::MainEventsCleared => {
             // RedrawRequested will only trigger once unless we manually request it.
-            state.window().request_redraw();
+            if let Some(window) = state.window() {
+                window.request_redraw();
+            }
         }
 
         _ => {}